@@ -122,6 +122,7 @@ impl<'a> System<'a> for LaunchAttacks {
 }
 
 /// The player pressed the key for picking up an item.
+/// Stairs are taken, and Locks opened, with the same key.
 pub struct PickupItems {
     pickup_presses: Arc<Mutex<usize>>,
 }
@@ -151,10 +152,15 @@ impl<'a> System<'a> for PickupItems {
         Read<'a, crate::net::ServerToLocalIds>,
         Read<'a, Player>,
         ReadStorage<'a, Item>,
+        ReadStorage<'a, comn::Stairs>,
+        ReadStorage<'a, comn::Lock>,
         ReadStorage<'a, Pos>,
     );
 
-    fn run(&mut self, (ents, sc, server_to_local_ids, player, items, poses): Self::SystemData) {
+    fn run(
+        &mut self,
+        (ents, sc, server_to_local_ids, player, items, stairs, locks, poses): Self::SystemData,
+    ) {
         use comn::item::{PickupRequest, MAX_INTERACTION_DISTANCE_SQUARED};
         use comn::{na::Translation2, vec_of_pos};
 
@@ -174,8 +180,8 @@ impl<'a> System<'a> for PickupItems {
             for _pickup_press in 0..*pickup_presses {
                 trace!("pickup press event!");
 
-                // grab the entity that's closest to the player
-                if let Some(&id) = (&*ents, &poses, &items)
+                // grab the item (or stairs, or lock) that's closest to the player
+                if let Some(&id) = (&*ents, &poses, items.mask() | stairs.mask() | locks.mask())
                     .join()
                     // returns (the entity of that item, that item's distance from the player^2)
                     .filter_map(|(item_entity, vec_of_pos!(item_loc), _)| {
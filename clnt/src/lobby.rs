@@ -0,0 +1,54 @@
+use crate::net::ServerConnection;
+use crate::prelude::*;
+use comn::net::LobbyCommand;
+use std::sync::{Arc, Mutex};
+
+/// Sends the lobby commands the player types into the box on the page,
+/// i.e. `/create crypt-run dungeon`, to the server.
+pub struct SendLobbyCommands {
+    typed: Arc<Mutex<Vec<String>>>,
+}
+impl Default for SendLobbyCommands {
+    fn default() -> Self {
+        let typed = Arc::new(Mutex::new(Vec::new()));
+
+        {
+            let typed = typed.clone();
+            let send = move |line: String| {
+                typed
+                    .lock()
+                    .expect("couldn't lock typed lobby commands")
+                    .push(line);
+            };
+            js! {
+                let send = @{send};
+                $("#lobby").on("keydown keyup keypress", function(e) {
+                    // the controls shouldn't hear what's typed here.
+                    e.stopPropagation();
+                    if (e.type === "keydown" && e.key === "Enter") {
+                        send(this.value);
+                        this.value = "";
+                    }
+                });
+            }
+        }
+
+        Self { typed }
+    }
+}
+impl<'a> System<'a> for SendLobbyCommands {
+    type SystemData = Read<'a, ServerConnection>;
+
+    fn run(&mut self, sc: Self::SystemData) {
+        if let Ok(mut typed) = self.typed.try_lock() {
+            for line in typed.drain(..) {
+                match LobbyCommand::parse(&line) {
+                    Ok(command) => sc.lobby(command),
+                    Err(e) => {
+                        js!(show_notice(@{e}));
+                    }
+                }
+            }
+        }
+    }
+}
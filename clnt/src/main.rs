@@ -14,6 +14,7 @@ use prelude::*;
 
 mod controls;
 mod item;
+mod lobby;
 mod net;
 mod renderer;
 
@@ -44,6 +45,7 @@ fn main() {
         .with(comn::art::UpdateAnimations,          "animate",      &[])
         // util
         .with(net::HandleServerPackets::default(),  "packets",      &[])
+        .with(lobby::SendLobbyCommands::default(),  "lobby",        &[])
         .with(comn::dead::ClearDead,                "clear dead",   &[])
         // items
         .with(item::DepositionItems,                "deposition",   &[])
@@ -36,6 +36,11 @@ impl ServerConnection {
     self.send(NetMessage::NewEnt(ent.id()));
     }*/
 
+    #[inline]
+    pub fn lobby(&self, command: comn::net::LobbyCommand) {
+        self.send(NetMessage::Lobby(command));
+    }
+
     #[inline]
     pub fn insert_comp<C: Into<NetComponent>>(
         &self,
@@ -149,11 +154,14 @@ pub struct ServerToLocalIds(pub BiMap<u32, u32>);
 
 #[derive(Default)]
 pub struct HandleServerPackets {
-    connection_established: bool,
     /// This system caches this value until it recieves it from the server,
     /// then it can know the local id (not the server id) of the Player,
     /// so it can then write to the Resource.
     local_player_server_id: Option<u32>,
+    /// The class the player picked, so that they can keep playing as it on the next floor.
+    class: Option<String>,
+    /// Filled in by the class picker on the page once the player has clicked one.
+    picked: Arc<Mutex<Option<String>>>,
 }
 impl<'a> System<'a> for HandleServerPackets {
     type SystemData = (
@@ -164,18 +172,15 @@ impl<'a> System<'a> for HandleServerPackets {
     );
 
     fn run(&mut self, (ents, mut server_to_local_ids, lu, sc): Self::SystemData) {
+        if let Some(class) = self.picked.try_lock().ok().and_then(|mut p| p.take()) {
+            sc.insert_comp(comn::net::SpawnPlayer {
+                class: class.clone(),
+            });
+            self.class = Some(class);
+        }
+
         if let Ok(mut msgs) = sc.message_queue.try_lock() {
             for msg in msgs.drain(0..) {
-                // you know the connection is established when
-                // we first get a message.
-                if !self.connection_established {
-                    // immediately request to be put in the game
-                    // (later on we might want to have this happen
-                    //  after i.e. a menu is clicked through)
-                    sc.insert_comp(comn::net::SpawnPlayer);
-                    self.connection_established = true;
-                }
-
                 use NetMessage::*;
 
                 match msg {
@@ -221,11 +226,35 @@ impl<'a> System<'a> for HandleServerPackets {
                         }
                     }
 
+                    RemoveComp(id, net_comp) => {
+                        // if it's died in the meantime, there's nothing left to remove.
+                        let ent = server_to_local_ids
+                            .0
+                            .get_by_left(&id)
+                            .map(|ent| ents.entity(*ent))
+                            .filter(|ent| ents.is_alive(*ent));
+                        if let Some(ent) = ent {
+                            net_comp.remove(ent, &lu);
+                        }
+                    }
+
                     Establishment {
                         local_player,
                         appearance_record,
+                        level_seed,
+                        classes,
                     } => {
-                        info!("establishment");
+                        info!("establishment, level seed {}", level_seed);
+
+                        // if we already had a world, we've been moved to another level,
+                        // so none of the entities we were told about are there anymore.
+                        for (_, &local) in server_to_local_ids.0.iter() {
+                            if let Err(e) = ents.delete(ents.entity(local)) {
+                                error!("couldn't forget entity from the last level: {}", e);
+                            }
+                        }
+                        server_to_local_ids.0.clear();
+                        lu.exec(|world| world.write_resource::<Player>().0 = None);
 
                         // start loading the assets we'll need
                         js!(load_assets(@{appearance_record.names.clone()}));
@@ -237,6 +266,49 @@ impl<'a> System<'a> for HandleServerPackets {
 
                         // store our server ID until the server tells us about it.
                         self.local_player_server_id = Some(local_player);
+
+                        // now that we're in, request to be put in the game,
+                        // once the player has picked which class to play as.
+                        if classes.is_empty() {
+                            sc.insert_comp(comn::net::SpawnPlayer {
+                                class: String::new(),
+                            });
+                        } else if let Some(class) = &self.class {
+                            // we've come down the stairs, so we already picked.
+                            sc.insert_comp(comn::net::SpawnPlayer {
+                                class: class.clone(),
+                            });
+                        } else {
+                            let choices: Vec<Vec<String>> = classes
+                                .into_iter()
+                                .map(|c| vec![c.id, c.name, c.description])
+                                .collect();
+                            let picked = self.picked.clone();
+                            let pick = move |id: String| {
+                                *picked.lock().expect("couldn't lock picked class") = Some(id);
+                            };
+                            js!(pick_class(@{choices}, @{pick}));
+                        }
+                    }
+
+                    Notice(text) => {
+                        info!("notice from server: {}", text);
+                        js!(show_notice(@{text}));
+                    }
+
+                    Kicked(reason) => {
+                        error!("kicked from server: {}", reason);
+                        js!(show_notice(@{format!("Disconnected: {}", reason)}));
+                    }
+
+                    Lobby(_) => error!("the server sent a lobby command!"),
+
+                    Queued(position) => {
+                        info!("waiting to join, #{} in line", position);
+                        js!(show_notice(@{format!(
+                            "The server is full. You're #{} in line to join.",
+                            position
+                        )}));
                     }
                 }
             }
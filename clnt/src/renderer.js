@@ -115,14 +115,19 @@ function clear_appearance(ent) {
 
 const cam = new THREE.Vector3(0.0, 0.0, 0.0);
 function render(ents, player) {
-	ents.forEach(({ent, rot, iso}) => {
+	ents.forEach(({ent, rot, iso, elevation, orientation}) => {
 		let mesh = meshes[ent];
 
 		if (mesh != undefined) {
-			//mesh.quaternion.fromArray(iso.rotation);
 			let t = iso.translation;
-			mesh.position.fromArray([t[0], 0, -t[1]]);
-			mesh.rotation.y = rot;
+			mesh.position.fromArray([t[0], elevation, -t[1]]);
+			if (orientation) {
+				// the server's Z is up, but ours is Y.
+				let [x, y, z, w] = orientation;
+				mesh.quaternion.set(x, z, -y, w);
+			} else {
+				mesh.rotation.y = rot;
+			}
 		} else {
 			console.log("We can't position a mesh because the mesh doesn't exist");
 		}
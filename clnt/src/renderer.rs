@@ -1,5 +1,6 @@
 use crate::prelude::*;
 use comn::art::Appearance;
+use comn::{Elevation, Orientation};
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize)]
@@ -7,6 +8,9 @@ struct RenderEntry {
     ent: u32,
     iso: Iso2,
     rot: f32,
+    elevation: f32,
+    /// If this is present, it's used instead of `rot`.
+    orientation: Option<Vec<f32>>,
 }
 stdweb::js_serializable!(RenderEntry);
 
@@ -34,9 +38,14 @@ impl<'a> System<'a> for Render {
         Read<'a, Player>,
         ReadStorage<'a, Appearance>,
         ReadStorage<'a, Pos>,
+        ReadStorage<'a, Elevation>,
+        ReadStorage<'a, Orientation>,
     );
 
-    fn run(&mut self, (ents, player, appearances, poses): Self::SystemData) {
+    fn run(
+        &mut self,
+        (ents, player, appearances, poses, elevations, orientations): Self::SystemData,
+    ) {
         let events = appearances.channel().read(self.reader_id.as_mut().unwrap());
 
         for event in events {
@@ -55,12 +64,14 @@ impl<'a> System<'a> for Render {
             }
         }
 
-        let render_entries = (&*ents, &poses)
+        let render_entries = (&*ents, &poses, elevations.maybe(), orientations.maybe())
             .join()
-            .map(|(ent, Pos { iso })| RenderEntry {
+            .map(|(ent, Pos { iso }, elevation, orientation)| RenderEntry {
                 ent: ent.id(),
                 iso: iso.clone(),
                 rot: iso.rotation.angle(),
+                elevation: elevation.map(|e| e.height).unwrap_or(0.0),
+                orientation: orientation.map(|o| o.quaternion.clone()),
             })
             .collect::<Vec<_>>();
 
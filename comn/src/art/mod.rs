@@ -2,15 +2,16 @@ use serde::{Deserialize, Serialize};
 use specs::{prelude::*, Component};
 use std::fmt::Debug;
 // scripting
+use crate::Scriptable;
 #[cfg(feature = "python")]
-use pyo3::{prelude::*, types::PyAny, PyRawObject};
+use crate::PyWrapper;
+#[cfg(feature = "python")]
+use pyo3::{prelude::*, PyRawObject};
 
 pub mod player_anim;
 pub use player_anim::PlayerAnimationController;
 
-#[cfg(feature = "python")]
-#[pyclass]
-#[derive(PartialEq, Eq, Hash, Clone, Debug, Serialize, Deserialize)]
+#[derive(PartialEq, Eq, Hash, Clone, Debug, Scriptable, Serialize, Deserialize)]
 /// Something's index into the AppearanceRecord; which out of any of those possible
 /// appearances they have.
 ///
@@ -18,31 +19,6 @@ pub use player_anim::PlayerAnimationController;
 /// the appearance should never affect the behavior.
 /// Therefore, this component isn't really used on the server all that much
 /// except for when it needs to be sent down to the clients.
-pub struct Appearance {
-    #[pyo3(get, set)]
-    pub index: usize,
-}
-#[cfg(feature = "python")]
-#[pymethods]
-impl Appearance {
-    #[new]
-    fn new(obj: &PyRawObject, index: u64) {
-        obj.init(Appearance {
-            index: index as usize,
-        });
-    }
-}
-#[cfg(feature = "python")]
-impl<'source> FromPyObject<'source> for Appearance {
-    fn extract(ob: &'source PyAny) -> PyResult<Self> {
-        Ok(Appearance {
-            index: ob.extract::<u64>()? as usize,
-        })
-    }
-}
-
-#[cfg(not(feature = "python"))]
-#[derive(PartialEq, Eq, Hash, Clone, Debug, Serialize, Deserialize)]
 pub struct Appearance {
     pub index: usize,
 }
@@ -74,11 +50,11 @@ impl AppearanceRecord {
     }
 
     /// Creates an Appearance component with the given name.
-    /// Panics if such an appearance can't be found.
-    pub fn appearance_of(&self, appearance: &str) -> PyResult<Appearance> {
-        Ok(self
-            .try_appearance_of(appearance)
-            .unwrap_or_else(|e| panic!(e)))
+    /// Raises a ValueError if such an appearance can't be found.
+    pub fn appearance_of(&self, appearance: &str) -> PyResult<PyAppearance> {
+        self.try_appearance_of(appearance)
+            .map(PyAppearance::from_inner)
+            .map_err(pyo3::exceptions::ValueError::py_err)
     }
 }
 #[cfg(feature = "python")]
@@ -57,5 +57,36 @@ fn test_player_anim_indexing() {
 
 use serde::{Deserialize, Serialize};
 use specs::{prelude::*, Component};
-#[derive(Clone, Debug, Component, Serialize, Deserialize)]
-pub struct PlayerAnimationController;
+use strum_macros::{Display, EnumString};
+
+#[derive(Copy, Clone, Debug, PartialEq, Display, EnumString, Serialize, Deserialize)]
+/// Which set of animations a player attacks with,
+/// i.e. a mage casts while a ranger shoots.
+pub enum AttackAnimation {
+    Cast,
+    Jab,
+    Swing,
+    Shoot,
+}
+impl Default for AttackAnimation {
+    fn default() -> Self {
+        AttackAnimation::Swing
+    }
+}
+impl AttackAnimation {
+    /// The animation for attacking in that direction.
+    pub fn facing(self, direction: Direction) -> PlayerAnimation {
+        match self {
+            AttackAnimation::Cast => PlayerAnimation::Cast(direction),
+            AttackAnimation::Jab => PlayerAnimation::Jab(direction),
+            AttackAnimation::Swing => PlayerAnimation::Swing(direction),
+            AttackAnimation::Shoot => PlayerAnimation::Shoot(direction),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Component, Serialize, Deserialize)]
+pub struct PlayerAnimationController {
+    /// Set by the character class the player picked.
+    pub attack: AttackAnimation,
+}
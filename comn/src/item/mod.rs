@@ -1,47 +1,10 @@
 use crate::prelude::*;
-#[cfg(feature = "python")]
-use pyo3::{prelude::*, types::PyAny};
+use crate::Scriptable;
 use serde::{Deserialize, Serialize};
 use specs::{prelude::*, Component};
 use std::collections::HashMap;
-#[cfg(feature = "python")]
-use strum::IntoEnumIterator;
 use strum_macros::{Display, EnumIter, EnumString};
 
-#[cfg(feature = "python")]
-#[pyclass(name=Item)]
-#[derive(Debug, Clone, Component)]
-pub struct PyItem {
-    #[pyo3(get, set)]
-    inner: Item,
-}
-#[cfg(feature = "python")]
-#[pymethods]
-impl PyItem {
-    #[new]
-    fn new(obj: &PyRawObject, inner: Item) {
-        obj.init(Self { inner })
-    }
-}
-#[cfg(feature = "python")]
-impl<'source> FromPyObject<'source> for PyItem {
-    fn extract(ob: &'source PyAny) -> PyResult<Self> {
-        unsafe {
-            let py = Python::assume_gil_acquired();
-            let obj: PyObject = ob.to_object(py);
-            Ok(Self {
-                inner: obj.getattr(py, "inner")?.extract(py)?,
-            })
-        }
-    }
-}
-#[cfg(feature = "python")]
-impl crate::PyWrapper<Item> for PyItem {
-    fn into_inner(self) -> Item {
-        self.inner
-    }
-}
-
 /// Convenience constant for easily grabbing the weapon.
 pub const WEAPON_SLOT: SlotIndex = SlotIndex::Reserved(Item::Weapon);
 
@@ -55,6 +18,7 @@ pub const WEAPON_SLOT: SlotIndex = SlotIndex::Reserved(Item::Weapon);
     EnumString,
     EnumIter,
     Component,
+    Scriptable,
     Serialize,
     Deserialize,
 )]
@@ -69,33 +33,6 @@ pub enum Item {
     /// type of Item.
     Misc,
 }
-#[cfg(feature = "python")]
-impl<'source> FromPyObject<'source> for Item {
-    fn extract(ob: &'source PyAny) -> PyResult<Self> {
-        let s: &str = ob.extract()?;
-        Ok(Item::str(s).unwrap())
-    }
-}
-#[cfg(feature = "python")]
-impl IntoPy<PyObject> for Item {
-    fn into_py(self, py: Python) -> PyObject {
-        self.to_string().into_py(py)
-    }
-}
-impl Item {
-    #[cfg(feature = "python")]
-    #[inline]
-    pub fn str(s: &str) -> Result<Self, String> {
-        use std::str::FromStr;
-        Item::from_str(s).map_err(|_| {
-            format!(
-                "{} is not an Alignment! Alignment must be one of: {:?}",
-                s,
-                Item::iter().collect::<Vec<_>>(),
-            )
-        })
-    }
-}
 impl Default for Item {
     fn default() -> Self {
         Item::Misc
@@ -171,7 +108,14 @@ impl Inventory {
     /// A `character` inventory has Reserved Slots for gear,
     /// as well as some amount of Loose Inventory.
     pub fn character() -> Self {
-        let mut inv = Self::new_loose(6, 2);
+        Self::character_sized(6, 2)
+    }
+
+    #[inline]
+    /// A `character` inventory with that much Loose Inventory,
+    /// i.e. for a character class that can carry more.
+    pub fn character_sized(rows: usize, cols: usize) -> Self {
+        let mut inv = Self::new_loose(rows, cols);
         inv.items.insert(SlotIndex::Reserved(Item::Weapon), None);
         inv
     }
@@ -236,6 +180,14 @@ impl Inventory {
         }
     }
 
+    /// Changes the entity id of every item in this Inventory, i.e. when they're moved
+    /// into another World. Items that `f` gives None for are taken out.
+    pub fn map_ids(&mut self, mut f: impl FnMut(u32) -> Option<u32>) {
+        for slot in self.items.values_mut() {
+            *slot = slot.and_then(&mut f);
+        }
+    }
+
     #[inline]
     /// Finds an empty slot in the Loose Inventory, and inserts the provided item entity id into it.
     ///
@@ -347,6 +299,18 @@ fn inventory_integration() {
     });
 }
 
+#[test]
+fn inventory_map_ids() {
+    let mut inv = Inventory::character();
+    let weapon = inv.insert(3, &Item::Weapon).unwrap();
+    let misc = inv.insert(4, &Item::Misc).unwrap();
+
+    // as if 3 made it into the new World as 7, and 4 didn't make it.
+    inv.map_ids(|id| if id == 3 { Some(7) } else { None });
+    assert_eq!(inv.slot(&weapon).unwrap(), &Some(7));
+    assert_eq!(inv.slot(&misc).unwrap(), &None);
+}
+
 /// A SlotIndex refers to a particular place in an Inventory.
 ///
 /// See the documentation on Inventory for a better understanding of what places exist in an
@@ -1,10 +1,14 @@
 #![feature(stmt_expr_attributes)]
+// lets the code generated by #[derive(Scriptable)] name comn the same way everywhere.
+extern crate self as comn;
 
 pub use nalgebra as na;
 pub use ncollide2d as collide;
 pub use rmp_serde as rmps;
 pub use serde;
 pub use specs;
+#[cfg(feature = "rhai")]
+pub use rhai;
 
 pub mod prelude {
     pub use super::na;
@@ -20,6 +24,7 @@ use specs::{prelude::*, Component};
 #[cfg(feature = "python")]
 pub trait PyWrapper<C: std::fmt::Debug + Component + Send + Sync> {
     fn into_inner(self) -> C;
+    fn from_inner(inner: C) -> Self;
 }
 
 pub type Vec2 = na::Vector2<f32>;
@@ -87,8 +92,14 @@ pub struct PyVec2 {
 impl<'source> FromPyObject<'source> for PyVec2 {
     fn extract(ob: &'source PyAny) -> PyResult<Self> {
         let s: Vec<f32> = ob.extract()?;
+        if s.len() < 2 {
+            return Err(pyo3::exceptions::ValueError::py_err(format!(
+                "expected at least 2 numbers for a 2D vector, found {:?}",
+                s
+            )));
+        }
         Ok(Self {
-            inner: Vec2::from_vec(s),
+            inner: Vec2::new(s[0], s[1]),
         })
     }
 }
@@ -100,31 +111,7 @@ impl IntoPy<PyObject> for PyVec2 {
     }
 }
 
-#[cfg(feature = "python")]
-#[pyclass]
-#[derive(Clone, Debug, Component, Serialize, Deserialize)]
-pub struct Pos {
-    pub iso: Iso2,
-}
-#[cfg(feature = "python")]
-#[pymethods]
-impl Pos {
-    #[new]
-    fn new(obj: &PyRawObject, py_iso: PyIso2) {
-        obj.init(Self { iso: py_iso.into() });
-    }
-
-    #[getter]
-    fn get_iso(&self) -> PyIso2 {
-        PyIso2::from_iso2(self.iso)
-    }
-    #[setter]
-    fn set_iso(&mut self, py_iso: PyIso2) {
-        self.iso = py_iso.into();
-    }
-}
-#[cfg(not(feature = "python"))]
-#[derive(Clone, Debug, Component, Serialize, Deserialize)]
+#[derive(Clone, Debug, Component, Scriptable, Serialize, Deserialize)]
 pub struct Pos {
     pub iso: Iso2,
 }
@@ -156,53 +143,112 @@ macro_rules! trans_of_pos {
     }
 }
 
-#[cfg(feature = "python")]
-#[pyclass]
-#[derive(Clone, Debug, Component, Serialize, Deserialize)]
+#[derive(Clone, Debug, Component, Scriptable, Serialize, Deserialize)]
 pub struct Hitbox {
+    /// Scripts see this as the Hitbox's full width and height.
+    #[script(rename = "extents")]
     pub cuboid: Cuboid<f32>,
+    /// How far the center of the Hitbox is from the center of the entity,
+    /// before the entity is rotated.
+    #[serde(default = "na::zero")]
+    #[script(default = "na::zero")]
+    pub offset: Vec2,
 }
 
-#[cfg(feature = "python")]
-#[pymethods]
 impl Hitbox {
-    #[new]
-    fn new(obj: &PyRawObject, extents: PyVec2) {
-        obj.init(Self {
-            cuboid: Cuboid::new(extents.inner / 2.0),
-        })
+    /// Create a Hitbox from its half extents.
+    /// (i.e. it will be twice as large as the provided numbers on each axis)
+    pub fn vec(vec: Vec2) -> Self {
+        Self {
+            cuboid: Cuboid::new(vec),
+            offset: na::zero(),
+        }
     }
 
-    #[getter]
-    fn get_extents(&self) -> PyVec2 {
-        let h = self.cuboid.half_extents();
-        PyVec2 { inner: h * 2.0 }
-    }
-    #[setter]
-    fn set_extents(&mut self, extents: PyVec2) {
-        self.cuboid = Cuboid::new(extents.inner / 2.0);
+    /// Where the Hitbox is in the world, for an entity at that Isometry.
+    pub fn iso(&self, iso: &Iso2) -> Iso2 {
+        iso * na::Translation2::from(self.offset)
     }
 }
 
-#[cfg(not(feature = "python"))]
-#[derive(Clone, Debug, Component, Serialize, Deserialize)]
-pub struct Hitbox {
-    pub cuboid: Cuboid<f32>,
+#[derive(Clone, Debug, Component, Scriptable, Serialize, Deserialize)]
+/// How far off of the ground an entity is.
+/// This doesn't affect collision, it's just for show.
+pub struct Elevation {
+    pub height: f32,
 }
 
-impl Hitbox {
-    /// Create a Hitbox from its half extents.
-    /// (i.e. it will be twice as large as the provided numbers on each axis)
-    pub fn vec(vec: Vec2) -> Self {
-        Self {
-            cuboid: Cuboid::new(vec),
+#[derive(Clone, Debug, Component, Scriptable, Serialize, Deserialize)]
+/// Takes players who use it to another level, i.e. the next floor down.
+/// They're used the same way items are picked up, from within the same distance.
+pub struct Stairs {
+    /// The name of the level they lead to.
+    pub to: String,
+}
+
+#[derive(Clone, Debug, Component, Scriptable, Serialize, Deserialize)]
+/// Keeps doors and chests shut until they're used by a player carrying the matching Key.
+/// They're used the same way items are picked up, from within the same distance.
+pub struct Lock {
+    /// The id of the Key that opens it.
+    pub key: String,
+    /// Whether the Key is used up in opening it.
+    #[serde(default)]
+    #[script(default = "bool::default")]
+    pub consume: bool,
+}
+
+#[derive(Clone, Debug, Component, Scriptable, Serialize, Deserialize)]
+/// Given to items so that the players carrying them can open the Locks with the same id.
+pub struct Key {
+    pub id: String,
+}
+
+#[cfg(feature = "python")]
+#[pyclass]
+#[derive(Clone, Debug, Component, Serialize, Deserialize)]
+/// An entity's rotation in all three dimensions, as a quaternion in `[x, y, z, w]` order,
+/// with Z pointing up.
+///
+/// Collision only cares about the rotation around the Z axis, which is in the entity's Pos,
+/// but entities with one of these are rendered with this rotation instead.
+pub struct Orientation {
+    #[pyo3(get, set)]
+    pub quaternion: Vec<f32>,
+}
+#[cfg(feature = "python")]
+#[pymethods]
+impl Orientation {
+    #[new]
+    fn new(obj: &PyRawObject, quaternion: Vec<f32>) -> PyResult<()> {
+        if quaternion.len() != 4 {
+            return Err(pyo3::exceptions::ValueError::py_err(format!(
+                "expected a quaternion with 4 numbers, found {:?}",
+                quaternion
+            )));
         }
+        obj.init(Self { quaternion });
+        Ok(())
     }
 }
+#[cfg(not(feature = "python"))]
+#[derive(Clone, Debug, Component, Serialize, Deserialize)]
+/// An entity's rotation in all three dimensions, as a quaternion in `[x, y, z, w]` order,
+/// with Z pointing up.
+///
+/// Collision only cares about the rotation around the Z axis, which is in the entity's Pos,
+/// but entities with one of these are rendered with this rotation instead.
+pub struct Orientation {
+    pub quaternion: Vec<f32>,
+}
 
 #[derive(Default)]
 pub struct Fps(pub f32);
 
+pub mod script;
+pub use script::{ScriptField, Scriptable};
+pub use script_derive::Scriptable;
+
 pub mod art;
 
 pub mod combat;
@@ -219,7 +265,7 @@ pub mod phys;
 
 pub mod net {
     pub use comp::NetComponent;
-    pub use msg::NetMessage;
+    pub use msg::{LobbyCommand, NetMessage};
     // UpdatePosition
     use super::prelude::*;
     use serde::{Deserialize, Serialize};
@@ -242,7 +288,21 @@ pub mod net {
     /// for their Pos and Appearance components.
     /// Essentially, when they want to enter the game world.
     /// Menu/Spectator -> Game
-    pub struct SpawnPlayer;
+    pub struct SpawnPlayer {
+        /// The id of the character class they picked,
+        /// out of the ones they were sent in the Establishment.
+        /// Ignored if the server doesn't have any.
+        pub class: String,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    /// One of the character classes a player can pick before they spawn.
+    pub struct ClassChoice {
+        /// What the client sends back in its SpawnPlayer.
+        pub id: String,
+        pub name: String,
+        pub description: String,
+    }
 
     #[derive(Clone, Debug, Component, Serialize, Deserialize)]
     /// The server attaches this to an entity on the clients to
@@ -267,14 +327,96 @@ pub mod net {
             /// can only be inserted onto the client that requested them.
             InsertComp(u32, NetComponent),
 
+            /// Removes the component of the same type as this one from an entity on the client;
+            /// only its type matters, not what's in it. I.e. a chest that's been unlocked loses
+            /// its Lock this way. If it's sent from the client to the server, it's ignored.
+            RemoveComp(u32, NetComponent),
+
             /// Contains all of the important data necessary to connect a new client to the game.
+            /// It's sent again when a player goes to another level, i.e. down the Stairs,
+            /// after which the client should forget every entity it was told about before.
             /// If it's sent from the client to the server, it's ignored.
             Establishment {
                 /// Tells the local client which of the entities they are.
                 local_player: u32,
                 /// A record of which indexes refer to which appearance names.
                 appearance_record: crate::art::AppearanceRecord,
+                /// The seed the level was generated from,
+                /// so that players can say which layout they ran into a bug on.
+                level_seed: u64,
+                /// The character classes the player can pick from before they spawn.
+                /// If this is empty, there's nothing to pick.
+                classes: Vec<super::ClassChoice>,
             },
+
+            /// A message from the server meant to be shown to the player,
+            /// i.e. an announcement from the server's operator.
+            /// If it's sent from the client to the server, it's ignored.
+            Notice(String),
+
+            /// Tells the client why they're being disconnected from the server.
+            /// The server closes the connection immediately after sending this.
+            /// If it's sent from the client to the server, it's ignored.
+            Kicked(String),
+
+            /// Tells a client who's waiting for a spot in a full server where they are in line;
+            /// the first position in line is 1. It's sent again whenever the line moves.
+            /// If it's sent from the client to the server, it's ignored.
+            Queued(usize),
+
+            /// Asks the server to list, create or join instances, each of which has its own
+            /// copy of a level. The server replies with a Notice.
+            /// If it's sent from the server to the client, it's ignored.
+            Lobby(LobbyCommand),
+        }
+
+        #[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+        /// What players can ask of the server before, or instead of, playing.
+        pub enum LobbyCommand {
+            /// Lists the instances that are running, and the level each is running.
+            List,
+            /// Starts a new instance of the level with this name, and joins it.
+            Create { name: String, level: String },
+            /// Leaves the instance the player is in for the one with this name.
+            Join(String),
+        }
+
+        impl LobbyCommand {
+            /// Reads a command the way players type them, i.e. `/join crypt-run`.
+            pub fn parse(line: &str) -> Result<Self, String> {
+                let line = line.trim();
+                let words: Vec<&str> = line.trim_start_matches('/').split_whitespace().collect();
+                match words.as_slice() {
+                    ["instances"] => Ok(LobbyCommand::List),
+                    ["create", name, level] => Ok(LobbyCommand::Create {
+                        name: name.to_string(),
+                        level: level.to_string(),
+                    }),
+                    ["join", name] => Ok(LobbyCommand::Join(name.to_string())),
+                    _ => Err(format!(
+                        "{:?} isn't a command; try /instances, /create <name> <level> or /join <name>",
+                        line
+                    )),
+                }
+            }
+        }
+
+        #[test]
+        fn test_parse_lobby_command() {
+            assert_eq!(LobbyCommand::parse("/instances"), Ok(LobbyCommand::List));
+            assert_eq!(
+                LobbyCommand::parse(" /create  crypt-run dungeon "),
+                Ok(LobbyCommand::Create {
+                    name: "crypt-run".to_string(),
+                    level: "dungeon".to_string(),
+                })
+            );
+            assert_eq!(
+                LobbyCommand::parse("/join crypt-run"),
+                Ok(LobbyCommand::Join("crypt-run".to_string()))
+            );
+            assert!(LobbyCommand::parse("/join").is_err());
+            assert!(LobbyCommand::parse("/dance").is_err());
         }
     }
 
@@ -286,7 +428,7 @@ pub mod net {
 
         macro_rules! net_component_base {
             ( $( $x:tt : $y:ty $(: $extra:ident)? ),+ $(,)? ) => {
-                #[derive(Deserialize, Serialize, Debug)]
+                #[derive(Deserialize, Serialize, Debug, Clone)]
                 pub enum NetComponent {
                     $(
                         $x($y),
@@ -309,6 +451,15 @@ pub mod net {
                             )+
                         }
                     }
+
+                    /// Removes the component of this one's type from the entity.
+                    pub fn remove(&self, ent: Entity, lu: &LazyUpdate) {
+                        match self {
+                            $(
+                                NetComponent::$x(_) => lu.remove::<$y>(ent),
+                            )+
+                        }
+                    }
                 }
             };
         }
@@ -328,7 +479,7 @@ pub mod net {
         use crate::controls::{Camera, Heading, Speed};
         use crate::dead::Dead;
         use crate::item::{Deposition, DropRequest, Inventory, PickupRequest};
-        use crate::{Hitbox, Item};
+        use crate::{Elevation, Hitbox, Item, Lock, Orientation, Stairs};
 
         net_component! {
             // art
@@ -346,6 +497,10 @@ pub mod net {
             // phys/net
             Pos,
             Hitbox,
+            Elevation,
+            Orientation,
+            Stairs,
+            Lock,
             UpdatePosition,
             SpawnPlayer,
             LocalPlayer,
@@ -24,14 +24,16 @@ impl<'a> System<'a> for Collision {
         // for everyone going somewhere...
         (&*ents, &poses, &hitboxes, &headings)
             .join()
-            .filter_map(|(ent, Pos { iso }, Hitbox { cuboid: hb }, _)| {
+            .filter_map(|(ent, Pos { iso }, hb, _)| {
+                let hb_iso = hb.iso(iso);
                 // for everything they could collide with...
-                for (o_ent, Pos { iso: o_iso }, Hitbox { cuboid: o_hb }) in
+                for (o_ent, Pos { iso: o_iso }, o_hb) in
                     (&*ents, &poses, &hitboxes).join()
                 {
                     if ent != o_ent {
                         // they're touching the goer! goer goes back!
-                        if let Some(c) = contact(iso, hb, o_iso, o_hb, 0.0) {
+                        let o_hb_iso = o_hb.iso(o_iso);
+                        if let Some(c) = contact(&hb_iso, &hb.cuboid, &o_hb_iso, &o_hb.cuboid, 0.0) {
                             return Some((ent, o_ent, c.normal.into_inner() * c.depth));
                         }
                     }
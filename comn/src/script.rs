@@ -0,0 +1,474 @@
+//! Lets level scripts make, look at and change components.
+//!
+//! Deriving Scriptable gives scripts a constructor named after the component,
+//! which takes each of its fields in order, and a getter and setter for each field.
+//! Every field's type has to be a ScriptField.
+//!
+//! ```ignore
+//! #[derive(Clone, Debug, Component, Scriptable)]
+//! pub struct Hitbox {
+//!     #[script(rename = "extents")]
+//!     pub cuboid: Cuboid<f32>,
+//!     #[script(default = "na::zero")]
+//!     pub offset: Vec2,
+//! }
+//! ```
+//!
+//! `rename` changes what scripts call a field, and `default` lets them leave it out,
+//! calling that function to fill it in instead. Fields with a default have to come last.
+//!
+//! Enums can derive it too, if their variants don't hold anything and they also derive
+//! Display, EnumString and EnumIter; scripts make them from a variant's name,
+//! i.e. `Alignment("Enemies")`.
+//!
+//! Python is given a class wrapping the component, i.e. `PyHitbox`,
+//! which is just called `Hitbox` in Python. Rhai is given the component itself.
+//! Each is only generated if comn is built with the `python` or `rhai` feature.
+use crate::prelude::*;
+use crate::Cuboid;
+use specs::Component;
+use std::fmt::Debug;
+use std::str::FromStr;
+use strum::IntoEnumIterator;
+// scripting
+#[cfg(feature = "python")]
+use crate::{PyIso2, PyVec2, PyWrapper};
+#[cfg(feature = "python")]
+use pyo3::{prelude::*, type_object::PyTypeCreate, types::PyAny};
+#[cfg(feature = "rhai")]
+use rhai::{Array, Dynamic, Engine, EvalAltResult, FLOAT, INT};
+
+/// Expands to what it's given, but only if comn is built with the python feature,
+/// so that what `#[derive(Scriptable)]` generates in other crates matches comn.
+#[cfg(feature = "python")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! if_python {
+    ( $( $t:tt )* ) => { $( $t )* };
+}
+#[cfg(not(feature = "python"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! if_python {
+    ( $( $t:tt )* ) => {};
+}
+
+/// Expands to what it's given, but only if comn is built with the rhai feature.
+#[cfg(feature = "rhai")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! if_rhai {
+    ( $( $t:tt )* ) => { $( $t )* };
+}
+#[cfg(not(feature = "rhai"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! if_rhai {
+    ( $( $t:tt )* ) => {};
+}
+
+/// A component that level scripts can use. Implement this with `#[derive(Scriptable)]`.
+pub trait Scriptable: Component + Debug + Clone + Send + Sync + 'static {
+    /// What scripts call this component.
+    const NAME: &'static str;
+
+    /// The class Python scripts make this component with.
+    #[cfg(feature = "python")]
+    type Py: PyWrapper<Self> + PyTypeCreate + Clone;
+
+    /// Gives Rhai scripts this component's constructor, getters and setters.
+    #[cfg(feature = "rhai")]
+    fn register_rhai(engine: &mut Engine);
+}
+
+/// A type that a Scriptable component's fields can have.
+pub trait ScriptField: Sized {
+    /// What Python scripts are given when they look at the field.
+    #[cfg(feature = "python")]
+    type Py: IntoPy<PyObject>;
+    #[cfg(feature = "python")]
+    fn to_py(&self) -> Self::Py;
+    #[cfg(feature = "python")]
+    fn from_py(ob: &PyAny) -> PyResult<Self>;
+
+    #[cfg(feature = "rhai")]
+    fn to_rhai(&self) -> Dynamic;
+    #[cfg(feature = "rhai")]
+    fn from_rhai(value: Dynamic) -> Result<Self, String>;
+}
+
+/// Gives Python a copy of a component.
+#[cfg(feature = "python")]
+pub fn py_component<C: Scriptable>(py: Python, component: C) -> PyResult<PyObject> {
+    let wrapper = Py::new(py, <C::Py as PyWrapper<C>>::from_inner(component))?;
+    Ok(IntoPy::<PyObject>::into_py(wrapper, py))
+}
+
+/// Finds the variant of an enum with this name, i.e. `Alignment::Enemies` for "Enemies".
+#[doc(hidden)]
+pub fn parse_variant<E: FromStr + IntoEnumIterator + Debug>(
+    type_name: &str,
+    s: &str,
+) -> Result<E, String>
+where
+    E::Iterator: Iterator<Item = E>,
+{
+    E::from_str(s).map_err(|_| {
+        format!(
+            "{:?} isn't a valid {}, expected one of: {:?}",
+            s,
+            type_name,
+            E::iter().collect::<Vec<_>>(),
+        )
+    })
+}
+
+/// Gives Rhai scripts the types that components' fields are made from, i.e. `Iso2`.
+#[cfg(feature = "rhai")]
+pub fn register_rhai_types(engine: &mut Engine) {
+    engine
+        .register_type_with_name::<Iso2>("Iso2")
+        .register_fn(
+            "Iso2",
+            |loc: Dynamic, rot: Dynamic| -> Result<Iso2, Box<EvalAltResult>> {
+                Ok(Iso2::new(Vec2::from_rhai(loc)?, f32::from_rhai(rot)?))
+            },
+        )
+        .register_get("loc", |iso: &mut Iso2| iso.translation.vector.to_rhai())
+        .register_get("rot", |iso: &mut Iso2| iso.rotation.angle() as FLOAT);
+}
+
+#[cfg(feature = "rhai")]
+fn rhai_float(value: &Dynamic) -> Result<f32, String> {
+    value
+        .as_float()
+        .map(|f| f as f32)
+        .or_else(|_| value.as_int().map(|i| i as f32))
+        .map_err(|found| format!("expected a number, found {}", found))
+}
+
+#[cfg(feature = "rhai")]
+fn rhai_array(value: Dynamic) -> Result<Array, String> {
+    let found = value.type_name();
+    value
+        .try_cast::<Array>()
+        .ok_or_else(|| format!("expected an array, found {}", found))
+}
+
+#[cfg(feature = "rhai")]
+fn rhai_uint(value: Dynamic) -> Result<INT, String> {
+    let found = value.type_name();
+    match value.as_int() {
+        Ok(i) if i >= 0 => Ok(i),
+        Ok(i) => Err(format!("expected a positive number, found {}", i)),
+        Err(_) => Err(format!("expected a whole number, found {}", found)),
+    }
+}
+
+impl ScriptField for f32 {
+    #[cfg(feature = "python")]
+    type Py = f32;
+    #[cfg(feature = "python")]
+    fn to_py(&self) -> f32 {
+        *self
+    }
+    #[cfg(feature = "python")]
+    fn from_py(ob: &PyAny) -> PyResult<Self> {
+        ob.extract()
+    }
+
+    #[cfg(feature = "rhai")]
+    fn to_rhai(&self) -> Dynamic {
+        Dynamic::from(*self as FLOAT)
+    }
+    #[cfg(feature = "rhai")]
+    fn from_rhai(value: Dynamic) -> Result<Self, String> {
+        rhai_float(&value)
+    }
+}
+
+impl ScriptField for u32 {
+    #[cfg(feature = "python")]
+    type Py = u32;
+    #[cfg(feature = "python")]
+    fn to_py(&self) -> u32 {
+        *self
+    }
+    #[cfg(feature = "python")]
+    fn from_py(ob: &PyAny) -> PyResult<Self> {
+        ob.extract()
+    }
+
+    #[cfg(feature = "rhai")]
+    fn to_rhai(&self) -> Dynamic {
+        Dynamic::from(*self as INT)
+    }
+    #[cfg(feature = "rhai")]
+    fn from_rhai(value: Dynamic) -> Result<Self, String> {
+        rhai_uint(value).map(|i| i as u32)
+    }
+}
+
+impl ScriptField for usize {
+    #[cfg(feature = "python")]
+    type Py = usize;
+    #[cfg(feature = "python")]
+    fn to_py(&self) -> usize {
+        *self
+    }
+    #[cfg(feature = "python")]
+    fn from_py(ob: &PyAny) -> PyResult<Self> {
+        ob.extract()
+    }
+
+    #[cfg(feature = "rhai")]
+    fn to_rhai(&self) -> Dynamic {
+        Dynamic::from(*self as INT)
+    }
+    #[cfg(feature = "rhai")]
+    fn from_rhai(value: Dynamic) -> Result<Self, String> {
+        rhai_uint(value).map(|i| i as usize)
+    }
+}
+
+impl ScriptField for bool {
+    #[cfg(feature = "python")]
+    type Py = bool;
+    #[cfg(feature = "python")]
+    fn to_py(&self) -> bool {
+        *self
+    }
+    #[cfg(feature = "python")]
+    fn from_py(ob: &PyAny) -> PyResult<Self> {
+        ob.extract()
+    }
+
+    #[cfg(feature = "rhai")]
+    fn to_rhai(&self) -> Dynamic {
+        Dynamic::from(*self)
+    }
+    #[cfg(feature = "rhai")]
+    fn from_rhai(value: Dynamic) -> Result<Self, String> {
+        let found = value.type_name();
+        value
+            .as_bool()
+            .map_err(|_| format!("expected true or false, found {}", found))
+    }
+}
+
+impl ScriptField for String {
+    #[cfg(feature = "python")]
+    type Py = String;
+    #[cfg(feature = "python")]
+    fn to_py(&self) -> String {
+        self.clone()
+    }
+    #[cfg(feature = "python")]
+    fn from_py(ob: &PyAny) -> PyResult<Self> {
+        ob.extract()
+    }
+
+    #[cfg(feature = "rhai")]
+    fn to_rhai(&self) -> Dynamic {
+        Dynamic::from(self.clone())
+    }
+    #[cfg(feature = "rhai")]
+    fn from_rhai(value: Dynamic) -> Result<Self, String> {
+        value
+            .into_string()
+            .map_err(|found| format!("expected a string, found {}", found))
+    }
+}
+
+impl ScriptField for Vec<f32> {
+    #[cfg(feature = "python")]
+    type Py = Vec<f32>;
+    #[cfg(feature = "python")]
+    fn to_py(&self) -> Vec<f32> {
+        self.clone()
+    }
+    #[cfg(feature = "python")]
+    fn from_py(ob: &PyAny) -> PyResult<Self> {
+        ob.extract()
+    }
+
+    #[cfg(feature = "rhai")]
+    fn to_rhai(&self) -> Dynamic {
+        Dynamic::from(self.iter().map(|f| f.to_rhai()).collect::<Array>())
+    }
+    #[cfg(feature = "rhai")]
+    fn from_rhai(value: Dynamic) -> Result<Self, String> {
+        rhai_array(value)?.iter().map(rhai_float).collect()
+    }
+}
+
+/// Scripts see these as lists of two numbers, i.e. `[1.0, 0.5]`.
+impl ScriptField for Vec2 {
+    #[cfg(feature = "python")]
+    type Py = PyVec2;
+    #[cfg(feature = "python")]
+    fn to_py(&self) -> PyVec2 {
+        PyVec2 { inner: *self }
+    }
+    #[cfg(feature = "python")]
+    fn from_py(ob: &PyAny) -> PyResult<Self> {
+        Ok(ob.extract::<PyVec2>()?.inner)
+    }
+
+    #[cfg(feature = "rhai")]
+    fn to_rhai(&self) -> Dynamic {
+        vec![self.x, self.y].to_rhai()
+    }
+    #[cfg(feature = "rhai")]
+    fn from_rhai(value: Dynamic) -> Result<Self, String> {
+        let numbers = Vec::<f32>::from_rhai(value)?;
+        if numbers.len() < 2 {
+            return Err(format!(
+                "expected at least 2 numbers for a 2D vector, found {:?}",
+                numbers
+            ));
+        }
+        Ok(Vec2::new(numbers[0], numbers[1]))
+    }
+}
+
+/// Scripts see these as the vectors they point along; they're normalized when set.
+impl ScriptField for na::Unit<Vec2> {
+    #[cfg(feature = "python")]
+    type Py = PyVec2;
+    #[cfg(feature = "python")]
+    fn to_py(&self) -> PyVec2 {
+        self.into_inner().to_py()
+    }
+    #[cfg(feature = "python")]
+    fn from_py(ob: &PyAny) -> PyResult<Self> {
+        Ok(na::Unit::new_normalize(<Vec2 as ScriptField>::from_py(ob)?))
+    }
+
+    #[cfg(feature = "rhai")]
+    fn to_rhai(&self) -> Dynamic {
+        self.into_inner().to_rhai()
+    }
+    #[cfg(feature = "rhai")]
+    fn from_rhai(value: Dynamic) -> Result<Self, String> {
+        Ok(na::Unit::new_normalize(Vec2::from_rhai(value)?))
+    }
+}
+
+impl ScriptField for Iso2 {
+    #[cfg(feature = "python")]
+    type Py = PyIso2;
+    #[cfg(feature = "python")]
+    fn to_py(&self) -> PyIso2 {
+        PyIso2::from_iso2(*self)
+    }
+    #[cfg(feature = "python")]
+    fn from_py(ob: &PyAny) -> PyResult<Self> {
+        Ok(ob.extract::<PyIso2>()?.into())
+    }
+
+    #[cfg(feature = "rhai")]
+    fn to_rhai(&self) -> Dynamic {
+        Dynamic::from(*self)
+    }
+    #[cfg(feature = "rhai")]
+    fn from_rhai(value: Dynamic) -> Result<Self, String> {
+        let found = value.type_name();
+        value
+            .try_cast::<Iso2>()
+            .ok_or_else(|| format!("expected an Iso2, found {}", found))
+    }
+}
+
+/// Scripts see these as their full width and height, rather than their half extents.
+impl ScriptField for Cuboid<f32> {
+    #[cfg(feature = "python")]
+    type Py = PyVec2;
+    #[cfg(feature = "python")]
+    fn to_py(&self) -> PyVec2 {
+        (self.half_extents() * 2.0).to_py()
+    }
+    #[cfg(feature = "python")]
+    fn from_py(ob: &PyAny) -> PyResult<Self> {
+        Ok(Cuboid::new(<Vec2 as ScriptField>::from_py(ob)? / 2.0))
+    }
+
+    #[cfg(feature = "rhai")]
+    fn to_rhai(&self) -> Dynamic {
+        (self.half_extents() * 2.0).to_rhai()
+    }
+    #[cfg(feature = "rhai")]
+    fn from_rhai(value: Dynamic) -> Result<Self, String> {
+        Ok(Cuboid::new(Vec2::from_rhai(value)? / 2.0))
+    }
+}
+
+/// A component for test_derive_scriptable, with a renamed field that can be left out.
+#[cfg(test)]
+#[derive(Clone, Debug, PartialEq, Component, crate::Scriptable)]
+#[storage(specs::VecStorage)]
+pub struct Tested {
+    pub speed: f32,
+    #[script(rename = "toward", default = "na::zero")]
+    pub heading: Vec2,
+}
+
+#[cfg(test)]
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    PartialEq,
+    Component,
+    crate::Scriptable,
+    strum_macros::Display,
+    strum_macros::EnumString,
+    strum_macros::EnumIter,
+)]
+#[storage(specs::VecStorage)]
+pub enum TestedMood {
+    Calm,
+    Angry,
+}
+
+#[cfg(feature = "rhai")]
+#[test]
+fn test_derive_scriptable() {
+    let mut engine = Engine::new();
+    register_rhai_types(&mut engine);
+    Tested::register_rhai(&mut engine);
+    TestedMood::register_rhai(&mut engine);
+
+    let tested = |script: &str| engine.eval::<Tested>(script);
+    assert_eq!(
+        tested("Tested(2.0)").unwrap(),
+        Tested {
+            speed: 2.0,
+            heading: na::zero(),
+        }
+    );
+    assert_eq!(
+        tested("let t = Tested(2, [1.0, 0.5]); t.speed = t.speed * 2; t").unwrap(),
+        Tested {
+            speed: 4.0,
+            heading: Vec2::new(1.0, 0.5),
+        }
+    );
+    assert_eq!(
+        engine
+            .eval::<FLOAT>("Tested(1.0, [3.0, 4.0]).toward[1]")
+            .unwrap(),
+        4.0
+    );
+    assert!(tested("Tested(\"fast\")").is_err());
+    assert!(tested("Tested()").is_err());
+
+    assert_eq!(
+        engine.eval::<TestedMood>("TestedMood(\"Angry\")").unwrap(),
+        TestedMood::Angry
+    );
+    assert_eq!(
+        parse_variant::<TestedMood>("TestedMood", "Sleepy"),
+        Err("\"Sleepy\" isn't a valid TestedMood, expected one of: [Calm, Angry]".to_string())
+    );
+}
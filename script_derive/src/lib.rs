@@ -0,0 +1,369 @@
+//! `#[derive(Scriptable)]`, which lets level scripts make, look at and change a component.
+//! See the `script` module in comn for how to use it.
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Data, DeriveInput, Fields, Ident, Lit, Meta, NestedMeta, Path, Type};
+
+#[proc_macro_derive(Scriptable, attributes(script))]
+pub fn derive_scriptable(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    let expanded = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => fields
+                .named
+                .iter()
+                .map(ScriptField::parse)
+                .collect::<syn::Result<Vec<_>>>()
+                .and_then(|fields| scriptable_struct(&input, &fields)),
+            _ => Err(syn::Error::new_spanned(
+                &input.ident,
+                "Scriptable can only be derived for structs with named fields",
+            )),
+        },
+        Data::Enum(data) => match data.variants.iter().find(|v| !v.fields.is_empty()) {
+            Some(variant) => Err(syn::Error::new_spanned(
+                variant,
+                "Scriptable can only be derived for enums whose variants hold no data",
+            )),
+            None => Ok(scriptable_enum(&input)),
+        },
+        Data::Union(_) => Err(syn::Error::new_spanned(
+            &input.ident,
+            "Scriptable can't be derived for unions",
+        )),
+    };
+
+    expanded.unwrap_or_else(|e| e.to_compile_error()).into()
+}
+
+/// A field of a struct deriving Scriptable.
+struct ScriptField {
+    ident: Ident,
+    ty: Type,
+    /// What scripts call the field; its name in Rust unless it has `#[script(rename = "...")]`.
+    name: Ident,
+    /// The function from `#[script(default = "...")]`,
+    /// called to fill in the field if a script leaves it out.
+    default: Option<Path>,
+}
+
+impl ScriptField {
+    fn parse(field: &syn::Field) -> syn::Result<Self> {
+        let ident = field.ident.clone().expect("named fields have names");
+        let mut name = ident.clone();
+        let mut default = None;
+
+        for attr in field.attrs.iter().filter(|a| a.path.is_ident("script")) {
+            let list = match attr.parse_meta()? {
+                Meta::List(list) => list,
+                other => {
+                    return Err(syn::Error::new_spanned(
+                        other,
+                        "expected #[script(rename = \"...\")] or #[script(default = \"...\")]",
+                    ))
+                }
+            };
+
+            for nested in list.nested.iter() {
+                let (key, value) = match nested {
+                    NestedMeta::Meta(Meta::NameValue(nv)) => match &nv.lit {
+                        Lit::Str(value) => (&nv.path, value),
+                        _ => return Err(syn::Error::new_spanned(&nv.lit, "expected a string")),
+                    },
+                    _ => return Err(syn::Error::new_spanned(nested, "expected key = \"value\"")),
+                };
+
+                if key.is_ident("rename") {
+                    name = value.parse()?;
+                } else if key.is_ident("default") {
+                    default = Some(value.parse()?);
+                } else {
+                    return Err(syn::Error::new_spanned(
+                        key,
+                        "unknown script attribute, expected rename or default",
+                    ));
+                }
+            }
+        }
+
+        Ok(Self {
+            ident,
+            ty: field.ty.clone(),
+            name,
+            default,
+        })
+    }
+}
+
+fn scriptable_struct(input: &DeriveInput, fields: &[ScriptField]) -> syn::Result<TokenStream2> {
+    // scripts give the fields in order, so only the last ones can be left out.
+    let required = fields.iter().take_while(|f| f.default.is_none()).count();
+    if let Some(field) = fields[required..].iter().find(|f| f.default.is_none()) {
+        return Err(syn::Error::new_spanned(
+            &field.ident,
+            "fields without a default can't come after fields with one",
+        ));
+    }
+
+    let ident = &input.ident;
+    let name = ident.to_string();
+    let wrapper = format_ident!("Py{}", ident);
+
+    let idents: Vec<_> = fields.iter().map(|f| &f.ident).collect();
+    let names: Vec<_> = fields.iter().map(|f| &f.name).collect();
+    let tys: Vec<_> = fields.iter().map(|f| &f.ty).collect();
+    let getters: Vec<_> = names.iter().map(|n| format_ident!("get_{}", n)).collect();
+    let setters: Vec<_> = names.iter().map(|n| format_ident!("set_{}", n)).collect();
+
+    // Python has keyword arguments, so one constructor with optional arguments does.
+    let py_params = fields.iter().map(|f| {
+        let name = &f.name;
+        match f.default {
+            Some(_) => quote! { #name: Option<&::pyo3::types::PyAny> },
+            None => quote! { #name: &::pyo3::types::PyAny },
+        }
+    });
+    let py_inits = fields.iter().map(|f| {
+        let (ident, name) = (&f.ident, &f.name);
+        match &f.default {
+            Some(default) => quote! {
+                #ident: match #name {
+                    Some(value) => ::comn::ScriptField::from_py(value)?,
+                    None => #default(),
+                }
+            },
+            None => quote! { #ident: ::comn::ScriptField::from_py(#name)? },
+        }
+    });
+    let optional = fields[required..].iter().map(|f| {
+        let name = &f.name;
+        quote! { #name = "None" }
+    });
+    let py_args = if required < fields.len() {
+        quote! { #[args(#(#optional),*)] }
+    } else {
+        quote! {}
+    };
+
+    // Rhai doesn't, so it gets a constructor for each number of arguments it can be given.
+    let rhai_constructors = (required..=fields.len()).map(|given| {
+        let params = &names[..given];
+        let inits = fields.iter().enumerate().map(|(i, f)| {
+            let (ident, name) = (&f.ident, &f.name);
+            match &f.default {
+                Some(default) if i >= given => quote! { #ident: #default() },
+                _ => quote! { #ident: ::comn::ScriptField::from_rhai(#name)? },
+            }
+        });
+        quote! {
+            engine.register_fn(
+                #name,
+                |#(#params: ::comn::rhai::Dynamic),*|
+                    -> Result<#ident, Box<::comn::rhai::EvalAltResult>> {
+                    Ok(#ident { #(#inits),* })
+                },
+            );
+        }
+    });
+    let rhai_names: Vec<_> = names.iter().map(|n| n.to_string()).collect();
+
+    let methods = quote! {
+        #[::pyo3::prelude::pymethods]
+        impl #wrapper {
+            #[new]
+            #py_args
+            fn new(obj: &::pyo3::PyRawObject, #(#py_params),*) -> ::pyo3::PyResult<()> {
+                obj.init(Self {
+                    inner: #ident { #(#py_inits),* },
+                });
+                Ok(())
+            }
+
+            #(
+                #[getter]
+                fn #getters(&self) -> <#tys as ::comn::ScriptField>::Py {
+                    ::comn::ScriptField::to_py(&self.inner.#idents)
+                }
+                #[setter]
+                fn #setters(&mut self, value: &::pyo3::types::PyAny) -> ::pyo3::PyResult<()> {
+                    self.inner.#idents = ::comn::ScriptField::from_py(value)?;
+                    Ok(())
+                }
+            )*
+        }
+    };
+
+    let python = python_wrapper(input, &wrapper, methods);
+    Ok(quote! {
+        impl ::comn::Scriptable for #ident {
+            const NAME: &'static str = #name;
+
+            ::comn::if_python! {
+                type Py = #wrapper;
+            }
+
+            ::comn::if_rhai! {
+                fn register_rhai(engine: &mut ::comn::rhai::Engine) {
+                    engine.register_type_with_name::<#ident>(#name);
+                    #(#rhai_constructors)*
+                    #(
+                        engine.register_get(#rhai_names, |c: &mut #ident| {
+                            ::comn::ScriptField::to_rhai(&c.#idents)
+                        });
+                        engine.register_set(
+                            #rhai_names,
+                            |c: &mut #ident, value: ::comn::rhai::Dynamic|
+                                -> Result<(), Box<::comn::rhai::EvalAltResult>> {
+                                c.#idents = ::comn::ScriptField::from_rhai(value)?;
+                                Ok(())
+                            },
+                        );
+                    )*
+                }
+            }
+        }
+
+        #python
+    })
+}
+
+/// The Python class for a component: a wrapper around it,
+/// named `Py` and then the component's name in Rust, and just the component's name in Python.
+///
+/// pyo3's macros expect `PyObject` to be in scope wherever they're used,
+/// so the wrapper and its `methods` are put in a module of their own that has it.
+fn python_wrapper(input: &DeriveInput, wrapper: &Ident, methods: TokenStream2) -> TokenStream2 {
+    let ident = &input.ident;
+    let vis = &input.vis;
+    let doc = format!("The Python class for {}.", ident);
+    let module = format_ident!("__py_{}", ident);
+
+    quote! {
+        ::comn::if_python! {
+            #[allow(non_snake_case)]
+            mod #module {
+                use super::*;
+                use ::pyo3::PyObject;
+
+                #[::pyo3::prelude::pyclass(name = #ident)]
+                #[derive(Debug, Clone)]
+                #[doc = #doc]
+                pub struct #wrapper {
+                    pub inner: #ident,
+                }
+
+                #methods
+            }
+            #vis use self::#module::#wrapper;
+
+            impl ::comn::PyWrapper<#ident> for #wrapper {
+                fn into_inner(self) -> #ident {
+                    self.inner
+                }
+
+                fn from_inner(inner: #ident) -> Self {
+                    Self { inner }
+                }
+            }
+
+            impl<'source> ::pyo3::FromPyObject<'source> for #wrapper {
+                fn extract(ob: &'source ::pyo3::types::PyAny) -> ::pyo3::PyResult<Self> {
+                    use ::pyo3::ObjectProtocol;
+                    Ok(ob.cast_as::<Self>()?.clone())
+                }
+            }
+        }
+    }
+}
+
+/// Enums are made from the names of their variants, and can be the fields of other components.
+fn scriptable_enum(input: &DeriveInput) -> TokenStream2 {
+    let ident = &input.ident;
+    let name = ident.to_string();
+    let wrapper = format_ident!("Py{}", ident);
+    let python = python_wrapper(
+        input,
+        &wrapper,
+        quote! {
+            #[::pyo3::prelude::pymethods]
+            impl #wrapper {
+                #[new]
+                fn new(obj: &::pyo3::PyRawObject, inner: &::pyo3::types::PyAny) -> ::pyo3::PyResult<()> {
+                    obj.init(Self {
+                        inner: ::comn::ScriptField::from_py(inner)?,
+                    });
+                    Ok(())
+                }
+
+                #[getter]
+                fn get_inner(&self) -> String {
+                    self.inner.to_string()
+                }
+                #[setter]
+                fn set_inner(&mut self, value: &::pyo3::types::PyAny) -> ::pyo3::PyResult<()> {
+                    self.inner = ::comn::ScriptField::from_py(value)?;
+                    Ok(())
+                }
+            }
+        },
+    );
+
+    quote! {
+        impl ::comn::ScriptField for #ident {
+            ::comn::if_python! {
+                type Py = String;
+
+                fn to_py(&self) -> String {
+                    self.to_string()
+                }
+
+                fn from_py(ob: &::pyo3::types::PyAny) -> ::pyo3::PyResult<Self> {
+                    use ::pyo3::ObjectProtocol;
+                    ::comn::script::parse_variant(#name, ob.extract()?)
+                        .map_err(::pyo3::exceptions::ValueError::py_err)
+                }
+            }
+
+            ::comn::if_rhai! {
+                fn to_rhai(&self) -> ::comn::rhai::Dynamic {
+                    ::comn::rhai::Dynamic::from(self.clone())
+                }
+
+                fn from_rhai(value: ::comn::rhai::Dynamic) -> Result<Self, String> {
+                    if value.is::<Self>() {
+                        return Ok(value.cast::<Self>());
+                    }
+                    match value.into_string() {
+                        Ok(s) => ::comn::script::parse_variant(#name, &s),
+                        Err(found) => Err(format!("expected a {}, found {}", #name, found)),
+                    }
+                }
+            }
+        }
+
+        impl ::comn::Scriptable for #ident {
+            const NAME: &'static str = #name;
+
+            ::comn::if_python! {
+                type Py = #wrapper;
+            }
+
+            ::comn::if_rhai! {
+                fn register_rhai(engine: &mut ::comn::rhai::Engine) {
+                    engine
+                        .register_type_with_name::<#ident>(#name)
+                        .register_fn(#name, |name: &str| -> Result<#ident, Box<::comn::rhai::EvalAltResult>> {
+                            Ok(::comn::script::parse_variant(#name, name)?)
+                        })
+                        .register_fn("to_string", |v: &mut #ident| v.to_string());
+                }
+            }
+        }
+
+        #python
+    }
+}
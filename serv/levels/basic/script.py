@@ -1,8 +1,13 @@
-import json
 import random
 
-def load_map(map_entries):
-    entries = json.loads(map_entries)
+# Every class an entry in map.json can have; anything else is a typo.
+CLASSES = [
+    "item", "hammer1", "hammer2",
+    "enemy", "boss", "enemygroup1", "enemygroup2",
+    "stairs_down",
+]
+
+def load_map(entries):
     entities = []
 
     banned_hammer = "hammer" + str(random.choice([1, 2]))
@@ -10,7 +15,7 @@ def load_map(map_entries):
 
     for entry in entries:
         obj = basic_components(entry)
-        classes = entry["classes"]
+        classes = entry.classes
 
         should_make = True
 
@@ -20,29 +25,8 @@ def load_map(map_entries):
         
         if banned_hammer in classes:
             should_make = False
-        
-        if "item" in classes:
-            obj.append(Item("Weapon"))
-
-        if "boss" in classes:
-            obj += [
-                Alignment("Enemies"),
-                Chaser(Alignment("Players"), distance = 5),
-                Hurtbox(hp = 2, knockback = 2.7),
-                Hitbox([1, 0.7]),
-                Speed(0.095),
-                Health(max = 4, current = 4),
-            ]
 
-        elif "enemy" in classes:
-            obj += [
-                Alignment("Enemies"),
-                Chaser(Alignment("Players"), distance = 5),
-                Hurtbox(hp = 1, knockback = 2.7),
-                Hitbox([1, 0.7]),
-                Speed(0.075),
-                Health(max = 4, current = 4),
-            ]
+        # items, enemies and the boss get the rest of their components from prefabs.toml.
 
         if should_make:
             entities.append(obj)
@@ -65,3 +49,11 @@ def banned_enemy_groups():
     # player must deal with both
 
     return groups
+
+def on_entity_death(entity, name, classes):
+    if "boss" in classes:
+        # without their leader, the rest of the enemies scatter.
+        for enemy in world.entities_with_class("enemy"):
+            world.despawn(enemy)
+
+        return [Notice("The boss has been defeated!")]
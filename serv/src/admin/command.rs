@@ -0,0 +1,423 @@
+use crate::net::{self, prelude::*};
+use comn::art::AppearanceRecord;
+use comn::combat::Health;
+use comn::item::Inventory;
+use comn::prelude::*;
+use specs::prelude::*;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+const HELP: &str = "\
+commands:
+  help                                  show this message
+  list                                  list connected clients, on every floor of every instance
+  kick <target> [reason]                disconnect a client
+  ban <target> [reason]                 disconnect a client and refuse their IP from now on
+  unban <ip>                            allow an IP to connect again
+  broadcast <message>                   show a message to every player
+  spawn <appearance> <x> <y>            create an entity with that appearance at that location
+  teleport <entity> <x> <y>             move an entity to that location
+  give <entity> <appearance> [item]     put a new item in an entity's inventory (item is Weapon or Misc)
+  health <entity> <current> [max]       set an entity's Health
+a <target> is an entity id, a client's address, or an IP.
+an <entity> is one on the main instance's entrance, where everyone starts out.";
+
+#[derive(Debug, Clone)]
+/// Which clients a Command should affect.
+pub enum Target {
+    Ent(u32),
+    Addr(SocketAddr),
+    Ip(IpAddr),
+}
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        s.parse()
+            .map(Target::Ent)
+            .or_else(|_| s.parse().map(Target::Addr))
+            .or_else(|_| s.parse().map(Target::Ip))
+            .map_err(|_| format!("{:?} isn't an entity id, address, or IP", s))
+    }
+}
+impl Target {
+    /// The addresses of the connected clients this Target refers to.
+    fn addrs(&self, cm: &ConnectionManager) -> Vec<SocketAddr> {
+        cm.addr_to_ent
+            .iter()
+            .filter(|&(addr, &id)| match self {
+                Target::Ent(ent) => id == *ent,
+                Target::Addr(a) => addr == a,
+                Target::Ip(ip) => addr.ip() == *ip,
+            })
+            .map(|(addr, _)| *addr)
+            .collect()
+    }
+
+    /// The addresses of the connected clients this Target refers to, in any of the Worlds.
+    /// Entity ids are only unique within a World, so they're only looked for in the main one.
+    fn addrs_in(&self, worlds: &[(String, &mut World)]) -> Vec<SocketAddr> {
+        let searched = match self {
+            Target::Ent(_) => &worlds[..1],
+            Target::Addr(_) | Target::Ip(_) => worlds,
+        };
+        searched
+            .iter()
+            .flat_map(|(_, world)| self.addrs(&world.read_resource::<ConnectionManager>()))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Command {
+    Help,
+    List,
+    Kick {
+        target: Target,
+        reason: String,
+    },
+    Ban {
+        target: Target,
+        reason: String,
+    },
+    Unban(IpAddr),
+    Broadcast(String),
+    Spawn {
+        appearance: String,
+        loc: Vec2,
+    },
+    Teleport {
+        id: u32,
+        loc: Vec2,
+    },
+    Give {
+        id: u32,
+        appearance: String,
+        item: Item,
+    },
+    SetHealth {
+        id: u32,
+        current: u32,
+        max: Option<u32>,
+    },
+}
+
+/// Parses the argument at the given index, describing it as `what` if something goes wrong.
+fn arg<T: FromStr>(args: &[&str], i: usize, what: &str) -> Result<T, String> {
+    let raw = args.get(i).ok_or_else(|| format!("missing {}", what))?;
+    raw.parse()
+        .map_err(|_| format!("{:?} isn't a valid {}", raw, what))
+}
+
+/// Everything from the given index onwards, or `default` if there's nothing there.
+fn rest(args: &[&str], from: usize, default: &str) -> String {
+    match args.get(from..) {
+        Some(words) if !words.is_empty() => words.join(" "),
+        _ => default.to_string(),
+    }
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let mut words = line.split_whitespace();
+        let name = words.next().ok_or_else(|| "no command given".to_string())?;
+        let args = words.collect::<Vec<_>>();
+
+        Ok(match name {
+            "help" => Command::Help,
+            "list" => Command::List,
+            "kick" => Command::Kick {
+                target: arg(&args, 0, "target")?,
+                reason: rest(&args, 1, "kicked by an admin"),
+            },
+            "ban" => Command::Ban {
+                target: arg(&args, 0, "target")?,
+                reason: rest(&args, 1, "banned by an admin"),
+            },
+            "unban" => Command::Unban(arg(&args, 0, "IP")?),
+            "broadcast" => Command::Broadcast(rest(&args, 0, "")),
+            "spawn" => Command::Spawn {
+                appearance: arg(&args, 0, "appearance")?,
+                loc: Vec2::new(arg(&args, 1, "x")?, arg(&args, 2, "y")?),
+            },
+            "teleport" => Command::Teleport {
+                id: arg(&args, 0, "entity")?,
+                loc: Vec2::new(arg(&args, 1, "x")?, arg(&args, 2, "y")?),
+            },
+            "give" => Command::Give {
+                id: arg(&args, 0, "entity")?,
+                appearance: arg(&args, 1, "appearance")?,
+                item: if args.len() > 2 {
+                    arg(&args, 2, "item")?
+                } else {
+                    Item::default()
+                },
+            },
+            "health" => Command::SetHealth {
+                id: arg(&args, 0, "entity")?,
+                current: arg(&args, 1, "current health")?,
+                max: if args.len() > 2 {
+                    Some(arg(&args, 2, "max health")?)
+                } else {
+                    None
+                },
+            },
+            other => return Err(format!("unknown command {:?}, try `help`", other)),
+        })
+    }
+
+    /// Carries out the Command, returning a description of what happened.
+    /// `worlds` is every floor of every instance, named, starting with
+    /// the main instance's entrance; see `Instances::worlds`.
+    pub fn run(self, worlds: &mut [(String, &mut World)]) -> String {
+        self.try_run(worlds)
+            .unwrap_or_else(|e| format!("error: {}", e))
+    }
+
+    fn try_run(self, worlds: &mut [(String, &mut World)]) -> Result<String, String> {
+        match self {
+            Command::Help => Ok(HELP.to_string()),
+
+            Command::List => {
+                let mut clients = 0;
+                let mut out = String::new();
+                for (name, world) in worlds.iter() {
+                    let cm = world.read_resource::<ConnectionManager>();
+                    let ents = world.entities();
+                    let poses = world.read_storage::<Pos>();
+                    let hps = world.read_storage::<Health>();
+
+                    clients += cm.addr_to_ent.len();
+                    for (addr, &id) in cm.addr_to_ent.iter() {
+                        let ent = ents.entity(id);
+                        out += &format!("\n{} -> entity {} in {}", addr, id, name);
+                        if let Some(Pos { iso }) = poses.get(ent) {
+                            let v = iso.translation.vector;
+                            out += &format!(" at ({:.2}, {:.2})", v.x, v.y);
+                        }
+                        if let Some(Health { current, max }) = hps.get(ent) {
+                            out += &format!(", {}/{} hp", current, max);
+                        }
+                    }
+                }
+                Ok(format!("{} client(s) connected{}", clients, out))
+            }
+
+            Command::Kick { target, reason } => {
+                let addrs = target.addrs_in(worlds);
+                if addrs.is_empty() {
+                    return Err(format!("nobody connected matches {:?}", target));
+                }
+                let cm = main(worlds).read_resource::<ConnectionManager>();
+                for addr in addrs.iter() {
+                    cm.kick(*addr, &reason);
+                }
+                Ok(format!("kicked {} client(s)", addrs.len()))
+            }
+
+            Command::Ban { target, reason } => {
+                let addrs = target.addrs_in(worlds);
+                let cm = main(worlds).read_resource::<ConnectionManager>();
+                // IPs can be banned before anyone from them has connected.
+                if let Target::Ip(ip) = target {
+                    cm.bans
+                        .lock()
+                        .expect("couldn't get ban list")
+                        .ban(ip, &reason, None);
+                }
+                for addr in addrs.iter() {
+                    cm.ban(*addr, &reason, None);
+                }
+                Ok(format!("banned {} connected client(s)", addrs.len()))
+            }
+
+            Command::Unban(ip) => {
+                let cm = main(worlds).read_resource::<ConnectionManager>();
+                if cm.bans.lock().expect("couldn't get ban list").unban(&ip) {
+                    Ok(format!("{} may connect again", ip))
+                } else {
+                    Err(format!("{} wasn't banned", ip))
+                }
+            }
+
+            Command::Broadcast(text) => {
+                if text.is_empty() {
+                    return Err("nothing to broadcast".to_string());
+                }
+                let mut sent = 0;
+                for (_, world) in worlds.iter() {
+                    let cm = world.read_resource::<ConnectionManager>();
+                    cm.broadcast(&text);
+                    sent += cm.addr_to_ent.len();
+                }
+                Ok(format!("sent to {} client(s)", sent))
+            }
+
+            Command::Spawn { appearance, loc } => {
+                let world = main(worlds);
+                let appearance = world
+                    .read_resource::<AppearanceRecord>()
+                    .try_appearance_of(&appearance)?;
+
+                let ent = world
+                    .create_entity()
+                    .with(Pos::vec(loc))
+                    .with(appearance)
+                    .build();
+                net::announce(world, ent);
+
+                Ok(format!("spawned entity {}", ent.id()))
+            }
+
+            Command::Teleport { id, loc } => {
+                let world = main(worlds);
+                let ent = alive(world, id)?;
+                let pos = {
+                    let mut poses = world.write_storage::<Pos>();
+                    let pos = poses
+                        .get_mut(ent)
+                        .ok_or_else(|| format!("entity {} has no position", id))?;
+                    pos.iso.translation.vector = loc;
+                    pos.clone()
+                };
+
+                // Pos rather than UpdatePosition, so that they snap to it.
+                let cm = world.read_resource::<ConnectionManager>();
+                let clients = world.read_storage::<Client>();
+                let logging_ins = world.read_storage::<LoggingIn>();
+                for (Client(addr), _) in (&clients, !&logging_ins).join() {
+                    cm.insert_comp(*addr, ent, pos.clone());
+                }
+
+                Ok(format!("moved entity {}", id))
+            }
+
+            Command::Give {
+                id,
+                appearance,
+                item,
+            } => {
+                let world = main(worlds);
+                let player = alive(world, id)?;
+                if !world.read_storage::<Inventory>().contains(player) {
+                    return Err(format!("entity {} has no inventory", id));
+                }
+                let appearance = world
+                    .read_resource::<AppearanceRecord>()
+                    .try_appearance_of(&appearance)?;
+
+                let item_ent = world
+                    .create_entity()
+                    .with(item.clone())
+                    .with(appearance)
+                    .build();
+
+                let inserted = world
+                    .write_storage::<Inventory>()
+                    .get_mut(player)
+                    .expect("inventory vanished while giving item")
+                    .insert(item_ent.id(), &item)
+                    .map_err(|e| format!("couldn't give item: {:?}", e));
+                let index = match inserted {
+                    Ok(index) => index,
+                    Err(e) => {
+                        world
+                            .delete_entity(item_ent)
+                            .expect("couldn't delete item that didn't fit");
+                        return Err(e);
+                    }
+                };
+
+                // everyone needs to know what the item looks like,
+                // then the player needs to know they have it.
+                net::announce(world, item_ent);
+                if let Some(Client(addr)) = world.read_storage::<Client>().get(player) {
+                    let inv = world
+                        .read_storage::<Inventory>()
+                        .get(player)
+                        .cloned()
+                        .expect("inventory vanished while giving item");
+                    world
+                        .read_resource::<ConnectionManager>()
+                        .insert_comp(*addr, player, inv);
+                }
+
+                Ok(format!(
+                    "gave entity {} item {} at {:?}",
+                    id,
+                    item_ent.id(),
+                    index
+                ))
+            }
+
+            Command::SetHealth { id, current, max } => {
+                let world = main(worlds);
+                let ent = alive(world, id)?;
+                let mut hps = world.write_storage::<Health>();
+                let max = max
+                    .or_else(|| hps.get(ent).map(|hp| hp.max))
+                    .unwrap_or(current);
+                hps.insert(ent, Health { current, max })
+                    .map_err(|e| e.to_string())?;
+                Ok(format!("entity {} now has {}/{} hp", id, current, max))
+            }
+        }
+    }
+}
+
+/// The World everyone starts out in, the main instance's entrance.
+/// Entity ids are only unique within a World, so the commands that take one use this.
+fn main<'a>(worlds: &'a mut [(String, &mut World)]) -> &'a mut World {
+    worlds
+        .first_mut()
+        .map(|(_, world)| &mut **world)
+        .expect("the main instance's entrance is never closed")
+}
+
+/// Finds the living entity with the given id.
+fn alive(world: &World, id: u32) -> Result<Entity, String> {
+    let ents = world.entities();
+    let ent = ents.entity(id);
+    if ents.is_alive(ent) {
+        Ok(ent)
+    } else {
+        Err(format!("entity {} doesn't exist", id))
+    }
+}
+
+#[test]
+fn test_target_entity_in_main_world_only() {
+    let addr = |ip| SocketAddr::from(([10, 0, 0, ip], 3012));
+    let (to_clients, sent) = crossbeam_channel::unbounded();
+    let server = ConnectionManager {
+        to_clients,
+        ..ConnectionManager::offline()
+    };
+    let world_with = |addr: SocketAddr| {
+        let (mut cm, _) = server.branch();
+        cm.addr_to_ent.insert(addr, 5);
+        let mut world = World::new();
+        world.insert(cm);
+        world
+    };
+    let (mut main, mut crypt) = (world_with(addr(1)), world_with(addr(2)));
+    let mut worlds = vec![
+        ("main/basic".to_string(), &mut main),
+        ("main/crypt".to_string(), &mut crypt),
+    ];
+
+    assert_eq!(Target::Ent(5).addrs_in(&worlds), vec![addr(1)]);
+    assert_eq!(Target::Ip(addr(2).ip()).addrs_in(&worlds), vec![addr(2)]);
+
+    let banned = Command::Ban {
+        target: Target::Ent(5),
+        reason: "cheating".to_string(),
+    }
+    .run(&mut worlds);
+    assert_eq!(banned, "banned 1 connected client(s)");
+    let bans = server.bans.lock().unwrap();
+    assert!(bans.get(&addr(1).ip()).is_some());
+    assert!(bans.get(&addr(2).ip()).is_none());
+    let kicked: Vec<SocketAddr> = sent.try_iter().map(|(addr, _)| addr).collect();
+    assert_eq!(kicked, vec![addr(1)]);
+}
@@ -0,0 +1,332 @@
+//! The admin console lets the server's operator manage a running game
+//! without having to restart it.
+//!
+//! It listens on a local TCP socket or, on unix, a unix socket, the address of which is
+//! configured along with a password in the `[admin]` section of hauntfall_server_config.toml.
+//! Addresses starting with `unix:` are the path to a unix socket, i.e.
+//! `unix:/run/hauntfall/admin.sock`, which only the user running the server can connect to.
+//! If that section is missing, the console is never started.
+//!
+//! Once connected (i.e. with `nc 127.0.0.1 3013`, or `nc -U /run/hauntfall/admin.sock`),
+//! the first line sent must be the password. Every line after that is a command;
+//! type `help` for a list of them. Each session gets a few tries at the password,
+//! and after too many wrong passwords in a row, nobody can log in for a while.
+//!
+//! Commands are parsed on the console's own threads, but they're only ever
+//! carried out on the game thread, in between dispatches.
+use crate::instances::Instances;
+use crossbeam_channel::{bounded, unbounded, Receiver, Sender};
+use log::*;
+use serde::Deserialize;
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    sync::{Arc, Mutex},
+    thread::spawn,
+    time::{Duration, Instant},
+};
+
+mod command;
+pub use command::Command;
+
+#[derive(Deserialize, Clone)]
+/// Found in the `[admin]` section of `hauntfall_server_config.toml`.
+pub struct AdminConfig {
+    /// Where the console should listen, i.e. "127.0.0.1:3013",
+    /// or "unix:" and then the path to a unix socket.
+    /// This should almost always be a loopback address.
+    pub address: String,
+    /// Must be sent as the first line of every console session.
+    pub password: String,
+}
+
+/// A Command, and where to send the text describing what happened when it ran.
+type Request = (Command, Sender<String>);
+
+/// What addresses that are the path to a unix socket start with.
+const UNIX_PREFIX: &str = "unix:";
+/// How many passwords can be tried before a session is closed.
+const ATTEMPTS_PER_SESSION: u32 = 3;
+/// How many wrong passwords in a row, across every session, lock everyone out.
+const FAILURES_BEFORE_LOCKOUT: u32 = 10;
+/// How long nobody can log in for once they're locked out.
+const LOCKOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, PartialEq)]
+enum Login {
+    Accepted,
+    WrongPassword,
+    /// No passwords are being checked until this much longer has passed.
+    LockedOut(Duration),
+}
+
+/// Counts wrong passwords across every session, so that opening more sessions
+/// doesn't allow for more guesses.
+#[derive(Default)]
+struct Logins {
+    failures: u32,
+    locked_until: Option<Instant>,
+}
+impl Logins {
+    fn attempt(&mut self, attempt: &str, password: &str, now: Instant) -> Login {
+        if let Some(until) = self.locked_until {
+            if now < until {
+                return Login::LockedOut(until - now);
+            }
+            self.locked_until = None;
+        }
+
+        if attempt.trim() == password {
+            self.failures = 0;
+            Login::Accepted
+        } else {
+            self.failures += 1;
+            if self.failures >= FAILURES_BEFORE_LOCKOUT {
+                self.failures = 0;
+                self.locked_until = Some(now + LOCKOUT);
+            }
+            Login::WrongPassword
+        }
+    }
+}
+
+pub struct AdminConsole {
+    requests: Receiver<Request>,
+}
+impl AdminConsole {
+    /// Starts listening for console sessions on a separate thread.
+    pub fn listen(config: &AdminConfig) -> Self {
+        let (to_game, requests) = unbounded();
+        let sessions = Sessions {
+            password: config.password.clone(),
+            logins: Arc::new(Mutex::new(Logins::default())),
+            to_game,
+        };
+
+        if config.address.starts_with(UNIX_PREFIX) {
+            sessions.listen_unix(&config.address[UNIX_PREFIX.len()..]);
+        } else {
+            let listener = TcpListener::bind(&config.address).unwrap_or_else(|e| {
+                panic!("couldn't bind admin console to {}: {}", config.address, e)
+            });
+            info!("admin console listening on {}", config.address);
+
+            spawn(move || {
+                for stream in listener.incoming() {
+                    let opened =
+                        stream.and_then(|s| Ok((s.peer_addr()?.to_string(), s.try_clone()?, s)));
+                    match opened {
+                        Ok((peer, read, write)) => sessions.start(peer, read, write),
+                        Err(e) => error!("admin console connection failed: {}", e),
+                    }
+                }
+            });
+        }
+
+        Self { requests }
+    }
+
+    /// Carries out every Command that has come in since the last time this was called.
+    /// This should be called on the game thread, in between dispatches.
+    pub fn run_pending(&self, instances: &mut Instances) {
+        while let Ok((command, reply)) = self.requests.try_recv() {
+            info!("admin console: {:?}", command);
+            let output = command.run(&mut instances.worlds());
+
+            // if they've disconnected in the meantime, they don't need to know.
+            let _ = reply.send(output);
+        }
+    }
+}
+
+/// What every console session needs, whichever kind of socket it's on.
+#[derive(Clone)]
+struct Sessions {
+    password: String,
+    logins: Arc<Mutex<Logins>>,
+    to_game: Sender<Request>,
+}
+impl Sessions {
+    /// Handles a connection on a separate thread.
+    fn start(
+        &self,
+        peer: String,
+        read: impl Read + Send + 'static,
+        out: impl Write + Send + 'static,
+    ) {
+        let sessions = self.clone();
+        spawn(move || {
+            if let Err(e) = sessions.session(&peer, read, out) {
+                debug!("admin console session ended: {}", e);
+            }
+        });
+    }
+
+    #[cfg(unix)]
+    fn listen_unix(self, path: &str) {
+        use std::os::unix::{fs::PermissionsExt, net::UnixListener};
+
+        // a socket left behind by an earlier run would keep it from being bound.
+        if let Err(e) = std::fs::remove_file(path) {
+            if e.kind() != io::ErrorKind::NotFound {
+                panic!(
+                    "couldn't remove old admin console socket at {}: {}",
+                    path, e
+                );
+            }
+        }
+        let listener = UnixListener::bind(path)
+            .unwrap_or_else(|e| panic!("couldn't bind admin console to {}: {}", path, e));
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+            .unwrap_or_else(|e| panic!("couldn't restrict the admin console socket: {}", e));
+        info!("admin console listening on {}", path);
+
+        let path = path.to_string();
+        spawn(move || {
+            for stream in listener.incoming() {
+                match stream.and_then(|s| Ok((s.try_clone()?, s))) {
+                    Ok((read, write)) => self.start(path.clone(), read, write),
+                    Err(e) => error!("admin console connection failed: {}", e),
+                }
+            }
+        });
+    }
+
+    #[cfg(not(unix))]
+    fn listen_unix(self, path: &str) {
+        panic!(
+            "couldn't bind admin console to {}: unix sockets are only supported on unix",
+            path
+        );
+    }
+
+    /// Handles a single connection to the admin console, from password to disconnect.
+    fn session(&self, peer: &str, read: impl Read, mut out: impl Write) -> io::Result<()> {
+        let mut lines = BufReader::new(read).lines();
+
+        let mut attempts = 0;
+        loop {
+            write!(out, "password: ")?;
+            out.flush()?;
+            let attempt = match lines.next() {
+                Some(line) => line?,
+                None => return Ok(()),
+            };
+
+            let login = self
+                .logins
+                .lock()
+                .expect("admin console logins poisoned")
+                .attempt(&attempt, &self.password, Instant::now());
+            match login {
+                Login::Accepted => break,
+                Login::WrongPassword => {
+                    warn!("admin console login from {} failed", peer);
+                    writeln!(out, "wrong password")?;
+                }
+                Login::LockedOut(left) => {
+                    warn!("admin console login from {} refused, locked out", peer);
+                    writeln!(
+                        out,
+                        "too many wrong passwords, try again in {} seconds",
+                        left.as_secs() + 1
+                    )?;
+                    return Ok(());
+                }
+            }
+
+            attempts += 1;
+            if attempts >= ATTEMPTS_PER_SESSION {
+                return Ok(());
+            }
+        }
+        info!("admin console session opened from {}", peer);
+        writeln!(out, "welcome! type `help` for a list of commands.")?;
+        let to_game = &self.to_game;
+
+        for line in lines {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            match Command::parse(&line) {
+                Ok(command) => {
+                    let (reply, output) = bounded(1);
+                    if to_game.send((command, reply)).is_err() {
+                        writeln!(out, "the game loop isn't running")?;
+                        break;
+                    }
+                    match output.recv() {
+                        Ok(text) => writeln!(out, "{}", text)?,
+                        Err(_) => break,
+                    }
+                }
+                Err(e) => writeln!(out, "{}", e)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_login_lockout() {
+    let mut logins = Logins::default();
+    let start = Instant::now();
+
+    assert_eq!(
+        logins.attempt("guess", "hunter2", start),
+        Login::WrongPassword
+    );
+    assert_eq!(
+        logins.attempt(" hunter2\r", "hunter2", start),
+        Login::Accepted
+    );
+
+    for _ in 0..FAILURES_BEFORE_LOCKOUT {
+        assert_eq!(
+            logins.attempt("guess", "hunter2", start),
+            Login::WrongPassword
+        );
+    }
+    // even the right password is refused until the lockout is over.
+    assert_eq!(
+        logins.attempt("hunter2", "hunter2", start + Duration::from_secs(1)),
+        Login::LockedOut(LOCKOUT - Duration::from_secs(1))
+    );
+    assert_eq!(
+        logins.attempt("hunter2", "hunter2", start + LOCKOUT),
+        Login::Accepted
+    );
+}
+
+#[test]
+fn test_session_password_attempts() {
+    let (to_game, _requests) = unbounded();
+    let sessions = Sessions {
+        password: "hunter2".to_string(),
+        logins: Arc::new(Mutex::new(Logins::default())),
+        to_game,
+    };
+    let transcript = |input: &str| {
+        let mut out = Vec::new();
+        sessions
+            .session("test", input.as_bytes(), &mut out)
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    };
+
+    // a few typos are forgiven.
+    assert!(
+        transcript("hunter\nhunter2\n").ends_with("welcome! type `help` for a list of commands.\n")
+    );
+
+    // but the session ends before the last guess is even read.
+    let out = transcript("a\nb\nc\nhunter2\n");
+    assert_eq!(
+        out.matches("wrong password").count(),
+        ATTEMPTS_PER_SESSION as usize
+    );
+    assert!(!out.contains("welcome"));
+}
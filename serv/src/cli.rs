@@ -0,0 +1,219 @@
+//! The command line interface for the server.
+//!
+//! Running `serv` without a subcommand is the same as running `serv serve`.
+use crate::combat::Chaser;
+use crate::config::{Overrides, ServerConfig};
+use comn::art::{Appearance, AppearanceRecord};
+use comn::specs::{prelude::*, WorldExt};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "serv", about = "The Hauntfall game server.")]
+pub struct Opt {
+    #[structopt(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct ConfigOpt {
+    /// The server config file to use.
+    #[structopt(
+        long,
+        default_value = "./hauntfall_server_config.toml",
+        parse(from_os_str)
+    )]
+    pub config: PathBuf,
+    /// The directory in which each level has a folder, if not the one in the config.
+    #[structopt(long, parse(from_os_str))]
+    pub levels_dir: Option<PathBuf>,
+    /// The seed to generate the level from, if not the one in the config.
+    #[structopt(long)]
+    pub seed: Option<u64>,
+}
+impl ConfigOpt {
+    /// Parses the config file, then applies any overrides given on the command line.
+    pub fn load(&self) -> Result<ServerConfig, String> {
+        let mut config = ServerConfig::parse(&self.config)?;
+        if let Some(levels_dir) = &self.levels_dir {
+            config.levels_dir = levels_dir.clone();
+        }
+        if let Some(seed) = self.seed {
+            config.seed = Some(seed);
+        }
+        Ok(config)
+    }
+}
+
+#[derive(StructOpt, Debug)]
+pub enum Command {
+    /// Hosts a game for players to connect to.
+    Serve {
+        #[structopt(flatten)]
+        config: ConfigOpt,
+        /// The level to play, if not the one in the config.
+        #[structopt(long)]
+        level: Option<String>,
+        /// The port to listen for players on, if not the one in the config.
+        #[structopt(long)]
+        port: Option<u16>,
+        /// How many times a second to update the game, if not as often as the config
+        /// or the level's level.toml says.
+        #[structopt(long)]
+        tick_rate: Option<f32>,
+        /// How many players can be in the game at once, if not as many as the config
+        /// or the level's level.toml says.
+        #[structopt(long)]
+        max_players: Option<usize>,
+        /// Reload the level whenever its files change, even if the config doesn't say to.
+        #[structopt(long)]
+        hot_reload: bool,
+    },
+    /// Checks a level's script and map for problems, without letting anyone connect.
+    Validate {
+        #[structopt(flatten)]
+        config: ConfigOpt,
+        level: String,
+    },
+    /// Runs a level for a number of ticks with nobody connected, then reports on how it went.
+    Simulate {
+        #[structopt(flatten)]
+        config: ConfigOpt,
+        level: String,
+        /// How many times to update the game.
+        #[structopt(long, default_value = "1000")]
+        ticks: u64,
+    },
+}
+impl Default for Command {
+    fn default() -> Self {
+        Command::Serve {
+            config: ConfigOpt {
+                config: PathBuf::from("./hauntfall_server_config.toml"),
+                levels_dir: None,
+                seed: None,
+            },
+            level: None,
+            port: None,
+            tick_rate: None,
+            max_players: None,
+            hot_reload: false,
+        }
+    }
+}
+
+impl Command {
+    pub fn run(self) -> Result<(), String> {
+        match self {
+            Command::Serve {
+                config,
+                level,
+                port,
+                tick_rate,
+                max_players,
+                hot_reload,
+            } => {
+                let mut config = config.load()?;
+                if let Some(level) = level {
+                    config.level = level;
+                }
+                if let Some(port) = port {
+                    config.port = port;
+                }
+                config.override_with(Overrides {
+                    tick_rate,
+                    max_players,
+                });
+                config.hot_reload |= hot_reload;
+                crate::serve(config)
+            }
+            Command::Validate { config, level } => {
+                let mut config = config.load()?;
+                config.level = level;
+                validate(config)
+            }
+            Command::Simulate {
+                config,
+                level,
+                ticks,
+            } => {
+                let mut config = config.load()?;
+                config.level = level;
+                simulate(config, ticks)
+            }
+        }
+    }
+}
+
+fn validate(config: ServerConfig) -> Result<(), String> {
+    let report = crate::config::validate(&config);
+    println!("{}", report);
+
+    if report.is_ok() {
+        Ok(())
+    } else {
+        Err(format!("level {} has problems", config.level))
+    }
+}
+
+fn simulate(config: ServerConfig, ticks: u64) -> Result<(), String> {
+    let config = config.for_level(&config.level)?;
+    let (mut world, mut dispatcher, mut level) = crate::setup(
+        &config,
+        config.seed_or_random(),
+        crate::net::ConnectionManager::offline(),
+    )?;
+    world.maintain();
+    let starting_entities = world.entities().join().count();
+
+    let mut slowest = Duration::from_secs(0);
+    let start = Instant::now();
+    for _ in 0..ticks {
+        let tick = Instant::now();
+        dispatcher.dispatch(&world);
+        world.maintain();
+        level.run_hooks(&mut world);
+        slowest = slowest.max(tick.elapsed());
+    }
+    let elapsed = start.elapsed();
+
+    println!(
+        "simulated {} ticks of level {} with seed {}",
+        ticks,
+        config.level,
+        level.seed()
+    );
+    println!("  game time:    {:.2}s", ticks as f32 / config.tick_rate);
+    println!("  real time:    {:.2?}", elapsed);
+    if ticks > 0 {
+        // dividing the Duration itself would take a u32, which `ticks` may not fit in.
+        let average = Duration::from_secs_f64(elapsed.as_secs_f64() / ticks as f64);
+        println!("  average tick: {:.2?}", average);
+        println!("  slowest tick: {:.2?}", slowest);
+    }
+    println!(
+        "  entities:     {} at the start, {} at the end",
+        starting_entities,
+        world.entities().join().count()
+    );
+    println!(
+        "  enemies:      {} alive",
+        world.read_storage::<Chaser>().join().count()
+    );
+
+    let record = world.read_resource::<AppearanceRecord>();
+    let mut appearances = BTreeMap::new();
+    for Appearance { index } in world.read_storage::<Appearance>().join() {
+        *appearances
+            .entry(record.names[*index].as_str())
+            .or_insert(0) += 1;
+    }
+    println!("  appearances:");
+    for (name, count) in appearances {
+        println!("    {:<20} {}", name, count);
+    }
+
+    Ok(())
+}
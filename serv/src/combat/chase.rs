@@ -1,10 +1,14 @@
 use comn::controls::Heading;
 use comn::{prelude::*, vec_of_pos};
+#[cfg(feature = "python")]
 use pyo3::prelude::*;
 use specs::{prelude::*, Component};
 
-pub use super::alignment::{Alignment, PyAlignment};
+pub use super::alignment::Alignment;
+#[cfg(feature = "python")]
+pub use super::alignment::PyAlignment;
 
+#[cfg(feature = "python")]
 #[pyclass]
 #[derive(Debug, Clone, Component)]
 /// Entities with this component will chase other entities
@@ -25,6 +29,7 @@ pub struct Chaser {
     /// calculations are done.
     pub distance_squared: f32,
 }
+#[cfg(feature = "python")]
 #[pymethods]
 impl Chaser {
     #[new]
@@ -45,6 +50,31 @@ impl Chaser {
     }
 }
 
+#[cfg(not(feature = "python"))]
+#[derive(Debug, Clone, Component)]
+/// Entities with this component will chase other entities
+/// who have an Alignment component that matches the one in
+/// the `target` field.
+/// In order for the chasing to begin and/or continue, these
+/// entities must also be within the distance indicated in the
+/// `distance` field.
+pub struct Chaser {
+    pub target: Alignment,
+    /// This is squared so that physics distance calculations
+    /// can be faster.
+    pub distance_squared: f32,
+}
+
+impl Chaser {
+    /// Builds a Chaser the way `Chaser(target, distance)` does in a level script.
+    pub fn chasing(target: Alignment, distance: f32) -> Self {
+        Self {
+            target,
+            distance_squared: distance.powi(2),
+        }
+    }
+}
+
 pub struct Chase;
 impl<'a> System<'a> for Chase {
     type SystemData = (
@@ -5,6 +5,7 @@ pub mod chase;
 pub use chase::{Chase, Chaser};
 
 mod damage {
+    use crate::config::{LevelEvent, LevelEvents, MapOrigin};
     use crate::net::prelude::*;
     use comn::combat::{Damage, Health};
     use comn::prelude::*;
@@ -25,16 +26,28 @@ mod damage {
         type SystemData = (
             Entities<'a>,
             Read<'a, ConnectionManager>,
+            Write<'a, LevelEvents>,
             WriteStorage<'a, Damage>,
             WriteStorage<'a, Health>,
             WriteStorage<'a, Pos>,
             WriteStorage<'a, Dead>,
             ReadStorage<'a, Client>,
+            ReadStorage<'a, MapOrigin>,
         );
 
         fn run(
             &mut self,
-            (ents, cm, mut damages, mut hps, mut poses, mut dead, clients): Self::SystemData,
+            (
+                ents,
+                cm,
+                mut events,
+                mut damages,
+                mut hps,
+                mut poses,
+                mut dead,
+                clients,
+                origins,
+            ): Self::SystemData,
         ) {
             for (ent, ref mut dmg, ref mut hp, vec_of_pos!(loc)) in
                 (&*ents, &mut damages, &mut hps, &mut poses).join()
@@ -52,6 +65,14 @@ mod damage {
                             for Client(addr) in (&clients).join() {
                                 cm.insert_comp(*addr, ent, Dead);
                             }
+
+                            events.push(LevelEvent::EntityDeath {
+                                ent: ent.id(),
+                                origin: origins.get(ent).cloned(),
+                            });
+                            if clients.contains(ent) {
+                                events.push(LevelEvent::PlayerDeath { player: ent.id() });
+                            }
                         }
                     }
                     dmg.hp = 0;
@@ -0,0 +1,173 @@
+//! Character classes are what players pick from before they spawn,
+//! listed in `hauntfall_server_config.toml` like so:
+//!
+//! ```toml
+//! [class.ranger]
+//! name = "Ranger"
+//! description = "Fragile, but quick on their feet."
+//! health = 3
+//! speed = 0.14
+//! inventory = { rows = 4, columns = 2 }
+//! items = ["bow"]
+//! animation = "Shoot"
+//! ```
+//!
+//! A class's `health` and `speed` replace the ones from the `player` prefab.
+//! Its `items` are the names of prefabs with an `item`, which the player starts out carrying,
+//! and its `animation` is which set of attack animations they have:
+//! `Cast`, `Jab`, `Swing` or `Shoot`.
+//!
+//! If there aren't any classes, players aren't asked to pick one, and spawn as before.
+//! Players who come down the stairs keep their class, along with their Health and Inventory.
+use super::{PrefabComponents, Prefabs};
+use comn::art::{player_anim::AttackAnimation, AppearanceRecord};
+use comn::combat::Health;
+use comn::controls::Speed;
+use comn::item::Inventory;
+use comn::net::ClassChoice;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct InventoryLayout {
+    pub rows: usize,
+    pub columns: usize,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+/// A kind of character players can pick to play as.
+pub struct CharacterClass {
+    /// What the class is called, for players to see.
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// Players start out with full Health.
+    pub health: Option<u32>,
+    pub speed: Option<f32>,
+    /// How much Loose Inventory they have, besides their weapon slot.
+    /// If this is left out, they get the usual amount.
+    pub inventory: Option<InventoryLayout>,
+    /// The names of the prefabs for the items they start out carrying.
+    #[serde(default)]
+    pub items: Vec<String>,
+    #[serde(default)]
+    pub animation: AttackAnimation,
+}
+
+impl CharacterClass {
+    /// Replaces the player's components with the ones this class has.
+    pub fn apply(&self, player: &mut PrefabComponents) {
+        if let Some(hp) = self.health {
+            player.health = Some(Health::full(hp));
+        }
+        if let Some(speed) = self.speed {
+            player.speed = Some(Speed { speed });
+        }
+    }
+
+    /// The components of each item players of this class start out carrying.
+    pub fn items(
+        &self,
+        prefabs: &Prefabs,
+        record: &AppearanceRecord,
+    ) -> Result<Vec<PrefabComponents>, String> {
+        self.items
+            .iter()
+            .map(|name| {
+                let item = prefabs.build(name, None, record)?;
+                if item.item.is_none() {
+                    return Err(format!("prefab {:?} doesn't have an item", name));
+                }
+                Ok(item)
+            })
+            .collect()
+    }
+
+    /// The Inventory players of this class start out with, before their items are put in.
+    pub fn inventory(&self) -> Inventory {
+        match &self.inventory {
+            Some(InventoryLayout { rows, columns }) => Inventory::character_sized(*rows, *columns),
+            None => Inventory::character(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(transparent)]
+/// Every CharacterClass, by the id players pick it with.
+/// Kept in the World so that new players can be told about them.
+pub struct CharacterClasses(BTreeMap<String, CharacterClass>);
+
+impl CharacterClasses {
+    /// What players are shown when they're picking a class.
+    pub fn choices(&self) -> Vec<ClassChoice> {
+        self.0
+            .iter()
+            .map(|(id, class)| ClassChoice {
+                id: id.clone(),
+                name: class.name.clone(),
+                description: class.description.clone(),
+            })
+            .collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &CharacterClass)> {
+        self.0.iter()
+    }
+
+    /// The class a player picked, or None if there aren't any to pick from.
+    pub fn pick(&self, id: &str) -> Result<Option<&CharacterClass>, String> {
+        if self.0.is_empty() {
+            return Ok(None);
+        }
+        self.0.get(id).map(Some).ok_or_else(|| {
+            format!(
+                "there's no class called {:?}, expected one of: {:?}",
+                id,
+                self.0.keys().collect::<Vec<_>>()
+            )
+        })
+    }
+}
+
+#[test]
+fn test_character_classes() {
+    #[derive(Deserialize)]
+    struct File {
+        class: CharacterClasses,
+    }
+    let classes = toml::from_str::<File>(
+        r#"
+        [class.mage]
+        name = "Mage"
+        health = 3
+        animation = "Cast"
+
+        [class.fighter]
+        name = "Fighter"
+        inventory = { rows = 2, columns = 2 }
+        items = ["sword"]
+        "#,
+    )
+    .unwrap()
+    .class;
+
+    let ids: Vec<_> = classes.choices().into_iter().map(|c| c.id).collect();
+    assert_eq!(ids, vec!["fighter", "mage"]);
+
+    let mage = classes.pick("mage").unwrap().unwrap();
+    assert_eq!(mage.animation, AttackAnimation::Cast);
+    let mut player = PrefabComponents::default();
+    mage.apply(&mut player);
+    assert_eq!(player.health.map(|h| h.max), Some(3));
+    assert!(player.speed.is_none());
+
+    let fighter = classes.pick("fighter").unwrap().unwrap();
+    assert_eq!(fighter.animation, AttackAnimation::Swing);
+    assert_eq!(fighter.inventory().loose().count(), 4);
+
+    assert!(classes.pick("bard").is_err());
+    assert!(CharacterClasses::default().pick("bard").unwrap().is_none());
+}
@@ -0,0 +1,387 @@
+//! Puts a level's map together out of rooms, instead of reading it from a map.json,
+//! if the level's folder has a `dungeon.toml`.
+//!
+//! ```toml
+//! rooms = 6
+//! room_size = [16.0, 16.0]
+//! start = "start"
+//! boss = "boss"
+//! key = "key"
+//! fillers = ["hall", "crypt"]
+//! ```
+//!
+//! Each room is a small map.json in the level's `rooms` folder, i.e. `rooms/hall.json`,
+//! whose entries are placed relative to the room's center. The rooms are laid out on a grid,
+//! each taking up one `room_size` cell, and are joined by their sockets: entries with
+//! the class "socket" and one of "north", "east", "south" or "west", for the side of the
+//! room they're on. Two rooms can only be put next to each other if they both have
+//! sockets on the sides that face each other.
+//!
+//! The layout is a tree growing out from the start room, so there's only one way to
+//! get to each room. The boss room is always as far from the start as any room is,
+//! and if there's a key room, the boss room's door is locked and the key room is never
+//! behind it. The seed decides everything else, so the same seed gives the same dungeon.
+//!
+//! The level's script is given the rooms' entries like any other map's, with their names
+//! prefixed by their room's, i.e. "hall3/Lantern". Sockets lose their "socket" class,
+//! and gain "door" if they lead to another room, along with "locked" if it's the boss room's
+//! side of its door, or "sealed" if they don't lead anywhere, so that the script can wall them off.
+//! Only one side is locked, so that there's only one door for the key to open.
+use super::MapEntry;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// How many times to start the layout over before giving up on fitting all of the rooms in.
+const ATTEMPTS: usize = 100;
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+/// Normally parsed in from a level's `dungeon.toml`.
+pub struct DungeonConfig {
+    /// How many rooms to put together, counting the start, key and boss rooms.
+    pub rooms: usize,
+    /// How far apart the centers of neighboring rooms are.
+    pub room_size: [f32; 2],
+    /// The room the players start in, which is put at the origin.
+    pub start: String,
+    /// The room put as far from the start as possible.
+    pub boss: String,
+    /// If given, this room is put somewhere outside of the boss room,
+    /// and the door into the boss room is locked.
+    pub key: Option<String>,
+    /// The rooms used to fill out the rest of the dungeon; each can be used any number of times.
+    pub fillers: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Side {
+    North,
+    East,
+    South,
+    West,
+}
+const SIDES: [Side; 4] = [Side::North, Side::East, Side::South, Side::West];
+
+impl Side {
+    fn class(self) -> &'static str {
+        match self {
+            Side::North => "north",
+            Side::East => "east",
+            Side::South => "south",
+            Side::West => "west",
+        }
+    }
+
+    fn opposite(self) -> Self {
+        match self {
+            Side::North => Side::South,
+            Side::East => Side::West,
+            Side::South => Side::North,
+            Side::West => Side::East,
+        }
+    }
+
+    /// The cell on this side of `cell`.
+    fn next(self, (x, y): Cell) -> Cell {
+        match self {
+            Side::North => (x, y + 1),
+            Side::East => (x + 1, y),
+            Side::South => (x, y - 1),
+            Side::West => (x - 1, y),
+        }
+    }
+
+    /// Which side of its room a socket is on.
+    fn of_socket(entry: &MapEntry) -> Result<Self, String> {
+        let mut sides = SIDES
+            .iter()
+            .filter(|side| entry.classes.iter().any(|c| c == side.class()));
+        match (sides.next(), sides.next()) {
+            (Some(side), None) => Ok(*side),
+            _ => Err(format!(
+                "socket {:?} needs exactly one of the classes north, east, south or west",
+                entry.name
+            )),
+        }
+    }
+}
+
+type Cell = (i32, i32);
+
+fn is_socket(entry: &MapEntry) -> bool {
+    entry.classes.iter().any(|c| c == "socket")
+}
+
+/// One of the rooms in the `rooms` folder.
+struct Room {
+    name: String,
+    entries: Vec<MapEntry>,
+    sockets: Vec<Side>,
+}
+
+impl Room {
+    fn load(rooms_dir: &Path, name: &str) -> Result<Self, String> {
+        let path = rooms_dir.join(name).with_extension("json");
+        let json = fs::read_to_string(&path)
+            .map_err(|e| format!("couldn't read room {:?} at {}: {}", name, path.display(), e))?;
+        let entries =
+            MapEntry::parse_all(&json).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        let mut sockets = Vec::new();
+        for entry in entries.iter().filter(|e| is_socket(e)) {
+            let side = Side::of_socket(entry).map_err(|e| format!("{}: {}", path.display(), e))?;
+            if !sockets.contains(&side) {
+                sockets.push(side);
+            }
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            entries,
+            sockets,
+        })
+    }
+
+    fn has_socket(&self, side: Side) -> bool {
+        self.sockets.contains(&side)
+    }
+}
+
+/// A room that's been given a spot in the dungeon.
+struct Placed<'a> {
+    room: &'a Room,
+    cell: Cell,
+    /// How many rooms away from the start room it is.
+    depth: usize,
+}
+
+#[derive(Default)]
+struct Layout<'a> {
+    placed: Vec<Placed<'a>>,
+    /// Both sides of every door between two rooms, and whether that side is locked.
+    doors: HashMap<(Cell, Side), bool>,
+}
+
+impl<'a> Layout<'a> {
+    fn is_free(&self, cell: Cell) -> bool {
+        self.placed.iter().all(|p| p.cell != cell)
+    }
+
+    /// Every room `room` could be attached to, and the side of that room it'd go on.
+    fn spots(&self, room: &Room) -> Vec<(usize, Side)> {
+        let mut spots = Vec::new();
+        for (i, placed) in self.placed.iter().enumerate() {
+            for &side in placed.room.sockets.iter() {
+                if room.has_socket(side.opposite()) && self.is_free(side.next(placed.cell)) {
+                    spots.push((i, side));
+                }
+            }
+        }
+        spots
+    }
+
+    fn attach(&mut self, room: &'a Room, (parent, side): (usize, Side), locked: bool) {
+        let from = &self.placed[parent];
+        let cell = side.next(from.cell);
+        let depth = from.depth + 1;
+
+        // the door is on the side of the room being attached.
+        self.doors.insert((from.cell, side), false);
+        self.doors.insert((cell, side.opposite()), locked);
+        self.placed.push(Placed { room, cell, depth });
+    }
+
+    /// The entries of every room, moved into place, with their sockets marked.
+    fn entries(&self, room_size: [f32; 2]) -> Vec<MapEntry> {
+        let mut entries = Vec::new();
+        for (i, placed) in self.placed.iter().enumerate() {
+            let (x, y) = placed.cell;
+            for entry in placed.room.entries.iter() {
+                let mut entry = entry.clone();
+                entry.name = format!("{}{}/{}", placed.room.name, i, entry.name);
+                entry.location[0] += x as f32 * room_size[0];
+                entry.location[1] += y as f32 * room_size[1];
+
+                if is_socket(&entry) {
+                    entry.classes.retain(|c| c != "socket");
+                    // sockets were checked when the room was loaded.
+                    let side = Side::of_socket(&entry).unwrap();
+                    match self.doors.get(&(placed.cell, side)) {
+                        Some(&locked) => {
+                            entry.classes.push("door".to_string());
+                            if locked {
+                                entry.classes.push("locked".to_string());
+                            }
+                        }
+                        None => entry.classes.push("sealed".to_string()),
+                    }
+                }
+
+                entries.push(entry);
+            }
+        }
+        entries
+    }
+}
+
+impl DungeonConfig {
+    pub fn parse(path: &Path) -> Result<Self, String> {
+        let toml = fs::read_to_string(path)
+            .map_err(|e| format!("couldn't read {}: {}", path.display(), e))?;
+        let config: Self =
+            toml::from_str(&toml).map_err(|e| format!("{} is malformed: {}", path.display(), e))?;
+
+        let needed = if config.key.is_some() { 3 } else { 2 };
+        if config.rooms < needed {
+            return Err(format!(
+                "{} asks for {} rooms, but needs at least {} for the start, key and boss rooms",
+                path.display(),
+                config.rooms,
+                needed
+            ));
+        }
+        if config.fillers.is_empty() && config.rooms > needed {
+            return Err(format!(
+                "{} needs some fillers to make {} rooms out of",
+                path.display(),
+                config.rooms
+            ));
+        }
+
+        Ok(config)
+    }
+
+    /// The names of every room the dungeon can be made of.
+    pub fn room_names(&self) -> impl Iterator<Item = &String> {
+        std::iter::once(&self.start)
+            .chain(std::iter::once(&self.boss))
+            .chain(self.key.iter())
+            .chain(self.fillers.iter())
+    }
+
+    /// Lays out a dungeon out of the rooms in `rooms_dir`, returning their entries.
+    pub fn generate(&self, rooms_dir: &Path, seed: u64) -> Result<Vec<MapEntry>, String> {
+        let mut rooms = HashMap::new();
+        for name in self.room_names() {
+            if !rooms.contains_key(name) {
+                rooms.insert(name.clone(), Room::load(rooms_dir, name)?);
+            }
+        }
+
+        Ok(self.layout(&rooms, seed)?.entries(self.room_size))
+    }
+
+    fn layout<'a>(
+        &self,
+        rooms: &'a HashMap<String, Room>,
+        seed: u64,
+    ) -> Result<Layout<'a>, String> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        for _ in 0..ATTEMPTS {
+            if let Some(layout) = self.try_layout(rooms, &mut rng) {
+                return Ok(layout);
+            }
+        }
+
+        Err(format!(
+            concat!(
+                "couldn't fit {} rooms together after {} tries; ",
+                "the rooms might not have enough sockets on the right sides"
+            ),
+            self.rooms, ATTEMPTS
+        ))
+    }
+
+    fn try_layout<'a>(
+        &self,
+        rooms: &'a HashMap<String, Room>,
+        rng: &mut StdRng,
+    ) -> Option<Layout<'a>> {
+        let mut layout = Layout::default();
+        layout.placed.push(Placed {
+            room: &rooms[&self.start],
+            cell: (0, 0),
+            depth: 0,
+        });
+
+        // None stands in for a filler, since which one is picked depends on where there's room.
+        let mut plan: Vec<Option<&String>> = vec![None; self.rooms - 2];
+        if let Some(key) = &self.key {
+            plan.pop();
+            let at = rng.gen_range(0, plan.len() + 1);
+            plan.insert(at, Some(key));
+        }
+
+        for name in plan {
+            let mut candidates: Vec<&Room> = match name {
+                Some(name) => vec![&rooms[name]],
+                None => self.fillers.iter().map(|name| &rooms[name]).collect(),
+            };
+            candidates.shuffle(rng);
+
+            let (room, spots) = candidates
+                .into_iter()
+                .map(|room| (room, layout.spots(room)))
+                .find(|(_, spots)| !spots.is_empty())?;
+            let spot = *spots.choose(rng)?;
+            layout.attach(room, spot, false);
+        }
+
+        // the boss room goes as deep as it can, which has to be at least as deep as any other.
+        let boss = &rooms[&self.boss];
+        let spots = layout.spots(boss);
+        let deepest = spots.iter().map(|&(i, _)| layout.placed[i].depth).max()?;
+        if layout.placed.iter().any(|p| p.depth > deepest + 1) {
+            return None;
+        }
+        let spots: Vec<_> = spots
+            .into_iter()
+            .filter(|&(i, _)| layout.placed[i].depth == deepest)
+            .collect();
+        let spot = *spots.choose(rng)?;
+        layout.attach(boss, spot, self.key.is_some());
+
+        Some(layout)
+    }
+}
+
+#[test]
+fn test_dungeon_layout() {
+    let room = |name: &str, sockets: Vec<Side>| Room {
+        name: name.to_string(),
+        entries: Vec::new(),
+        sockets,
+    };
+    let mut rooms = HashMap::new();
+    rooms.insert("start".to_string(), room("start", SIDES.to_vec()));
+    rooms.insert("hall".to_string(), room("hall", SIDES.to_vec()));
+    rooms.insert("key".to_string(), room("key", vec![Side::South]));
+    rooms.insert("boss".to_string(), room("boss", vec![Side::South]));
+
+    let config = DungeonConfig {
+        rooms: 6,
+        room_size: [16.0, 16.0],
+        start: "start".to_string(),
+        boss: "boss".to_string(),
+        key: Some("key".to_string()),
+        fillers: vec!["hall".to_string()],
+    };
+
+    for seed in 0..20 {
+        let layout = config.layout(&rooms, seed).unwrap();
+        assert_eq!(layout.placed.len(), 6);
+
+        let boss = layout.placed.last().unwrap();
+        assert_eq!(boss.room.name, "boss");
+        assert!(layout.placed.iter().all(|p| p.depth <= boss.depth));
+        assert!(layout.doors[&(boss.cell, Side::South)]);
+        assert_eq!(
+            layout.doors.values().filter(|&&locked| locked).count(),
+            1,
+            "only the boss room's side of its door should be locked"
+        );
+    }
+}
@@ -0,0 +1,440 @@
+//! Reads a level's map straight out of the scene exported from Blender as a `map.glb`
+//! (or `map.gltf`), so that the map.json doesn't have to be exported separately
+//! and can't fall out of date with the scene.
+//!
+//! Every object with a mesh becomes an entry, named after the object. Its appearance is
+//! the name of its mesh, without the ".001" Blender gives copies, or its `appearance`
+//! custom property if it has one. Every custom property set to true is one of its classes,
+//! as is each name in a comma separated `classes` property. Custom properties are exported
+//! as "extras", which has to be ticked under "Include" in Blender's glTF exporter.
+//!
+//! An object's hitbox comes from a child named i.e. "hitbox" or "collider", or with a
+//! `collider` custom property set to true. If the child is an empty, its hitbox is as big
+//! as a cube-shaped empty of size 1 would look, so an empty scaled to (2, 0.5, 1) gives a
+//! hitbox 4 wide and 1 tall. If it has a mesh, the hitbox is as big as the mesh.
+//! Either way, the hitbox is centered wherever the child is.
+//!
+//! Objects without a mesh are only used to move the objects parented to them.
+//! Positions are turned back into Blender's axes, with Z up.
+use super::MapEntry;
+use comn::na::{Quaternion, UnitQuaternion, Vector3};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// What a .glb file starts with, "glTF" in ASCII.
+const GLB_MAGIC: &[u8] = b"glTF";
+/// The type of the chunk of a .glb file the scene's JSON is in, "JSON" in ASCII.
+const JSON_CHUNK: &[u8] = b"JSON";
+
+#[derive(Deserialize, Debug)]
+struct Gltf {
+    #[serde(default)]
+    scene: usize,
+    scenes: Vec<Scene>,
+    #[serde(default)]
+    nodes: Vec<Node>,
+    #[serde(default)]
+    meshes: Vec<Mesh>,
+    #[serde(default)]
+    accessors: Vec<Accessor>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Scene {
+    #[serde(default)]
+    nodes: Vec<usize>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Node {
+    #[serde(default)]
+    name: String,
+    mesh: Option<usize>,
+    #[serde(default)]
+    children: Vec<usize>,
+    translation: Option<[f32; 3]>,
+    /// In `[x, y, z, w]` order.
+    rotation: Option<[f32; 4]>,
+    scale: Option<[f32; 3]>,
+    matrix: Option<Vec<f32>>,
+    /// Blender's custom properties.
+    #[serde(default)]
+    extras: Value,
+}
+
+#[derive(Deserialize, Debug)]
+struct Mesh {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    primitives: Vec<Primitive>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Primitive {
+    attributes: HashMap<String, usize>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Accessor {
+    min: Option<Vec<f32>>,
+    max: Option<Vec<f32>>,
+}
+
+/// glTF's Y is up, and Blender's -Y is glTF's Z.
+fn to_blender([x, y, z]: [f32; 3]) -> Vector3<f32> {
+    Vector3::new(x, -z, y)
+}
+
+/// Where a node is relative to its parent, in Blender's axes.
+#[derive(Clone, Copy, Debug)]
+struct Transform {
+    translation: Vector3<f32>,
+    rotation: UnitQuaternion<f32>,
+    scale: Vector3<f32>,
+}
+
+impl Transform {
+    fn of(node: &Node) -> Result<Self, String> {
+        if node.matrix.is_some() {
+            return Err(format!(
+                concat!(
+                    "node {:?} is placed with a matrix; ",
+                    "export it with its translation, rotation and scale instead"
+                ),
+                node.name
+            ));
+        }
+        let [x, y, z, w] = node.rotation.unwrap_or([0.0, 0.0, 0.0, 1.0]);
+        let [sx, sy, sz] = node.scale.unwrap_or([1.0, 1.0, 1.0]);
+        Ok(Self {
+            translation: to_blender(node.translation.unwrap_or([0.0; 3])),
+            // the axes of a rotation are turned around like any other vector.
+            rotation: UnitQuaternion::from_quaternion(Quaternion::new(w, x, -z, y)),
+            // scales don't have a direction, so they're only swapped around.
+            scale: Vector3::new(sx, sz, sy),
+        })
+    }
+
+    /// Where something placed `child` relative to this ends up.
+    fn then(&self, child: &Transform) -> Transform {
+        Transform {
+            translation: self.translation
+                + self.rotation * self.scale.component_mul(&child.translation),
+            rotation: self.rotation * child.rotation,
+            scale: self.scale.component_mul(&child.scale),
+        }
+    }
+}
+
+fn property<'a>(extras: &'a Value, name: &str) -> Option<&'a Value> {
+    extras.as_object().and_then(|extras| extras.get(name))
+}
+
+/// The name of the original of something Blender copied,
+/// i.e. "StoneWall" for "StoneWall.004".
+fn without_copy_suffix(name: &str) -> &str {
+    match name.rfind('.') {
+        Some(dot)
+            if dot + 1 < name.len() && name[dot + 1..].chars().all(|c| c.is_ascii_digit()) =>
+        {
+            &name[..dot]
+        }
+        _ => name,
+    }
+}
+
+struct Importer {
+    gltf: Gltf,
+}
+
+impl Importer {
+    fn node(&self, index: usize) -> Result<&Node, String> {
+        self.gltf
+            .nodes
+            .get(index)
+            .ok_or_else(|| format!("there's no node #{}", index))
+    }
+
+    fn mesh(&self, index: usize) -> Result<&Mesh, String> {
+        self.gltf
+            .meshes
+            .get(index)
+            .ok_or_else(|| format!("there's no mesh #{}", index))
+    }
+
+    fn is_collider(node: &Node) -> bool {
+        let name = node.name.to_lowercase();
+        name.starts_with("hitbox")
+            || name.starts_with("collider")
+            || property(&node.extras, "collider").and_then(Value::as_bool) == Some(true)
+    }
+
+    fn entries(&self) -> Result<Vec<MapEntry>, String> {
+        let scene = self
+            .gltf
+            .scenes
+            .get(self.gltf.scene)
+            .ok_or_else(|| format!("there's no scene #{}", self.gltf.scene))?;
+
+        let origin = Transform {
+            translation: Vector3::zeros(),
+            rotation: UnitQuaternion::identity(),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+        };
+        let mut entries = Vec::new();
+        for &index in &scene.nodes {
+            self.add_node(index, &origin, &mut entries)?;
+        }
+        Ok(entries)
+    }
+
+    fn add_node(
+        &self,
+        index: usize,
+        parent: &Transform,
+        entries: &mut Vec<MapEntry>,
+    ) -> Result<(), String> {
+        let node = self.node(index)?;
+        let transform = parent.then(&Transform::of(node)?);
+
+        if let Some(mesh) = node.mesh {
+            let mut entry = self
+                .entry(node, mesh, &transform)
+                .map_err(|e| format!("object {:?}: {}", node.name, e))?;
+
+            for &child in &node.children {
+                let child = self.node(child)?;
+                if Self::is_collider(child) {
+                    let ([w, h, d], [x, y, z]) = self.hitbox(child, &transform.scale)?;
+                    entry.hitbox_dimensions = Some([w, h, d]);
+                    entry.hitbox_offset = Some([x, y, z]);
+                }
+            }
+            entries.push(entry);
+        }
+
+        for &child in &node.children {
+            if !Self::is_collider(self.node(child)?) {
+                self.add_node(child, &transform, entries)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn entry(&self, node: &Node, mesh: usize, transform: &Transform) -> Result<MapEntry, String> {
+        let appearance = match property(&node.extras, "appearance").and_then(Value::as_str) {
+            Some(appearance) => appearance.to_string(),
+            None => without_copy_suffix(&self.mesh(mesh)?.name).to_string(),
+        };
+        if appearance.is_empty() {
+            return Err("its mesh has no name, and it has no appearance property".to_string());
+        }
+
+        let mut classes = Vec::new();
+        if let Some(extras) = node.extras.as_object() {
+            classes.extend(
+                extras
+                    .iter()
+                    .filter(|(name, value)| {
+                        name.as_str() != "collider" && **value == Value::Bool(true)
+                    })
+                    .map(|(name, _)| name.clone()),
+            );
+        }
+        classes.extend(
+            property(&node.extras, "classes")
+                .and_then(Value::as_str)
+                .unwrap_or("")
+                .split(',')
+                .map(str::trim)
+                .filter(|class| !class.is_empty())
+                .map(str::to_string),
+        );
+
+        let t = transform.translation;
+        let mut entry = MapEntry::new(node.name.clone(), classes, [t.x, t.y, t.z], appearance);
+        let q = transform.rotation.quaternion().coords;
+        entry.rotation_quaternion = [q.x, q.y, q.z, q.w];
+        Ok(entry)
+    }
+
+    /// The full size of the hitbox this collider gives its parent,
+    /// and where its center is relative to the parent, before the parent's rotation.
+    fn hitbox(
+        &self,
+        collider: &Node,
+        parent_scale: &Vector3<f32>,
+    ) -> Result<([f32; 3], [f32; 3]), String> {
+        let local = Transform::of(collider)?;
+
+        // a cube-shaped empty of size 1 goes from -1 to 1 along each axis.
+        let (mut min, mut max) = (Vector3::from_element(-1.0), Vector3::from_element(1.0));
+        if let Some(mesh) = collider.mesh {
+            let bounds = self
+                .bounds(mesh)
+                .map_err(|e| format!("collider {:?}: {}", collider.name, e))?;
+            min = bounds.0;
+            max = bounds.1;
+        }
+
+        let scale = parent_scale.component_mul(&local.scale);
+        let size = (max - min).component_mul(&scale);
+        let center = parent_scale.component_mul(&local.translation)
+            + ((max + min) / 2.0).component_mul(&scale);
+        Ok((
+            [size.x.abs(), size.y.abs(), size.z.abs()],
+            [center.x, center.y, center.z],
+        ))
+    }
+
+    /// The corners of the box around every vertex of a mesh, in Blender's axes.
+    fn bounds(&self, mesh: usize) -> Result<(Vector3<f32>, Vector3<f32>), String> {
+        let mut bounds: Option<(Vector3<f32>, Vector3<f32>)> = None;
+        for primitive in &self.mesh(mesh)?.primitives {
+            let accessor = primitive
+                .attributes
+                .get("POSITION")
+                .and_then(|&index| self.gltf.accessors.get(index))
+                .ok_or("its mesh has no vertices")?;
+            let corner = |corner: &Option<Vec<f32>>| -> Result<Vector3<f32>, String> {
+                let corner: [f32; 3] = corner
+                    .as_ref()
+                    .and_then(|c| c.as_slice().try_into().ok())
+                    .ok_or("its mesh's vertices have no bounds")?;
+                Ok(to_blender(corner))
+            };
+            // flipping an axis around swaps which end is the minimum.
+            let (a, b) = (corner(&accessor.min)?, corner(&accessor.max)?);
+            let (min, max) = (a.zip_map(&b, f32::min), a.zip_map(&b, f32::max));
+
+            bounds = Some(match bounds {
+                Some((old_min, old_max)) => (
+                    old_min.zip_map(&min, f32::min),
+                    old_max.zip_map(&max, f32::max),
+                ),
+                None => (min, max),
+            });
+        }
+        bounds.ok_or_else(|| "its mesh has nothing in it".to_string())
+    }
+}
+
+/// The JSON in a .glb file.
+fn glb_json(glb: &[u8]) -> Result<&[u8], String> {
+    let u32_at = |at: usize| -> Option<usize> {
+        let bytes = glb.get(at..at + 4)?;
+        Some(u32::from_le_bytes(bytes.try_into().ok()?) as usize)
+    };
+    if glb.get(0..4) != Some(GLB_MAGIC) {
+        return Err("it isn't a .glb file".to_string());
+    }
+    let length = u32_at(12).ok_or("it ends too early")?;
+    if glb.get(16..20) != Some(JSON_CHUNK) {
+        return Err("its first chunk isn't JSON".to_string());
+    }
+    glb.get(20..20 + length)
+        .ok_or_else(|| "it ends too early".to_string())
+}
+
+/// Where the level in this folder's glTF scene is, if it has one.
+pub fn scene_path(level_dir: &Path) -> Option<PathBuf> {
+    ["map.glb", "map.gltf"]
+        .iter()
+        .map(|name| level_dir.join(name))
+        .find(|path| path.exists())
+}
+
+/// Reads in a glTF scene, either a .glb or a .gltf file,
+/// and turns the objects in it into map entries.
+pub fn read(path: &Path) -> Result<Vec<MapEntry>, String> {
+    let bytes = fs::read(path).map_err(|e| format!("couldn't read {}: {}", path.display(), e))?;
+    let json = if path.extension().map(|e| e == "glb").unwrap_or(false) {
+        glb_json(&bytes).map_err(|e| format!("{} is malformed: {}", path.display(), e))?
+    } else {
+        &bytes
+    };
+    let gltf = serde_json::from_slice(json)
+        .map_err(|e| format!("{} is malformed: {}", path.display(), e))?;
+
+    Importer { gltf }
+        .entries()
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+#[test]
+fn test_gltf_import() {
+    let gltf: Gltf = serde_json::from_str(
+        r#"{
+            "scene": 0,
+            "scenes": [{ "nodes": [0] }],
+            "nodes": [
+                { "name": "Room", "children": [1], "translation": [10, 0, 0] },
+                {
+                    "name": "Dungeon Wall 2.010", "mesh": 0, "children": [2],
+                    "translation": [0, 0.5, -4],
+                    "rotation": [0, 0.7071068, 0, 0.7071068],
+                    "extras": { "door": true, "classes": "locked, boss", "health": 3 }
+                },
+                { "name": "Hitbox", "translation": [0, 0, -0.5], "scale": [2, 1, 0.5] }
+            ],
+            "meshes": [{ "name": "StoneWall.003", "primitives": [] }]
+        }"#,
+    )
+    .unwrap();
+    let mut entries = Importer { gltf }.entries().unwrap();
+    assert_eq!(entries.len(), 1);
+
+    let wall = entries.pop().unwrap();
+    assert_eq!(wall.name, "Dungeon Wall 2.010");
+    assert_eq!(wall.appearance, "StoneWall");
+    assert_eq!(wall.classes, vec!["door", "locked", "boss"]);
+    assert_eq!(wall.location, [10.0, 4.0, 0.5]);
+    // a quarter turn around glTF's Y is a quarter turn around Blender's Z.
+    assert!((wall.rotation() - std::f32::consts::FRAC_PI_2).abs() < 0.001);
+    assert!(!wall.is_tilted());
+    assert_eq!(wall.hitbox_dimensions, Some([4.0, 1.0, 2.0]));
+    assert_eq!(wall.hitbox_offset, Some([0.0, 0.5, 0.0]));
+
+    assert_eq!(without_copy_suffix("Skeleton"), "Skeleton");
+    assert_eq!(without_copy_suffix("Stone.Wall"), "Stone.Wall");
+}
+
+#[test]
+fn test_gltf_mesh_collider() {
+    let gltf: Gltf = serde_json::from_str(
+        r#"{
+            "scenes": [{ "nodes": [0] }],
+            "nodes": [
+                { "name": "Pillar", "mesh": 0, "children": [1] },
+                { "name": "Collider", "mesh": 1 }
+            ],
+            "meshes": [
+                { "name": "Pillar", "primitives": [] },
+                {
+                    "name": "Collider",
+                    "primitives": [
+                        { "attributes": { "POSITION": 0 } },
+                        { "attributes": { "POSITION": 1 } }
+                    ]
+                }
+            ],
+            "accessors": [
+                { "min": [-1, 0, -0.5], "max": [1, 2, 0.5] },
+                { "min": [0, 0, -2], "max": [3, 1, 0] }
+            ]
+        }"#,
+    )
+    .unwrap();
+    let mut entries = Importer { gltf }.entries().unwrap();
+    assert_eq!(entries.len(), 1);
+
+    // the hitbox covers both primitives, after glTF's axes are turned into Blender's.
+    let pillar = entries.pop().unwrap();
+    assert_eq!(pillar.hitbox_dimensions, Some([4.0, 2.5, 2.0]));
+    assert_eq!(pillar.hitbox_offset, Some([1.0, 0.75, 1.0]));
+}
@@ -0,0 +1,232 @@
+//! Lets a level's script respond to things that happen while the level is being played.
+//!
+//! Systems push LevelEvents onto the LevelEvents Resource as things happen, and once
+//! each tick, Level::run_hooks hands each of them to the matching function in the
+//! script, if it has one:
+//!
+//! - `on_tick(tick)`
+//! - `on_entity_death(entity, name, classes)`, where `name` and `classes` come from
+//!    the map entry the entity was made from, or are `None` and `[]`.
+//! - `on_item_pickup(player, item)`
+//! - `spawn_player(player, client_info)`, which returns a list of components to give the
+//!   player rather than actions; see the spawn module.
+//! - `on_player_join(player)`
+//! - `on_player_death(player)`
+//! - `on_trigger_enter(trigger, entity, name, classes)`, `on_trigger_stay(...)` and
+//!   `on_trigger_exit(...)`, where `name` and `classes` are the Trigger's, like above;
+//!   see the triggers module.
+//! - `on_unlock(lock, player, name, classes)`, where `name` and `classes` are the Lock's;
+//!   see the locks module.
+//!
+//! Each of these can return a list of actions (`Spawn`, `Insert`, `Despawn` and `Notice`)
+//! which are carried out once all of the hooks for that tick have run.
+//! Hooks can also look at and change the game directly through the script's `world`;
+//! see the script_world module.
+use super::map::MapOrigin;
+#[cfg(feature = "python")]
+use pyo3::{prelude::*, types::PyDict};
+
+#[derive(Debug, Clone)]
+/// What the level's script is told about a player as they're spawned.
+pub struct ClientInfo {
+    pub address: String,
+    /// Whether they came from another level, keeping the Health and Inventory they had.
+    pub arrived: bool,
+}
+#[cfg(feature = "python")]
+impl IntoPy<PyObject> for ClientInfo {
+    fn into_py(self, py: Python) -> PyObject {
+        let dict = PyDict::new(py);
+        let Self { address, arrived } = self;
+        dict.set_item("address", address)
+            .and_then(|_| dict.set_item("arrived", arrived))
+            .expect("Couldn't fill in the client info.");
+        dict.to_object(py)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum LevelEvent {
+    EntityDeath {
+        ent: u32,
+        /// Death removes the MapOrigin along with the entity, so it's kept here.
+        origin: Option<MapOrigin>,
+    },
+    ItemPickup {
+        player: u32,
+        item: u32,
+    },
+    PlayerJoin {
+        player: u32,
+        info: ClientInfo,
+    },
+    PlayerDeath {
+        player: u32,
+    },
+    TriggerEnter {
+        trigger: u32,
+        ent: u32,
+        origin: Option<MapOrigin>,
+    },
+    TriggerStay {
+        trigger: u32,
+        ent: u32,
+        origin: Option<MapOrigin>,
+    },
+    TriggerExit {
+        trigger: u32,
+        ent: u32,
+        origin: Option<MapOrigin>,
+    },
+    Unlock {
+        lock: u32,
+        player: u32,
+        /// Doors are removed once they're unlocked, so this is kept here like with EntityDeath.
+        origin: Option<MapOrigin>,
+    },
+}
+impl LevelEvent {
+    /// The name of the function in the script that handles this event.
+    pub fn hook_name(&self) -> &'static str {
+        match self {
+            LevelEvent::EntityDeath { .. } => "on_entity_death",
+            LevelEvent::ItemPickup { .. } => "on_item_pickup",
+            LevelEvent::PlayerJoin { .. } => "on_player_join",
+            LevelEvent::PlayerDeath { .. } => "on_player_death",
+            LevelEvent::TriggerEnter { .. } => "on_trigger_enter",
+            LevelEvent::TriggerStay { .. } => "on_trigger_stay",
+            LevelEvent::TriggerExit { .. } => "on_trigger_exit",
+            LevelEvent::Unlock { .. } => "on_unlock",
+        }
+    }
+
+    /// The name of the function in the script that handles this event,
+    /// and the arguments it should be passed.
+    #[cfg(feature = "python")]
+    pub fn hook<'p>(self, py: Python<'p>) -> (&'static str, Vec<PyObject>) {
+        let hook = self.hook_name();
+        let origin_args = |origin: Option<MapOrigin>| {
+            let (name, classes) = match origin {
+                Some(MapOrigin { name, classes }) => (Some(name), classes),
+                None => (None, Vec::new()),
+            };
+            vec![name.into_py(py), classes.into_py(py)]
+        };
+        let trigger_args = |trigger: u32, ent: u32, origin: Option<MapOrigin>| {
+            let mut args = vec![trigger.into_py(py), ent.into_py(py)];
+            args.append(&mut origin_args(origin));
+            args
+        };
+        let args = match self {
+            LevelEvent::EntityDeath { ent, origin } => {
+                let mut args = vec![ent.into_py(py)];
+                args.append(&mut origin_args(origin));
+                args
+            }
+            LevelEvent::ItemPickup { player, item } => vec![player.into_py(py), item.into_py(py)],
+            LevelEvent::PlayerJoin { player, .. } => vec![player.into_py(py)],
+            LevelEvent::PlayerDeath { player } => vec![player.into_py(py)],
+            LevelEvent::TriggerEnter {
+                trigger,
+                ent,
+                origin,
+            }
+            | LevelEvent::TriggerStay {
+                trigger,
+                ent,
+                origin,
+            }
+            | LevelEvent::TriggerExit {
+                trigger,
+                ent,
+                origin,
+            } => trigger_args(trigger, ent, origin),
+            LevelEvent::Unlock {
+                lock,
+                player,
+                origin,
+            } => {
+                let mut args = vec![lock.into_py(py), player.into_py(py)];
+                args.append(&mut origin_args(origin));
+                args
+            }
+        };
+        (hook, args)
+    }
+}
+
+#[derive(Default)]
+/// The LevelEvents that haven't been handed to the level's script yet.
+pub struct LevelEvents(Vec<LevelEvent>);
+impl LevelEvents {
+    #[inline]
+    pub fn push(&mut self, event: LevelEvent) {
+        self.0.push(event);
+    }
+
+    pub fn drain(&mut self) -> Vec<LevelEvent> {
+        std::mem::replace(&mut self.0, Vec::new())
+    }
+}
+
+#[cfg(feature = "python")]
+#[pyclass]
+/// Creates a new entity with these components, and tells the players about it.
+pub struct Spawn {
+    pub components: Vec<PyObject>,
+}
+#[cfg(feature = "python")]
+#[pymethods]
+impl Spawn {
+    #[new]
+    fn new(obj: &PyRawObject, components: Vec<PyObject>) {
+        obj.init(Self { components })
+    }
+}
+
+#[cfg(feature = "python")]
+#[pyclass]
+/// Adds these components to an existing entity, replacing any of the same type,
+/// and tells the players about the changes.
+pub struct Insert {
+    pub entity: u32,
+    pub components: Vec<PyObject>,
+}
+#[cfg(feature = "python")]
+#[pymethods]
+impl Insert {
+    #[new]
+    fn new(obj: &PyRawObject, entity: u32, components: Vec<PyObject>) {
+        obj.init(Self { entity, components })
+    }
+}
+
+#[cfg(feature = "python")]
+#[pyclass]
+/// Removes an entity from the game.
+pub struct Despawn {
+    pub entity: u32,
+}
+#[cfg(feature = "python")]
+#[pymethods]
+impl Despawn {
+    #[new]
+    fn new(obj: &PyRawObject, entity: u32) {
+        obj.init(Self { entity })
+    }
+}
+
+#[cfg(feature = "python")]
+#[pyclass]
+/// Shows every player a message.
+pub struct Notice {
+    pub text: String,
+}
+#[cfg(feature = "python")]
+#[pymethods]
+impl Notice {
+    #[new]
+    fn new(obj: &PyRawObject, text: String) {
+        obj.init(Self { text })
+    }
+}
@@ -1,309 +1,335 @@
+//! A level's script can be written in Python, as `script.py`, or in Rhai, as `script.rhai`.
+//! Whichever of the two the level's folder has is used, preferring Rhai if it has both.
+//!
+//! Either way, the script's `load_map` function is given the entries from the map.json,
+//! and returns a list of entities, which are lists of components, for the server to create.
+//! The components' constructors are named and take arguments the same way in both languages,
+//! but Rhai doesn't have keyword arguments, so they're all given in order.
+//! Both can also make entities from the prefabs in the level's `prefabs.toml` files.
+//!
+//! Each level is given a seed, which Python's `random` module and Rhai's `random_int`
+//! are reset to before load_map is called, so that the same seed gives the same layout.
+//! Every level draws from its own generator, even when several run in the same process.
+//! Scripts can see it as `level_seed` in Python, or `level_seed()` in Rhai.
+//! Likewise, the `[rules]` from the level's level.toml are `rules` in Python,
+//! or `rules()` in Rhai.
+//!
+//! Python scripts can only be run if the server is built with the `python` feature.
+use super::dungeon::DungeonConfig;
+use super::rhai_level::RhaiLevel;
+use super::{gltf, tiled};
+use super::{FromMap, MapEntry, Prefabs, ScriptLimits, ServerConfig};
+use crate::net::{self, prelude::*};
+use comn::item::Inventory;
+use comn::prelude::*;
 use comn::specs::{LazyUpdate, World, WorldExt};
-use specs::{Component, Entity};
-// std
-use std::fmt::Debug;
-use std::marker::PhantomData;
+use specs::{Entity, Join};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use std::{fs::File, io::Read};
-// us
-use super::ServerConfig;
-use crate::combat::Chaser;
-use comn::prelude::*;
-use comn::PyWrapper;
-// script
-use pyo3::prelude::*;
-use pyo3::PyTypeInfo;
 
-trait ComponentFactory {
-    fn try_py_insert<'p>(
-        &self,
-        py: Python<'p>,
-        obj: &PyObject,
-        lu: &LazyUpdate,
-        e: Entity,
-    ) -> Result<(), ()>;
+#[cfg(feature = "python")]
+use super::py_level::PyLevel;
+
+/// A component a level's load_map function returned that the server
+/// doesn't know what to do with.
+pub struct UnregisteredComponent {
+    /// Which of the entities load_map returned it was given for.
+    pub entity: usize,
+    /// Where that entity was, if it had a position.
+    pub location: Option<Vec2>,
+    /// The name of the component's type in the script.
+    pub component: String,
 }
 
-struct ComponentEntry<C: PyTypeInfo + Debug + Component + Clone + Send + Sync> {
-    pd: PhantomData<C>,
-}
-impl<C: PyTypeInfo + Debug + Component + Clone + Send + Sync> ComponentEntry<C> {
-    const INSTANCE: Self = Self { pd: PhantomData };
-}
-impl<C: PyTypeInfo + Component + Debug + Clone + Send + Sync> ComponentFactory
-    for ComponentEntry<C>
-{
-    fn try_py_insert<'p>(
-        &self,
-        py: Python<'p>,
-        obj: &PyObject,
-        lu: &LazyUpdate,
-        e: Entity,
-    ) -> Result<(), ()> {
-        if let Ok(c) = obj.cast_as::<C>(py) {
-            log::trace!("{:?}", c);
-            lu.insert(e, c.clone());
-            Ok(())
-        } else {
-            Err(())
-        }
-    }
-}
-/// For a struct like PyAlignment or PyItem that just wraps a real Component like
-/// Alignment or Item.
-struct PyWrapperComponentEntry<
-    W: PyTypeInfo + Clone + PyWrapper<C>,
-    C: Debug + Component + Send + Sync,
-> {
-    w: PhantomData<W>,
-    c: PhantomData<C>,
-}
-impl<W: PyTypeInfo + Clone + PyWrapper<C>, C: Debug + Component + Send + Sync>
-    PyWrapperComponentEntry<W, C>
-{
-    const INSTANCE: Self = Self {
-        w: PhantomData,
-        c: PhantomData,
-    };
-}
-impl<W: PyTypeInfo + Clone + PyWrapper<C>, C: Debug + Component + Send + Sync> ComponentFactory
-    for PyWrapperComponentEntry<W, C>
-{
-    fn try_py_insert<'p>(
-        &self,
-        py: Python<'p>,
-        obj: &PyObject,
-        lu: &LazyUpdate,
-        e: Entity,
-    ) -> Result<(), ()> {
-        if let Ok(w) = obj.cast_as::<W>(py) {
-            let c = w.clone().into_inner();
-            log::trace!("{:?}", c);
-            lu.insert(e, c);
-            Ok(())
-        } else {
-            Err(())
-        }
-    }
-}
-
-struct ComponentFactoryRegistry(Vec<&'static dyn ComponentFactory>);
-impl ComponentFactoryRegistry {
-    pub fn new() -> Self {
-        Self(Vec::new())
-    }
+/// Kills an entity, and tells the players it's gone.
+pub(super) fn despawn(world: &World, ent: Entity) {
+    world.read_resource::<LazyUpdate>().insert(ent, comn::Dead);
 
-    pub fn register<C: PyTypeInfo + Component + Debug + Clone + Send + Sync>(&mut self) {
-        self.0.push(&ComponentEntry::<C>::INSTANCE);
+    let cm = world.read_resource::<ConnectionManager>();
+    for Client(addr) in world.read_storage::<Client>().join() {
+        cm.insert_comp(*addr, ent, comn::Dead);
     }
+}
 
-    pub fn register_py_wrapper<
-        W: 'static + PyTypeInfo + Clone + PyWrapper<C>,
-        C: Component + Debug + Send + Sync,
-    >(
-        &mut self,
-    ) {
-        self.0.push(&PyWrapperComponentEntry::<W, C>::INSTANCE);
-    }
+fn read_script(script_path: &Path) -> Result<String, String> {
+    let mut script_src = String::new();
+    File::open(script_path)
+        .and_then(|mut f| f.read_to_string(&mut script_src))
+        .map_err(|e| {
+            format!(
+                concat!(
+                    "couldn't read the script file at {}: {} ",
+                    "Perhaps an invalid/nonexistent level name was provided?",
+                ),
+                script_path.display(),
+                e
+            )
+        })?;
+    Ok(script_src)
+}
 
-    pub fn try_py_insert<'p>(
-        &self,
-        py: Python<'p>,
-        obj: PyObject,
-        lu: &LazyUpdate,
-        a: Entity,
-    ) -> Result<(), String> {
-        for cf in self.0.iter() {
-            if let Ok(()) = cf.try_py_insert(py, &obj, lu, a) {
-                return Ok(());
-            }
-        }
-        Err("No such component!".to_string())
-    }
+enum Script {
+    #[cfg(feature = "python")]
+    Python(PyLevel),
+    Rhai(RhaiLevel),
 }
 
 pub struct Level {
     level: String,
-    registry: ComponentFactoryRegistry,
+    /// The folder the level's files are in.
+    dir: PathBuf,
+    script: Script,
+    /// The shared prefabs, along with the level's own.
+    prefabs: Rc<Prefabs>,
+    seed: u64,
+    /// How many times run_hooks has been called.
+    tick: u64,
 }
 
-const BASIC_COMPONENTS: &'static str = r#"
-def basic_components(entry):
-    components = []
-
-    components.append(Pos(Iso2(entry["location"], entry["z_rotation"])))
-
-    components.append(
-       appearance_record.appearance_of(
-           entry["appearance"]
-       )
-    )
-
-    if None != (hb := entry["hitbox_dimensions"]):
-        components.append(Hitbox(hb))
-
-    return components    
-"#;
-
 impl Level {
-    pub fn from_name(level: String) -> Self {
-        // open the script.py file for the level
-        let script_path = format!("./levels/{}/script.py", level);
-        let mut script_src = String::new();
-        File::open(&script_path)
-            .and_then(|mut f| f.read_to_string(&mut script_src))
-            .unwrap_or_else(|e| {
-                panic!(
-                    concat!(
-                        "couldn't read the script.py file at {}: {} ",
-                        "Perhaps an invalid/nonexistent level name was ",
-                        "provided in the hauntfall_server_config.toml?",
-                    ),
-                    script_path, e
-                )
-            });
-
-        // interpret the Python file
-        let gil = Python::acquire_gil();
-        let py = gil.python();
-
-        let script = PyModule::from_code(
-            py,
-            &(script_src + BASIC_COMPONENTS),
-            &format!("{}.py", &level),
-            "level",
-        )
-        .unwrap_or_else(|e| {
-            e.print(py);
-            panic!("Couldn't load Python script for level {}", &level)
-        });
-        script
-            .add("level_name", level.clone())
-            .expect("Couldn't insert level name into module.");
-
-        // register all Rust types
-        let mut registry = ComponentFactoryRegistry::new();
-
-        macro_rules! register_components {
-            ( $( $t:tt , )* ) => {
-                $(
-                    registry.register::<$t>();
-                    script.add_class::<$t>()
-                        .expect(concat!(
-                            "couldn't add class ",
-                            stringify!($t)
-                        ));
-                )*
-            }
-        }
-
-        macro_rules! register_component_wrappers {
-            ( $( $w:tt: $c:tt , )* ) => {
-                $(
-                    registry.register_py_wrapper::<$w, $c>();
-                    script.add_class::<$w>()
-                        .expect(concat!(
-                            "couldn't add class ",
-                            stringify!($w)
-                        ));
-                )*
-            }
-        }
-
-        use crate::combat::alignment::{Alignment, PyAlignment};
-        use comn::art::{Appearance, AppearanceRecord};
-        use comn::combat::{Health, Hurtbox};
-        use comn::controls::{Heading, Speed};
-        use comn::item::PyItem;
-        use comn::{Hitbox, PyIso2};
-
-        script.add_class::<PyIso2>().unwrap();
-        script.add_class::<AppearanceRecord>().unwrap();
+    /// Reads in and interprets the script for the level with this name,
+    /// which should have a folder in `levels_dir`. Its randomness is drawn from `seed`.
+    pub fn from_name(
+        levels_dir: &Path,
+        level: String,
+        limits: &ScriptLimits,
+        seed: u64,
+    ) -> Result<Self, String> {
+        let dir = levels_dir.join(&level);
+        let prefabs = Rc::new(Prefabs::load(levels_dir, &dir)?);
+
+        let rhai_path = dir.join("script.rhai");
+        let script = if rhai_path.exists() {
+            let src = read_script(&rhai_path)?;
+            Script::Rhai(RhaiLevel::load(
+                level.clone(),
+                &rhai_path,
+                src,
+                limits,
+                prefabs.clone(),
+                seed,
+            )?)
+        } else {
+            Self::load_python(&level, &dir, limits, prefabs.clone(), seed)?
+        };
+
+        Ok(Self {
+            level,
+            dir,
+            script,
+            prefabs,
+            seed,
+            tick: 0,
+        })
+    }
 
-        #[rustfmt::skip]
-        register_components!(
-            Appearance,
+    #[cfg(feature = "python")]
+    fn load_python(
+        level: &str,
+        dir: &Path,
+        limits: &ScriptLimits,
+        prefabs: Rc<Prefabs>,
+        seed: u64,
+    ) -> Result<Script, String> {
+        let py_path = dir.join("script.py");
+        let src = read_script(&py_path)?;
+        Ok(Script::Python(PyLevel::load(
+            level.to_string(),
+            &py_path,
+            src,
+            limits,
+            prefabs,
+            seed,
+        )?))
+    }
 
-            Hitbox,
-            Hurtbox,
-            Health,
+    #[cfg(not(feature = "python"))]
+    fn load_python(
+        level: &str,
+        dir: &Path,
+        _: &ScriptLimits,
+        _: Rc<Prefabs>,
+        _: u64,
+    ) -> Result<Script, String> {
+        Err(format!(
+            concat!(
+                "couldn't find a script.rhai file for level {} in {}; ",
+                "the server has to be built with the python feature to run script.py files."
+            ),
+            level,
+            dir.display()
+        ))
+    }
 
-            Chaser,
-            Speed,
-            Heading,
-            Pos,
-        );
+    /// What the level's randomness is drawn from.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
 
-        #[rustfmt::skip]
-        register_component_wrappers!(
-            PyAlignment: Alignment,
-            PyItem: Item,
-        );
+    /// The folder the level's files are in.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
 
-        Self { level, registry }
+    pub fn map_path(&self) -> PathBuf {
+        self.dir.join("map.json")
     }
 
-    #[inline]
-    pub fn load_map(&mut self, world: &mut World, config: &ServerConfig) -> Result<(), String> {
-        // parse the map
-        let map_path = format!("./levels/{}/map.json", self.level);
+    pub fn read_map(&self) -> Result<String, String> {
+        let map_path = self.map_path();
         let mut map_json = String::new();
         File::open(&map_path)
             .and_then(|mut f| f.read_to_string(&mut map_json))
-            .map_err(|e| format!("couldn't read the map.json file at {}: {} ", map_path, e))?;
-
-        // Run the python function
-        let gil = Python::acquire_gil();
-        let py = gil.python();
-
-        // grab the level module
-        let script = PyModule::import(py, "level").expect("Couldn't get level module!");
-
-        // share some of the config with it.
-        script
-            .add("appearance_record", config.appearance_record.clone())
-            .expect("Couldn't insert the appearance_record into the level module!");
-
-        // prepare to create the entities the script tells us about
-        let ents = world.entities();
-        let lu = world.read_resource::<LazyUpdate>();
-
-        // run the script and collect the list of entities (which are lists of components) from it.
-        let output: Vec<Vec<PyObject>> = script
-            .call1("load_map", (map_json,))
             .map_err(|e| {
-                e.print(py);
-                format!("{}.py's load_map function failed.", self.level)
-            })?
-            .extract::<Vec<Vec<PyObject>>>()
-            .map_err(|e| {
-                e.print(py);
                 format!(
-                    concat!(
-                        "{}.py's load_map function gave output in the wrong format, ",
-                        "expected a list of list of components."
-                    ),
-                    self.level
+                    "couldn't read the map.json file at {}: {} ",
+                    map_path.display(),
+                    e
                 )
             })?;
+        Ok(map_json)
+    }
 
-        // loop over each entity's list of components
-        for entity_components in output {
-            // make an index for that entity
-            let ent = ents.create();
+    /// The entries in the level's map, before they're checked.
+    ///
+    /// If the level has a `dungeon.toml`, they're put together out of the rooms in its
+    /// `rooms` folder, going by its seed. Otherwise they're read from its Tiled map.tmj,
+    /// its glTF scene, map.glb or map.gltf, or from its map.json, in that order.
+    pub fn map_entries(&self) -> Result<Vec<MapEntry>, String> {
+        let dungeon_path = self.dir.join("dungeon.toml");
+        let tiled_path = self.dir.join("map.tmj");
+        if dungeon_path.exists() {
+            DungeonConfig::parse(&dungeon_path)?.generate(&self.dir.join("rooms"), self.seed)
+        } else if tiled_path.exists() {
+            tiled::read(&tiled_path)
+        } else if let Some(scene_path) = gltf::scene_path(&self.dir) {
+            gltf::read(&scene_path)
+        } else {
+            MapEntry::parse_all(&self.read_map()?)
+        }
+    }
+
+    /// The names of the classes this level's script knows what to do with, if it says.
+    ///
+    /// Scripts list these in a `CLASSES` variable, so that classes in the map.json
+    /// that aren't in it can be reported as typos.
+    pub fn known_classes(&self) -> Result<Option<Vec<String>>, String> {
+        match &self.script {
+            #[cfg(feature = "python")]
+            Script::Python(script) => script.known_classes(),
+            Script::Rhai(script) => script.known_classes(),
+        }
+    }
+
+    /// Runs the script's load_map function without creating any entities,
+    /// and finds any components in its output that can't be inserted.
+    pub fn unregistered_components(
+        &self,
+        config: &ServerConfig,
+    ) -> Result<Vec<UnregisteredComponent>, String> {
+        let entries = MapEntry::resolve_all(
+            self.map_entries()?,
+            &config.appearance_record,
+            &self.prefabs,
+        )?;
+        match &self.script {
+            #[cfg(feature = "python")]
+            Script::Python(script) => script.unregistered_components(config, entries),
+            Script::Rhai(script) => script.unregistered_components(config, entries),
+        }
+    }
 
-            // add each of the components to it.
-            for comp in entity_components {
-                self.registry
-                    .try_py_insert(py, comp, &lu, ent)
-                    .map_err(|e| {
-                        format!(
-                            "{}.py's load_map function gave invalid output: {}",
-                            self.level, e
-                        )
-                    })?;
+    /// Creates the entities the script's load_map function asks for, returning them.
+    ///
+    /// Every one of them is marked FromMap. If the script fails or gives invalid output,
+    /// none of them are created.
+    ///
+    /// The level's Prefabs are then put into the World, for players to be spawned from.
+    pub fn load_map(
+        &mut self,
+        world: &mut World,
+        config: &ServerConfig,
+    ) -> Result<Vec<Entity>, String> {
+        world.register::<FromMap>();
+        self.prefabs.player(&config.appearance_record)?;
+        let entries = MapEntry::resolve_all(
+            self.map_entries()?,
+            &config.appearance_record,
+            &self.prefabs,
+        )?;
+
+        let spawned = match &mut self.script {
+            #[cfg(feature = "python")]
+            Script::Python(script) => script.load_map(world, config, entries, self.tick)?,
+            Script::Rhai(script) => script.load_map(world, config, entries, self.tick)?,
+        };
+
+        {
+            let lu = world.read_resource::<LazyUpdate>();
+            for &ent in spawned.iter() {
+                lu.insert(ent, FromMap);
             }
         }
+        world.insert((*self.prefabs).clone());
+
+        Ok(spawned)
+    }
 
+    /// Reads the level's script and map back in, and swaps the entities from
+    /// the old map for the ones from the new one, telling the players about it.
+    ///
+    /// The new level has the same seed as the old one.
+    /// If anything goes wrong, the old level is left as it was.
+    pub fn reload(&mut self, world: &mut World, config: &ServerConfig) -> Result<(), String> {
+        let mut new = Level::from_name(
+            &config.levels_dir,
+            self.level.clone(),
+            &config.scripts,
+            self.seed,
+        )?;
+        new.tick = self.tick;
+
+        // items players are carrying came from the map too, but they stay with the players.
+        let old: Vec<Entity> = {
+            let inventories = world.read_storage::<Inventory>();
+            let held: HashSet<u32> = inventories
+                .join()
+                .flat_map(|inventory| inventory.reserved().chain(inventory.loose()))
+                .filter_map(|(_, slot)| *slot)
+                .collect();
+            (&world.entities(), &world.read_storage::<FromMap>())
+                .join()
+                .map(|(ent, _)| ent)
+                .filter(|ent| !held.contains(&ent.id()))
+                .collect()
+        };
+        let spawned = new.load_map(world, config)?;
+
+        for ent in old {
+            despawn(world, ent);
+        }
+        world.maintain();
+        for ent in spawned {
+            net::announce(world, ent);
+        }
+
+        *self = new;
         Ok(())
     }
+
+    /// Hands the LevelEvents that have happened since the last call to the script,
+    /// along with a tick, then carries out the actions it returns.
+    ///
+    /// Should be called once after each time the World is dispatched and maintained.
+    pub fn run_hooks(&mut self, world: &mut World) {
+        let tick = self.tick;
+        self.tick += 1;
+        match &mut self.script {
+            #[cfg(feature = "python")]
+            Script::Python(script) => script.run_hooks(world, tick),
+            Script::Rhai(script) => script.run_hooks(world, tick),
+        }
+    }
 }
@@ -0,0 +1,101 @@
+//! A level can have a `level.toml` next to its script, for settings that would otherwise
+//! have to be changed in `hauntfall_server_config.toml` for every level at once.
+//!
+//! ```toml
+//! name = "The Crypt"
+//! description = "Fight your way down to the boss, and find the key to its door on the way."
+//! party_size = 4
+//!
+//! tick_rate = 30.0
+//! max_players = 4
+//! appearance_record.names = ["Player", "Skeleton", "StoneWall", "StoneDoor"]
+//!
+//! # merged over the `player` prefab, for this level's players only.
+//! [player]
+//! speed = 0.13
+//!
+//! # anything at all, for the level's script to read.
+//! [rules]
+//! boss_notice = "The crypt falls silent."
+//! ```
+//!
+//! Everything is optional. Whatever's left out is taken from the ServerConfig,
+//! except for the `rules`, which scripts see as `rules` in Python or `rules()` in Rhai.
+//! A level with its own `appearance_record` only sends its players those appearances,
+//! so it has to list every one its map, prefabs and script use.
+//!
+//! The file is only read when the level is first loaded, not when it's hot reloaded.
+use super::Prefab;
+use comn::art::AppearanceRecord;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+/// Read in from the `level.toml` in a level's folder.
+pub struct LevelConfig {
+    /// What the level is called, for players to see.
+    pub name: Option<String>,
+    pub description: Option<String>,
+    /// How many players the level is made for.
+    pub party_size: Option<usize>,
+    pub appearance_record: Option<AppearanceRecord>,
+    pub tick_rate: Option<f32>,
+    /// These two only count for the server's main level,
+    /// since every instance lets players in from the same JoinQueue.
+    pub max_players: Option<usize>,
+    pub spectate_while_queued: Option<bool>,
+    /// Merged over the `player` prefab.
+    pub player: Option<Prefab>,
+    #[serde(default)]
+    pub rules: toml::value::Table,
+}
+
+impl LevelConfig {
+    /// Reads in the `level.toml` in this folder, if there is one.
+    pub fn load(level_dir: &Path) -> Result<Self, String> {
+        let path = level_dir.join("level.toml");
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let toml = fs::read_to_string(&path)
+            .map_err(|e| format!("couldn't read {}: {}", path.display(), e))?;
+        toml::from_str(&toml).map_err(|e| format!("{} is malformed: {}", path.display(), e))
+    }
+
+    /// A few words about the level, i.e. for the list of instances.
+    pub fn summary(&self, level: &str) -> String {
+        let mut summary = self.name.clone().unwrap_or_else(|| level.to_string());
+        if let Some(party_size) = self.party_size {
+            summary += &format!(", for {}", party_size);
+        }
+        summary
+    }
+}
+
+#[test]
+fn level_config_parses() {
+    let config: LevelConfig = toml::from_str(
+        r#"
+        name = "The Crypt"
+        party_size = 4
+        tick_rate = 30.0
+        appearance_record.names = ["Player"]
+        [player]
+        speed = 0.13
+        [rules]
+        boss_notice = "The crypt falls silent."
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(config.summary("dungeon"), "The Crypt, for 4");
+    assert_eq!(config.tick_rate, Some(30.0));
+    assert_eq!(config.player.unwrap().speed, Some(0.13));
+    assert_eq!(
+        config.rules["boss_notice"].as_str(),
+        Some("The crypt falls silent.")
+    );
+    assert_eq!(LevelConfig::default().summary("dungeon"), "dungeon");
+}
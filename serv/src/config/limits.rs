@@ -0,0 +1,35 @@
+//! How much a level's script is allowed to do, whichever language it's written in.
+use serde::Deserialize;
+use std::time::Duration;
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+/// Found in the `[scripts]` section of `hauntfall_server_config.toml`.
+pub struct ScriptLimits {
+    /// How long a script may take to load, and then to run its load_map function.
+    pub load_ms: u64,
+    /// How long each hook or timer may run for.
+    /// This should be well under how long a tick is meant to take.
+    pub hook_ms: u64,
+    /// The modules Python scripts are allowed to import; their submodules are allowed as well.
+    /// Rhai scripts can't import anything.
+    pub allowed_imports: Vec<String>,
+}
+impl Default for ScriptLimits {
+    fn default() -> Self {
+        Self {
+            load_ms: 5000,
+            hook_ms: 20,
+            allowed_imports: vec!["json".to_string(), "math".to_string(), "random".to_string()],
+        }
+    }
+}
+impl ScriptLimits {
+    pub fn load(&self) -> Duration {
+        Duration::from_millis(self.load_ms)
+    }
+
+    pub fn hook(&self) -> Duration {
+        Duration::from_millis(self.hook_ms)
+    }
+}
@@ -0,0 +1,354 @@
+use super::{PrefabComponents, Prefabs};
+use comn::art::{Appearance, AppearanceRecord};
+use comn::prelude::*;
+#[cfg(feature = "python")]
+use comn::script::py_component;
+use comn::{Cuboid, Elevation, Hitbox, Orientation};
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+use serde::Deserialize;
+use specs::{prelude::*, Component};
+
+const IDENTITY: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+fn identity() -> [f32; 4] {
+    IDENTITY
+}
+
+/// Anything closer to zero than this is treated as zero,
+/// so that floating point noise from the exporter doesn't produce components.
+const EPSILON: f32 = 0.0001;
+
+#[cfg(feature = "python")]
+#[pyclass]
+#[derive(Debug, Clone, Component)]
+/// Which entry in the map.json an entity was made from,
+/// so that the level's script can tell what it was when i.e. it dies.
+pub struct MapOrigin {
+    #[pyo3(get)]
+    pub name: String,
+    #[pyo3(get)]
+    pub classes: Vec<String>,
+}
+
+#[cfg(not(feature = "python"))]
+#[derive(Debug, Clone, Component)]
+/// Which entry in the map.json an entity was made from,
+/// so that the level's script can tell what it was when i.e. it dies.
+pub struct MapOrigin {
+    pub name: String,
+    pub classes: Vec<String>,
+}
+
+#[derive(Debug, Default, Component)]
+#[storage(NullStorage)]
+/// Marks the entities created while loading the map,
+/// so that they can be swapped out when the level is reloaded.
+pub struct FromMap;
+
+#[cfg_attr(feature = "python", pyclass)]
+#[derive(Deserialize, Clone, Debug)]
+/// A single object in a level, as exported from Blender into that level's map.json file.
+/// A map.json file is simply a list of these.
+///
+/// Before a level's script gets to see the entries, they're checked for mistakes,
+/// so that i.e. a typo in an appearance name is reported with the name of the entry
+/// it's in. The script's `load_map` function is then given the list of them,
+/// and can turn each into the components Rust would by calling `basic_components`.
+/// Those include the components of any prefabs the entry's classes name.
+pub struct MapEntry {
+    /// Blender's name for the object, only used to point out problems with it.
+    pub name: String,
+    /// Used by scripts to give entries special behavior, i.e. "enemy" or "item".
+    #[serde(default)]
+    pub classes: Vec<String>,
+    /// Z is up, and becomes the entity's Elevation.
+    pub location: [f32; 3],
+    /// In `[x, y, z, w]` order. Left alone unless the object is in quaternion rotation mode,
+    /// in which case it takes precedence over `z_rotation`.
+    #[serde(default = "identity")]
+    pub rotation_quaternion: [f32; 4],
+    /// The object's rotation around the Z axis, in radians.
+    #[serde(default)]
+    pub z_rotation: f32,
+    /// Must be one of the names in the appearance_record.
+    pub appearance: String,
+    /// The full width, height and depth of the object's hitbox, if it has one.
+    /// Only the width and height are used.
+    pub hitbox_dimensions: Option<[f32; 3]>,
+    /// Where the center of the hitbox is, relative to the object's location before rotation.
+    pub hitbox_offset: Option<[f32; 3]>,
+    #[serde(skip)]
+    /// Filled in once the appearance is found in the AppearanceRecord.
+    appearance_index: Option<usize>,
+    #[serde(skip)]
+    /// The components of the prefabs named by the entry's classes, if any are,
+    /// filled in along with the appearance.
+    prefab: Option<PrefabComponents>,
+}
+
+impl MapEntry {
+    /// An entry that wasn't read from a map.json, i.e. one made from a Tiled map,
+    /// with no rotation or hitbox until they're set.
+    pub fn new(name: String, classes: Vec<String>, location: [f32; 3], appearance: String) -> Self {
+        Self {
+            name,
+            classes,
+            location,
+            rotation_quaternion: IDENTITY,
+            z_rotation: 0.0,
+            appearance,
+            hitbox_dimensions: None,
+            hitbox_offset: None,
+            appearance_index: None,
+            prefab: None,
+        }
+    }
+
+    /// Parses all of the entries in a map.json file, without checking them.
+    pub fn parse_all(map_json: &str) -> Result<Vec<Self>, String> {
+        serde_json::from_str(map_json).map_err(|e| format!("map.json is malformed: {}", e))
+    }
+
+    /// Makes sure each of these entries would produce valid components.
+    pub fn resolve_all(
+        mut entries: Vec<Self>,
+        record: &AppearanceRecord,
+        prefabs: &Prefabs,
+    ) -> Result<Vec<Self>, String> {
+        for entry in entries.iter_mut() {
+            entry
+                .resolve(record, prefabs)
+                .map_err(|e| format!("map entry {:?} is invalid: {}", entry.name, e))?;
+        }
+        Ok(entries)
+    }
+
+    fn resolve(&mut self, record: &AppearanceRecord, prefabs: &Prefabs) -> Result<(), String> {
+        if !self.has_valid_hitbox() {
+            return Err(format!(
+                "hitbox dimensions {:?} need a positive width and height",
+                self.hitbox_dimensions
+            ));
+        }
+        if self
+            .location
+            .iter()
+            .chain(self.rotation_quaternion.iter())
+            .any(|n| !n.is_finite())
+        {
+            return Err("location and rotation must be finite".to_string());
+        }
+
+        self.appearance_index = Some(record.try_appearance_of(&self.appearance)?.index);
+        self.prefab = match prefabs.for_classes(&self.classes)? {
+            Some(prefab) => Some(prefab.build(record)?),
+            None => None,
+        };
+        Ok(())
+    }
+
+    pub fn has_valid_hitbox(&self) -> bool {
+        match self.hitbox_dimensions {
+            Some([w, h, _]) => w.is_finite() && h.is_finite() && w > 0.0 && h > 0.0,
+            None => true,
+        }
+    }
+
+    pub fn loc(&self) -> Vec2 {
+        Vec2::new(self.location[0], self.location[1])
+    }
+
+    /// The object's rotation around the Z axis, in radians,
+    /// taken from the quaternion if it's been set.
+    pub fn rotation(&self) -> f32 {
+        let [x, y, z, w] = self.rotation_quaternion;
+        if self.rotation_quaternion == IDENTITY {
+            self.z_rotation
+        } else {
+            (2.0 * (w * z + x * y)).atan2(1.0 - 2.0 * (y * y + z * z))
+        }
+    }
+
+    /// Whether the object is rotated around an axis other than Z,
+    /// which can't be represented by its Pos alone.
+    pub fn is_tilted(&self) -> bool {
+        let [x, y, _, _] = self.rotation_quaternion;
+        x.abs() > EPSILON || y.abs() > EPSILON
+    }
+
+    /// The components every entity made from this entry should have.
+    pub fn components(&self) -> Result<BasicComponents, String> {
+        let index = self.appearance_index.ok_or_else(|| {
+            format!(
+                "map entry {:?} hasn't been checked against the appearance record",
+                self.name
+            )
+        })?;
+
+        Ok(BasicComponents {
+            pos: Pos {
+                iso: Iso2::new(self.loc(), self.rotation()),
+            },
+            appearance: Appearance { index },
+            origin: MapOrigin {
+                name: self.name.clone(),
+                classes: self.classes.clone(),
+            },
+            hitbox: self.hitbox(),
+            elevation: if self.location[2].abs() > EPSILON {
+                Some(Elevation {
+                    height: self.location[2],
+                })
+            } else {
+                None
+            },
+            orientation: if self.is_tilted() {
+                Some(Orientation {
+                    quaternion: self.rotation_quaternion.to_vec(),
+                })
+            } else {
+                None
+            },
+            prefab: self.prefab.clone(),
+        })
+    }
+
+    pub fn hitbox(&self) -> Option<Hitbox> {
+        self.hitbox_dimensions.map(|[w, h, _]| Hitbox {
+            cuboid: Cuboid::new(Vec2::new(w, h) / 2.0),
+            offset: self
+                .hitbox_offset
+                .map(|[x, y, _]| Vec2::new(x, y))
+                .unwrap_or_else(na::zero),
+        })
+    }
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl MapEntry {
+    #[getter]
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    #[getter]
+    fn get_classes(&self) -> Vec<String> {
+        self.classes.clone()
+    }
+
+    #[getter]
+    fn get_z_rotation(&self) -> f32 {
+        self.z_rotation
+    }
+
+    #[getter]
+    fn get_appearance(&self) -> String {
+        self.appearance.clone()
+    }
+
+    #[getter]
+    fn get_location(&self) -> Vec<f32> {
+        self.location.to_vec()
+    }
+
+    #[getter]
+    fn get_elevation(&self) -> f32 {
+        self.location[2]
+    }
+
+    #[getter]
+    fn get_rotation(&self) -> f32 {
+        self.rotation()
+    }
+
+    #[getter]
+    fn get_rotation_quaternion(&self) -> Vec<f32> {
+        self.rotation_quaternion.to_vec()
+    }
+
+    #[getter]
+    fn get_hitbox_dimensions(&self) -> Option<Vec<f32>> {
+        self.hitbox_dimensions.map(|d| d.to_vec())
+    }
+
+    #[getter]
+    fn get_hitbox_offset(&self) -> Option<Vec<f32>> {
+        self.hitbox_offset.map(|o| o.to_vec())
+    }
+
+    /// The components every entity made from this entry should have:
+    /// a Pos, an Appearance and a MapOrigin, maybe a Hitbox, Elevation and Orientation,
+    /// and then the components of any prefabs its classes name.
+    fn basic_components(&self, py: Python) -> PyResult<Vec<PyObject>> {
+        let basic = self
+            .components()
+            .map_err(pyo3::exceptions::ValueError::py_err)?;
+
+        let mut components = vec![
+            py_component(py, basic.pos)?,
+            py_component(py, basic.appearance)?,
+            PyObject::from(Py::new(py, basic.origin)?),
+        ];
+        if let Some(hitbox) = basic.hitbox {
+            components.push(py_component(py, hitbox)?);
+        }
+        if let Some(elevation) = basic.elevation {
+            components.push(py_component(py, elevation)?);
+        }
+        if let Some(orientation) = basic.orientation {
+            components.push(Py::new(py, orientation)?.into());
+        }
+        if let Some(prefab) = basic.prefab {
+            components.append(&mut prefab.py_components(py)?);
+        }
+
+        Ok(components)
+    }
+}
+
+/// A Pos, an Appearance and a MapOrigin, maybe a Hitbox, Elevation and Orientation,
+/// and the components of any prefabs the entry's classes name.
+pub struct BasicComponents {
+    pub pos: Pos,
+    pub appearance: Appearance,
+    pub origin: MapOrigin,
+    pub hitbox: Option<Hitbox>,
+    pub elevation: Option<Elevation>,
+    pub orientation: Option<Orientation>,
+    pub prefab: Option<PrefabComponents>,
+}
+
+#[test]
+fn test_map_entry_geometry() {
+    let record = AppearanceRecord {
+        names: vec!["StoneWall".to_string()],
+    };
+    let load_all = |map_json| {
+        MapEntry::resolve_all(MapEntry::parse_all(map_json)?, &record, &Prefabs::default())
+    };
+    let mut entries = load_all(
+        r#"[{
+            "name": "Wall",
+            "location": [4.0, 6.0, 0.5],
+            "rotation_quaternion": [0.0, 0.0, 0.7071068, 0.7071068],
+            "z_rotation": 0.0,
+            "appearance": "StoneWall",
+            "hitbox_dimensions": [4.0, 1.0, 0.0],
+            "hitbox_offset": [0.0, 0.5, 1.0]
+        }]"#,
+    )
+    .unwrap();
+    let wall = entries.pop().unwrap();
+
+    assert!((wall.rotation() - std::f32::consts::FRAC_PI_2).abs() < 0.001);
+    assert!(!wall.is_tilted());
+
+    let hitbox = wall.hitbox().unwrap();
+    assert_eq!(hitbox.cuboid.half_extents(), &Vec2::new(2.0, 0.5));
+    assert_eq!(hitbox.offset, Vec2::new(0.0, 0.5));
+
+    let unknown = r#"[{"name": "Oops", "location": [0, 0, 0], "appearance": "Nope",
+                       "hitbox_dimensions": null, "hitbox_offset": null}]"#;
+    assert!(load_all(unknown).is_err());
+}
@@ -18,6 +18,14 @@
 //!
 //! A map.json file is stored in each level, next to that level's script.
 //! More documentation for map.json can be found on the MapEntry struct.
+//! A level's map can also be made in Tiled and saved as a map.tmj instead,
+//! or read straight from the scene exported from Blender as a map.glb.
+//!
+//! A level can also have a level.toml, whose settings replace the ones in
+//! hauntfall_server_config.toml for that level; see the LevelConfig struct.
+//!
+//! The character classes players pick from are also listed in hauntfall_server_config.toml;
+//! see the classes module.
 //!
 //! # Scripting
 //! Scripts can expose functions that are run in two different situations.
@@ -39,26 +47,190 @@
 //! to navigate by having walls launch projectiles across hallways, forcing the player
 //! to dash across.
 use serde::Deserialize;
+use std::path::{Path, PathBuf};
 
+mod classes;
+pub use classes::CharacterClasses;
+mod dungeon;
+mod gltf;
 mod level;
 pub use level::Level;
+mod level_config;
+pub use level_config::LevelConfig;
+mod map;
+pub use map::{FromMap, MapEntry, MapOrigin};
+mod hooks;
+pub use hooks::{ClientInfo, LevelEvent, LevelEvents};
+mod limits;
+pub use limits::ScriptLimits;
+mod prefab;
+pub use prefab::{Prefab, PrefabComponents, Prefabs};
+mod reload;
+pub use reload::LevelWatcher;
+mod rhai_level;
+mod tiled;
+mod timers;
+
+#[cfg(feature = "python")]
+mod py_level;
+#[cfg(feature = "python")]
+mod sandbox;
+#[cfg(feature = "python")]
+mod script_world;
+mod validate;
+pub use validate::validate;
 
-#[derive(Deserialize)]
-/// Normally parsed in from `hauntfall.serverconfig`.
+#[derive(Deserialize, Clone)]
+/// Normally parsed in from `hauntfall_server_config.toml`.
 pub struct ServerConfig {
     pub appearance_record: comn::art::AppearanceRecord,
     pub level: String,
+    /// The directory in which each level has a folder.
+    #[serde(default = "default_levels_dir")]
+    pub levels_dir: PathBuf,
+    /// Which port clients connect to.
+    #[serde(default = "default_port")]
+    pub port: u16,
+    /// How many times a second the game is updated.
+    #[serde(default = "default_tick_rate")]
+    pub tick_rate: f32,
+    /// Whether to reload the level whenever its files are changed, keeping everyone connected.
+    #[serde(default)]
+    pub hot_reload: bool,
+    /// If this is left out, the admin console isn't started.
+    pub admin: Option<crate::admin::AdminConfig>,
+    #[serde(default)]
+    pub moderation: crate::moderation::ModerationConfig,
+    /// How long level scripts may run for, and what they may import.
+    #[serde(default)]
+    pub scripts: ScriptLimits,
+    /// If this is left out, any number of players can join.
+    /// Otherwise, once this many are playing in all of the instances put together,
+    /// others have to wait in line.
+    pub max_players: Option<usize>,
+    /// How many instances of levels can be running at once, counting the main one.
+    #[serde(default = "default_max_instances")]
+    pub max_instances: usize,
+    /// Whether the players waiting in line get to watch the game in the meantime.
+    #[serde(default)]
+    pub spectate_while_queued: bool,
+    /// What the level's randomness is drawn from, so that a layout can be played again.
+    /// If this is left out, a seed is picked at random and logged.
+    pub seed: Option<u64>,
+    /// The character classes players pick from before they spawn.
+    #[serde(default, rename = "class")]
+    pub classes: CharacterClasses,
+    /// The level.toml of the level this config is for, once it's been merged in.
+    #[serde(skip)]
+    pub level_config: LevelConfig,
+    /// The settings given on the command line, which are put back over each level.toml.
+    #[serde(skip)]
+    pub overrides: Overrides,
+}
+
+#[derive(Clone, Debug, Default)]
+/// Settings given on the command line, which win out over both the config
+/// and every level's level.toml.
+pub struct Overrides {
+    pub tick_rate: Option<f32>,
+    pub max_players: Option<usize>,
+}
+/// The seed the level was generated from, kept in the World so that it can be told to players.
+pub struct LevelSeed(pub u64);
+
+fn default_levels_dir() -> PathBuf {
+    PathBuf::from("./levels")
+}
+fn default_port() -> u16 {
+    3012
+}
+fn default_tick_rate() -> f32 {
+    20.0
+}
+fn default_max_instances() -> usize {
+    4
 }
 impl ServerConfig {
-    pub fn parse() -> Self {
-        let mut config = String::new();
-        std::fs::File::open("./hauntfall_server_config.toml")
-            .and_then(|mut f| {
-                use std::io::Read;
-                f.read_to_string(&mut config)
-            })
-            .expect("couldn't open hauntfall_server_config.toml");
+    /// The seed from the config, or a random one if it doesn't have one.
+    pub fn seed_or_random(&self) -> u64 {
+        self.seed.unwrap_or_else(rand::random)
+    }
 
-        toml::from_str(&config).expect("hauntfall_server_config.toml file isn't proper TOML")
+    /// Puts these settings in place of the config's own, and keeps them
+    /// so that `for_level` puts them back over each level's level.toml.
+    pub fn override_with(&mut self, overrides: Overrides) {
+        if let Some(tick_rate) = overrides.tick_rate {
+            self.tick_rate = tick_rate;
+        }
+        if overrides.max_players.is_some() {
+            self.max_players = overrides.max_players;
+        }
+        self.overrides = overrides;
     }
+
+    /// This config, with the settings in the level.toml of the level with this name
+    /// put in place of its own, except for the ones given on the command line.
+    pub fn for_level(&self, level: &str) -> Result<Self, String> {
+        let level_config = LevelConfig::load(&self.levels_dir.join(level))?;
+        Ok(self.merge(level, level_config))
+    }
+
+    fn merge(&self, level: &str, level_config: LevelConfig) -> Self {
+        let mut config = self.clone();
+        config.level = level.to_string();
+        if let Some(appearance_record) = &level_config.appearance_record {
+            config.appearance_record = appearance_record.clone();
+        }
+        if let Some(tick_rate) = level_config.tick_rate {
+            config.tick_rate = tick_rate;
+        }
+        if level_config.max_players.is_some() {
+            config.max_players = level_config.max_players;
+        }
+        if let Some(spectate) = level_config.spectate_while_queued {
+            config.spectate_while_queued = spectate;
+        }
+        config.level_config = level_config;
+        config.override_with(self.overrides.clone());
+
+        config
+    }
+
+    pub fn parse(path: &Path) -> Result<Self, String> {
+        let config = std::fs::read_to_string(path)
+            .map_err(|e| format!("couldn't open {}: {}", path.display(), e))?;
+
+        toml::from_str(&config)
+            .map_err(|e| format!("{} file isn't proper TOML: {}", path.display(), e))
+    }
+}
+
+#[test]
+fn test_overrides_beat_level_config() {
+    let mut config: ServerConfig = toml::from_str(
+        r#"
+        level = "crypt"
+        appearance_record.names = []
+        tick_rate = 20.0
+        max_players = 8
+        "#,
+    )
+    .unwrap();
+    let level_config = LevelConfig {
+        tick_rate: Some(30.0),
+        max_players: Some(4),
+        ..Default::default()
+    };
+
+    let merged = config.merge("crypt", level_config.clone());
+    assert_eq!(merged.tick_rate, 30.0);
+    assert_eq!(merged.max_players, Some(4));
+
+    config.override_with(Overrides {
+        tick_rate: Some(60.0),
+        max_players: None,
+    });
+    let merged = config.merge("crypt", level_config);
+    assert_eq!(merged.tick_rate, 60.0);
+    assert_eq!(merged.max_players, Some(4));
 }
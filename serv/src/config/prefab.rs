@@ -0,0 +1,517 @@
+//! Prefabs are named sets of components, read in from `prefabs.toml` files,
+//! so that i.e. every skeleton in a level doesn't need its components listed out in the script.
+//!
+//! ```toml
+//! [prefab.enemy]
+//! appearance = "Skeleton"
+//! alignment = "Enemies"
+//! chaser = { target = "Players", distance = 5.0 }
+//! hurtbox = { hp = 1, knockback = 2.7 }
+//! hitbox = { extents = [1.0, 0.7] }
+//! speed = 0.075
+//! health = 4
+//!
+//! [prefab.boss]
+//! extends = "enemy"
+//! hurtbox = { hp = 2, knockback = 2.7 }
+//! speed = 0.095
+//!
+//! [prefab.stairs_down]
+//! stairs = "dungeon"
+//!
+//! [prefab.boss_room]
+//! trigger = { extents = [10.0, 10.0], alignment = "Players" }
+//!
+//! [prefab.boss_door]
+//! lock = { key = "boss", consume = true }
+//!
+//! [prefab.boss_key]
+//! item = "Misc"
+//! key = "boss"
+//! ```
+//!
+//! The `prefabs.toml` in the levels directory is shared by every level, and a level can
+//! have its own next to its script, whose prefabs replace any shared ones with the same name.
+//! The shared file has to have a `player` prefab, which every player is spawned from.
+//! A level's `level.toml` can change its players by merging a `[player]` section over it.
+//!
+//! Entries in a map.json are given the components of every prefab named by one of their
+//! classes, in the order the classes are listed, along with their basic components.
+//! Scripts can also make them with `prefab(name, pos)`, which returns a list of components,
+//! or `spawn_prefab(name, pos)`, which returns a Spawn action. Both take overrides,
+//! i.e. `spawn_prefab("enemy", [3, 4], speed = 0.1)` in Python,
+//! or `spawn_prefab("enemy", [3.0, 4.0], #{ speed: 0.1 })` in Rhai.
+use crate::combat::{alignment::Alignment, Chaser};
+use crate::triggers::Trigger;
+use comn::art::{Appearance, AppearanceRecord};
+use comn::combat::{Health, Hurtbox};
+use comn::controls::Speed;
+use comn::prelude::*;
+use comn::{Cuboid, Elevation, Hitbox, Key, Lock, NetComponent, Stairs};
+use serde::Deserialize;
+use specs::{Entity, LazyUpdate};
+use std::collections::HashMap;
+use std::path::Path;
+// scripting
+#[cfg(feature = "python")]
+use super::hooks::Spawn;
+#[cfg(feature = "python")]
+use comn::{script::py_component, ScriptField};
+#[cfg(feature = "python")]
+use pyo3::{
+    exceptions::ValueError,
+    prelude::*,
+    types::{PyAny, PyDict},
+};
+#[cfg(feature = "python")]
+use std::{cell::RefCell, rc::Rc};
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ChaserData {
+    pub target: Alignment,
+    pub distance: f32,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct HurtboxData {
+    pub hp: u32,
+    pub knockback: f32,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct HitboxData {
+    /// The full width and height of the hitbox.
+    pub extents: [f32; 2],
+    /// Where the center of the hitbox is, relative to the entity.
+    #[serde(default)]
+    pub offset: [f32; 2],
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct TriggerData {
+    /// The full width and height of the area.
+    pub extents: [f32; 2],
+    /// Who sets it off; anyone with an Alignment if this is left out.
+    pub alignment: Option<Alignment>,
+    /// Only entities made from a map entry with this class set it off, if it's given.
+    #[serde(default)]
+    pub class: String,
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+/// A named set of components, as written in a prefabs.toml file.
+/// Every field is optional, and the components left out aren't given.
+pub struct Prefab {
+    /// The name of another prefab whose components this one starts with.
+    pub extends: Option<String>,
+    /// Must be one of the names in the appearance_record.
+    pub appearance: Option<String>,
+    pub alignment: Option<Alignment>,
+    pub chaser: Option<ChaserData>,
+    pub hurtbox: Option<HurtboxData>,
+    pub hitbox: Option<HitboxData>,
+    pub speed: Option<f32>,
+    /// Entities start out with full Health.
+    pub health: Option<u32>,
+    pub elevation: Option<f32>,
+    pub item: Option<Item>,
+    /// The name of the level these stairs lead to.
+    pub stairs: Option<String>,
+    pub trigger: Option<TriggerData>,
+    pub lock: Option<Lock>,
+    /// The id of the Locks this key opens.
+    pub key: Option<String>,
+}
+
+/// Hitboxes and Triggers can't be empty, or inside out.
+fn check_extents(what: &str, [w, h]: [f32; 2]) -> Result<(), String> {
+    if !(w.is_finite() && h.is_finite() && w > 0.0 && h > 0.0) {
+        return Err(format!(
+            "{} extents {:?} need a positive width and height",
+            what,
+            [w, h]
+        ));
+    }
+    Ok(())
+}
+
+impl Prefab {
+    /// Replaces the components in this prefab with any that `over` has.
+    pub fn merge(&mut self, over: &Prefab) {
+        macro_rules! merge {
+            ( $( $field:ident ),* $(,)? ) => {
+                $(
+                    if over.$field.is_some() {
+                        self.$field = over.$field.clone();
+                    }
+                )*
+            }
+        }
+        merge!(
+            appearance, alignment, chaser, hurtbox, hitbox, speed, health, elevation, item, stairs,
+            trigger, lock, key,
+        );
+    }
+
+    /// Turns this prefab into the components it describes.
+    pub fn build(&self, record: &AppearanceRecord) -> Result<PrefabComponents, String> {
+        if let Some(hitbox) = &self.hitbox {
+            check_extents("hitbox", hitbox.extents)?;
+        }
+        if let Some(trigger) = &self.trigger {
+            check_extents("trigger", trigger.extents)?;
+        }
+
+        Ok(PrefabComponents {
+            appearance: match &self.appearance {
+                Some(name) => Some(record.try_appearance_of(name)?),
+                None => None,
+            },
+            alignment: self.alignment,
+            chaser: self
+                .chaser
+                .as_ref()
+                .map(|c| Chaser::chasing(c.target, c.distance)),
+            hurtbox: self
+                .hurtbox
+                .as_ref()
+                .map(|h| Hurtbox::new(h.hp, h.knockback)),
+            hitbox: self.hitbox.as_ref().map(|h| Hitbox {
+                cuboid: Cuboid::new(Vec2::new(h.extents[0], h.extents[1]) / 2.0),
+                offset: Vec2::new(h.offset[0], h.offset[1]),
+            }),
+            speed: self.speed.map(|speed| Speed { speed }),
+            health: self.health.map(Health::full),
+            elevation: self.elevation.map(|height| Elevation { height }),
+            item: self.item.clone(),
+            stairs: self.stairs.clone().map(|to| Stairs { to }),
+            trigger: self.trigger.as_ref().map(|t| Trigger {
+                cuboid: Cuboid::new(Vec2::new(t.extents[0], t.extents[1]) / 2.0),
+                alignment: t.alignment.unwrap_or(Alignment::All),
+                class: t.class.clone(),
+            }),
+            lock: self.lock.clone(),
+            key: self.key.clone().map(|id| Key { id }),
+        })
+    }
+}
+
+/// The components a Prefab describes.
+#[derive(Clone, Debug, Default)]
+pub struct PrefabComponents {
+    pub appearance: Option<Appearance>,
+    pub alignment: Option<Alignment>,
+    pub chaser: Option<Chaser>,
+    pub hurtbox: Option<Hurtbox>,
+    pub hitbox: Option<Hitbox>,
+    pub speed: Option<Speed>,
+    pub health: Option<Health>,
+    pub elevation: Option<Elevation>,
+    pub item: Option<Item>,
+    pub stairs: Option<Stairs>,
+    pub trigger: Option<Trigger>,
+    pub lock: Option<Lock>,
+    pub key: Option<Key>,
+}
+
+impl PrefabComponents {
+    pub fn insert(self, ent: Entity, lu: &LazyUpdate) {
+        macro_rules! insert {
+            ( $( $field:ident ),* $(,)? ) => {
+                $(
+                    if let Some(c) = self.$field {
+                        lu.insert(ent, c);
+                    }
+                )*
+            }
+        }
+        insert!(
+            appearance, alignment, chaser, hurtbox, hitbox, speed, health, elevation, item, stairs,
+            trigger, lock, key,
+        );
+    }
+
+    /// The components that clients need to be told about.
+    pub fn net_components(&self) -> Vec<NetComponent> {
+        let mut comps: Vec<NetComponent> = Vec::new();
+        comps.extend(self.appearance.clone().map(Into::into));
+        comps.extend(self.hitbox.clone().map(Into::into));
+        comps.extend(self.speed.clone().map(Into::into));
+        comps.extend(self.elevation.clone().map(Into::into));
+        comps.extend(self.item.clone().map(Into::into));
+        comps.extend(self.stairs.clone().map(Into::into));
+        comps.extend(self.lock.clone().map(Into::into));
+        comps
+    }
+
+    #[cfg(feature = "python")]
+    pub fn py_components(self, py: Python) -> PyResult<Vec<PyObject>> {
+        let mut comps = Vec::new();
+        macro_rules! push {
+            ( $( $field:ident ),* $(,)? ) => {
+                $(
+                    if let Some(c) = self.$field {
+                        comps.push(py_component(py, c)?);
+                    }
+                )*
+            }
+        }
+        push!(
+            appearance, alignment, hurtbox, hitbox, speed, health, elevation, item, stairs,
+            trigger, lock, key,
+        );
+        if let Some(chaser) = self.chaser {
+            comps.push(Py::new(py, chaser)?.into());
+        }
+        Ok(comps)
+    }
+
+    pub fn rhai_components(self) -> Vec<rhai::Dynamic> {
+        let mut comps = Vec::new();
+        macro_rules! push {
+            ( $( $field:ident ),* $(,)? ) => {
+                $( comps.extend(self.$field.map(rhai::Dynamic::from)); )*
+            }
+        }
+        push!(
+            appearance, alignment, chaser, hurtbox, hitbox, speed, health, elevation, item, stairs,
+            trigger, lock, key,
+        );
+        comps
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct PrefabFile {
+    #[serde(default)]
+    prefab: HashMap<String, Prefab>,
+}
+
+fn read_prefabs(path: &Path) -> Result<HashMap<String, Prefab>, String> {
+    if !path.exists() {
+        return Ok(HashMap::new());
+    }
+    let toml = std::fs::read_to_string(path)
+        .map_err(|e| format!("couldn't read {}: {}", path.display(), e))?;
+    let file: PrefabFile =
+        toml::from_str(&toml).map_err(|e| format!("{} is malformed: {}", path.display(), e))?;
+    Ok(file.prefab)
+}
+
+/// Every prefab a level can use.
+#[derive(Clone, Debug, Default)]
+pub struct Prefabs {
+    prefabs: HashMap<String, Prefab>,
+}
+
+impl Prefabs {
+    /// Reads in the prefabs.toml shared by every level, then the level's own,
+    /// then the `[player]` from its level.toml. Any of them can be left out.
+    pub fn load(levels_dir: &Path, level_dir: &Path) -> Result<Self, String> {
+        let mut prefabs = read_prefabs(&levels_dir.join("prefabs.toml"))?;
+        prefabs.extend(read_prefabs(&level_dir.join("prefabs.toml"))?);
+        if let Some(over) = super::LevelConfig::load(level_dir)?.player {
+            let player = prefabs.entry("player".to_string()).or_default();
+            if over.extends.is_some() {
+                player.extends = over.extends.clone();
+            }
+            player.merge(&over);
+        }
+        let prefabs = Self { prefabs };
+
+        // so that a missing or circular `extends` is caught now, rather than when it's used.
+        for name in prefabs.prefabs.keys() {
+            prefabs.get(name)?;
+        }
+
+        Ok(prefabs)
+    }
+
+    /// The names of the appearances these prefabs give.
+    pub fn appearances(&self) -> impl Iterator<Item = &str> {
+        self.prefabs
+            .values()
+            .filter_map(|p| p.appearance.as_ref().map(String::as_str))
+    }
+
+    /// The name of each prefab with stairs, along with the level they lead to.
+    pub fn stairs(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.prefabs
+            .iter()
+            .filter_map(|(name, p)| Some((name.as_str(), p.stairs.as_ref()?.as_str())))
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.prefabs.contains_key(name)
+    }
+
+    /// The prefab with this name, along with everything it extends.
+    pub fn get(&self, name: &str) -> Result<Prefab, String> {
+        let mut chain = vec![name];
+        let mut prefab = self.find(name)?;
+        while let Some(base) = &prefab.extends {
+            if chain.contains(&base.as_str()) {
+                chain.push(base.as_str());
+                return Err(format!("prefabs extend each other in a loop: {:?}", chain));
+            }
+            chain.push(base.as_str());
+            prefab = self.find(base)?;
+        }
+
+        let mut merged = Prefab::default();
+        for name in chain.into_iter().rev() {
+            merged.merge(self.find(name)?);
+        }
+        Ok(merged)
+    }
+
+    fn find(&self, name: &str) -> Result<&Prefab, String> {
+        self.prefabs.get(name).ok_or_else(|| {
+            let mut names: Vec<_> = self.prefabs.keys().collect();
+            names.sort();
+            format!(
+                "there's no prefab named {:?}, expected one of: {:?}",
+                name, names
+            )
+        })
+    }
+
+    /// The prefabs named by these classes merged together, in order,
+    /// or None if none of them name a prefab.
+    pub fn for_classes(&self, classes: &[String]) -> Result<Option<Prefab>, String> {
+        let mut merged = None;
+        for class in classes.iter().filter(|c| self.contains(c)) {
+            merged
+                .get_or_insert_with(Prefab::default)
+                .merge(&self.get(class)?);
+        }
+        Ok(merged)
+    }
+
+    /// The components of the prefab with this name, with `overrides` replacing any it has.
+    pub fn build(
+        &self,
+        name: &str,
+        overrides: Option<&Prefab>,
+        record: &AppearanceRecord,
+    ) -> Result<PrefabComponents, String> {
+        let mut prefab = self.get(name)?;
+        if let Some(overrides) = overrides {
+            if overrides.extends.is_some() {
+                return Err("a prefab's overrides can't extend another prefab".to_string());
+            }
+            prefab.merge(overrides);
+        }
+        prefab
+            .build(record)
+            .map_err(|e| format!("prefab {:?} is invalid: {}", name, e))
+    }
+
+    /// The components every player is spawned with.
+    pub fn player(&self, record: &AppearanceRecord) -> Result<PrefabComponents, String> {
+        self.build("player", None, record)
+    }
+}
+
+#[cfg(feature = "python")]
+#[pyclass]
+/// Owns the script's `prefab` and `spawn_prefab` functions.
+pub struct PrefabSpawner {
+    prefabs: Rc<Prefabs>,
+    /// Set from the ServerConfig before load_map is called.
+    appearance_record: Rc<RefCell<AppearanceRecord>>,
+}
+#[cfg(feature = "python")]
+impl PrefabSpawner {
+    pub fn new(prefabs: Rc<Prefabs>, appearance_record: Rc<RefCell<AppearanceRecord>>) -> Self {
+        Self {
+            prefabs,
+            appearance_record,
+        }
+    }
+}
+#[cfg(feature = "python")]
+#[pymethods]
+impl PrefabSpawner {
+    /// The components of the prefab with this name, at `pos`.
+    /// Any keyword arguments replace the prefab's components, like they'd be written in TOML.
+    #[args(overrides = "**")]
+    fn prefab(
+        &self,
+        py: Python,
+        name: &str,
+        pos: &PyAny,
+        overrides: Option<&PyDict>,
+    ) -> PyResult<Vec<PyObject>> {
+        let overrides = match overrides {
+            Some(dict) => {
+                let json: String = py.import("json")?.call1("dumps", (dict,))?.extract()?;
+                Some(serde_json::from_str::<Prefab>(&json).map_err(|e| {
+                    ValueError::py_err(format!("invalid overrides for prefab {:?}: {}", name, e))
+                })?)
+            }
+            None => None,
+        };
+        let built = self
+            .prefabs
+            .build(name, overrides.as_ref(), &self.appearance_record.borrow())
+            .map_err(ValueError::py_err)?;
+
+        let pos = <Vec2 as ScriptField>::from_py(pos)?;
+        let mut comps = vec![py_component(py, Pos::vec(pos))?];
+        comps.append(&mut built.py_components(py)?);
+        Ok(comps)
+    }
+
+    /// A Spawn action for the prefab with this name, at `pos`.
+    #[args(overrides = "**")]
+    fn spawn_prefab(
+        &self,
+        py: Python,
+        name: &str,
+        pos: &PyAny,
+        overrides: Option<&PyDict>,
+    ) -> PyResult<Py<Spawn>> {
+        let components = self.prefab(py, name, pos, overrides)?;
+        Py::new(py, Spawn { components })
+    }
+}
+
+#[test]
+fn test_prefabs_extend_and_merge() {
+    let prefabs = Prefabs {
+        prefabs: toml::from_str::<PrefabFile>(
+            r#"
+            [prefab.enemy]
+            alignment = "Enemies"
+            speed = 0.075
+            health = 4
+
+            [prefab.boss]
+            extends = "enemy"
+            speed = 0.095
+
+            [prefab.loop]
+            extends = "loop"
+            "#,
+        )
+        .unwrap()
+        .prefab,
+    };
+
+    let boss = prefabs.get("boss").unwrap();
+    assert_eq!(boss.speed, Some(0.095));
+    assert_eq!(boss.health, Some(4));
+    assert!(prefabs.get("loop").is_err());
+
+    let classes = vec!["enemy".to_string(), "boss".to_string(), "other".to_string()];
+    let merged = prefabs.for_classes(&classes).unwrap().unwrap();
+    assert_eq!(merged.speed, Some(0.095));
+    assert!(prefabs.for_classes(&classes[2..]).unwrap().is_none());
+}
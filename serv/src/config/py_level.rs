@@ -0,0 +1,804 @@
+use comn::specs::{storage::MaskedStorage, LazyUpdate, World, WorldExt};
+use specs::{Component, Entity};
+// std
+use std::cell::RefCell;
+use std::fmt::Debug;
+use std::marker::PhantomData;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::Duration;
+// us
+use super::hooks::{ClientInfo, Despawn, Insert, LevelEvent, LevelEvents, Notice, Spawn};
+use super::level::UnregisteredComponent;
+use super::prefab::PrefabSpawner;
+use super::sandbox::Sandbox;
+use super::script_world::ScriptWorld;
+use super::timers::{Scheduler, TimerHandle, TimerQueue};
+use super::{MapEntry, MapOrigin, Prefabs, ScriptLimits, ServerConfig};
+use crate::combat::Chaser;
+use crate::net::{self, prelude::*};
+use comn::art::AppearanceRecord;
+use comn::prelude::*;
+use comn::{PyPos, PyWrapper, Scriptable};
+// script
+use pyo3::prelude::*;
+use pyo3::type_object::PyTypeCreate;
+use pyo3::types::PyAny;
+
+trait ComponentFactory {
+    fn try_py_insert<'p>(
+        &self,
+        py: Python<'p>,
+        obj: &PyObject,
+        lu: &LazyUpdate,
+        e: Entity,
+    ) -> Result<(), ()>;
+
+    /// Whether or not `try_py_insert` would succeed with this object.
+    fn accepts<'p>(&self, py: Python<'p>, obj: &PyObject) -> bool;
+
+    /// The name of the Python class for this component.
+    fn name(&self) -> &'static str;
+
+    /// A copy of this entity's component, as a Python object, if it has one.
+    fn py_get<'p>(&self, py: Python<'p>, world: &World, e: Entity) -> PyResult<Option<PyObject>>;
+}
+
+struct ComponentEntry<C: PyTypeCreate + Debug + Component + Clone + Send + Sync> {
+    pd: PhantomData<C>,
+}
+impl<C: PyTypeCreate + Debug + Component + Clone + Send + Sync> ComponentEntry<C> {
+    const INSTANCE: Self = Self { pd: PhantomData };
+}
+impl<C: PyTypeCreate + Component + Debug + Clone + Send + Sync> ComponentFactory
+    for ComponentEntry<C>
+{
+    fn try_py_insert<'p>(
+        &self,
+        py: Python<'p>,
+        obj: &PyObject,
+        lu: &LazyUpdate,
+        e: Entity,
+    ) -> Result<(), ()> {
+        if let Ok(c) = obj.cast_as::<C>(py) {
+            log::trace!("{:?}", c);
+            lu.insert(e, c.clone());
+            Ok(())
+        } else {
+            Err(())
+        }
+    }
+
+    fn accepts<'p>(&self, py: Python<'p>, obj: &PyObject) -> bool {
+        obj.cast_as::<C>(py).is_ok()
+    }
+
+    fn name(&self) -> &'static str {
+        C::NAME
+    }
+
+    fn py_get<'p>(&self, py: Python<'p>, world: &World, e: Entity) -> PyResult<Option<PyObject>> {
+        // if the storage was never registered, no entity can have one.
+        if !world.has_value::<MaskedStorage<C>>() {
+            return Ok(None);
+        }
+        match world.read_storage::<C>().get(e) {
+            Some(c) => Ok(Some(Py::new(py, c.clone())?.into())),
+            None => Ok(None),
+        }
+    }
+}
+/// For a struct like PyAlignment or PyItem that just wraps a real Component like
+/// Alignment or Item.
+struct PyWrapperComponentEntry<
+    W: PyTypeCreate + Clone + PyWrapper<C>,
+    C: Debug + Component + Clone + Send + Sync,
+> {
+    w: PhantomData<W>,
+    c: PhantomData<C>,
+}
+impl<W: PyTypeCreate + Clone + PyWrapper<C>, C: Debug + Component + Clone + Send + Sync>
+    PyWrapperComponentEntry<W, C>
+{
+    const INSTANCE: Self = Self {
+        w: PhantomData,
+        c: PhantomData,
+    };
+}
+impl<W: PyTypeCreate + Clone + PyWrapper<C>, C: Debug + Component + Clone + Send + Sync>
+    ComponentFactory for PyWrapperComponentEntry<W, C>
+{
+    fn try_py_insert<'p>(
+        &self,
+        py: Python<'p>,
+        obj: &PyObject,
+        lu: &LazyUpdate,
+        e: Entity,
+    ) -> Result<(), ()> {
+        if let Ok(w) = obj.cast_as::<W>(py) {
+            let c = w.clone().into_inner();
+            log::trace!("{:?}", c);
+            lu.insert(e, c);
+            Ok(())
+        } else {
+            Err(())
+        }
+    }
+
+    fn accepts<'p>(&self, py: Python<'p>, obj: &PyObject) -> bool {
+        obj.cast_as::<W>(py).is_ok()
+    }
+
+    fn name(&self) -> &'static str {
+        W::NAME
+    }
+
+    fn py_get<'p>(&self, py: Python<'p>, world: &World, e: Entity) -> PyResult<Option<PyObject>> {
+        if !world.has_value::<MaskedStorage<C>>() {
+            return Ok(None);
+        }
+        match world.read_storage::<C>().get(e) {
+            Some(c) => Ok(Some(Py::new(py, W::from_inner(c.clone()))?.into())),
+            None => Ok(None),
+        }
+    }
+}
+
+pub(super) struct ComponentFactoryRegistry(Vec<&'static dyn ComponentFactory>);
+impl ComponentFactoryRegistry {
+    pub fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    pub fn register<C: PyTypeCreate + Component + Debug + Clone + Send + Sync>(&mut self) {
+        self.0.push(&ComponentEntry::<C>::INSTANCE);
+    }
+
+    /// Registers a component that derives Scriptable, through its Python class.
+    pub fn register_scriptable<C: Scriptable>(&mut self) {
+        self.register_py_wrapper::<C::Py, C>();
+    }
+
+    pub fn register_py_wrapper<
+        W: 'static + PyTypeCreate + Clone + PyWrapper<C>,
+        C: Component + Debug + Clone + Send + Sync,
+    >(
+        &mut self,
+    ) {
+        self.0.push(&PyWrapperComponentEntry::<W, C>::INSTANCE);
+    }
+
+    pub fn try_py_insert<'p>(
+        &self,
+        py: Python<'p>,
+        obj: PyObject,
+        lu: &LazyUpdate,
+        a: Entity,
+    ) -> Result<(), String> {
+        for cf in self.0.iter() {
+            if let Ok(()) = cf.try_py_insert(py, &obj, lu, a) {
+                return Ok(());
+            }
+        }
+        Err(format!("No such component as {}!", type_name(py, &obj)))
+    }
+
+    pub fn accepts<'p>(&self, py: Python<'p>, obj: &PyObject) -> bool {
+        self.0.iter().any(|cf| cf.accepts(py, obj))
+    }
+
+    /// A copy of the component with this Python class name the entity has, if it has one.
+    pub fn py_get<'p>(
+        &self,
+        py: Python<'p>,
+        world: &World,
+        e: Entity,
+        name: &str,
+    ) -> Result<Option<PyObject>, String> {
+        let cf = self
+            .0
+            .iter()
+            .find(|cf| cf.name() == name)
+            .ok_or_else(|| format!("No such component as {}!", name))?;
+        cf.py_get(py, world, e).map_err(|e| {
+            e.print(py);
+            format!("couldn't give a copy of the {} to Python", name)
+        })
+    }
+
+    /// Inserts each of these components into the entity.
+    pub fn insert_all<'p>(
+        &self,
+        py: Python<'p>,
+        components: &[PyObject],
+        lu: &LazyUpdate,
+        ent: Entity,
+    ) -> Result<(), String> {
+        for comp in components {
+            self.try_py_insert(py, comp.clone_ref(py), lu, ent)?;
+        }
+        Ok(())
+    }
+}
+
+/// The name of the Python class this object is an instance of.
+fn type_name<'p>(py: Python<'p>, obj: &PyObject) -> String {
+    obj.as_ref(py).get_type().name().into_owned()
+}
+
+/// Where the entity made from these components will be, if they include a position.
+fn location_of<'p>(py: Python<'p>, comps: &[PyObject]) -> Option<Vec2> {
+    comps
+        .iter()
+        .find_map(|comp| comp.cast_as::<PyPos>(py).ok())
+        .map(|pos| pos.inner.iso.translation.vector)
+}
+
+/// Describes an entity from the output of a load_map function, for error messages.
+fn describe_entity<'p>(py: Python<'p>, index: usize, comps: &[PyObject]) -> String {
+    match location_of(py, comps) {
+        Some(loc) => format!("entity #{} (at {:.2}, {:.2})", index, loc.x, loc.y),
+        None => format!("entity #{}", index),
+    }
+}
+
+/// A level whose script is written in Python.
+pub struct PyLevel {
+    level: String,
+    registry: Rc<ComponentFactoryRegistry>,
+    /// The module the script was loaded into.
+    module: PyObject,
+    /// The script's `world`.
+    world: Py<ScriptWorld>,
+    /// Everything the script has scheduled with `after` and `every`.
+    timers: Rc<RefCell<TimerQueue<PyObject>>>,
+    /// The appearances prefabs can have, set from the ServerConfig before load_map is called.
+    appearance_record: Rc<RefCell<AppearanceRecord>>,
+    /// What the level's random generator is seeded with before load_map is called.
+    seed: u64,
+    /// The level's own `random.Random`, whose state Python's `random` module
+    /// has while any of the script runs; see `PyLevel::run`.
+    random: PyObject,
+    /// Everything the script runs goes through this.
+    sandbox: Sandbox,
+}
+
+const BASIC_COMPONENTS: &'static str = r#"
+def basic_components(entry):
+    return entry.basic_components()
+"#;
+
+impl PyLevel {
+    /// Interprets the source of the script for the level with this name.
+    pub fn load(
+        level: String,
+        script_path: &Path,
+        script_src: String,
+        limits: &ScriptLimits,
+        prefabs: Rc<Prefabs>,
+        seed: u64,
+    ) -> Result<Self, String> {
+        // interpret the Python file
+        let gil = Python::acquire_gil();
+        let py = gil.python();
+
+        let sandbox = Sandbox::new(script_path.to_path_buf(), limits.clone());
+        let script = sandbox
+            .load_module(py, "level", &(script_src + BASIC_COMPONENTS))
+            .map_err(|e| {
+                e.print(py);
+                format!(
+                    "Couldn't load Python script for level {} at {}",
+                    &level,
+                    script_path.display()
+                )
+            })?;
+        script
+            .add("level_name", level.clone())
+            .expect("Couldn't insert level name into module.");
+        script
+            .add("level_seed", seed)
+            .expect("Couldn't insert level seed into module.");
+        let random = py
+            .import("random")
+            .and_then(|random| random.call1("Random", (seed,)))
+            .map_err(|e| {
+                e.print(py);
+                format!("couldn't make level {} a random generator", level)
+            })?
+            .to_object(py);
+
+        // register all Rust types
+        let mut registry = ComponentFactoryRegistry::new();
+
+        macro_rules! register_components {
+            ( $( $t:tt , )* ) => {
+                $(
+                    registry.register::<$t>();
+                    script.add_class::<$t>()
+                        .expect(concat!(
+                            "couldn't add class ",
+                            stringify!($t)
+                        ));
+                )*
+            }
+        }
+
+        macro_rules! register_scriptable {
+            ( $( $t:tt , )* ) => {
+                $(
+                    registry.register_scriptable::<$t>();
+                    script.add_class::<<$t as Scriptable>::Py>()
+                        .expect(concat!(
+                            "couldn't add class ",
+                            stringify!($t)
+                        ));
+                )*
+            }
+        }
+
+        use crate::combat::alignment::Alignment;
+        use crate::triggers::Trigger;
+        use comn::art::Appearance;
+        use comn::combat::{Health, Hurtbox};
+        use comn::controls::{Heading, Speed};
+        use comn::{Elevation, Hitbox, Key, Lock, Orientation, PyIso2, Stairs};
+
+        script.add_class::<PyIso2>().unwrap();
+        script.add_class::<AppearanceRecord>().unwrap();
+        script.add_class::<MapEntry>().unwrap();
+        script.add_class::<Spawn>().unwrap();
+        script.add_class::<Insert>().unwrap();
+        script.add_class::<Despawn>().unwrap();
+        script.add_class::<Notice>().unwrap();
+        script.add_class::<ScriptWorld>().unwrap();
+        script.add_class::<Scheduler>().unwrap();
+        script.add_class::<TimerHandle>().unwrap();
+        script.add_class::<PrefabSpawner>().unwrap();
+
+        #[rustfmt::skip]
+        register_scriptable!(
+            Appearance,
+
+            Hitbox,
+            Hurtbox,
+            Health,
+
+            Elevation,
+
+            Speed,
+            Heading,
+            Pos,
+
+            Alignment,
+            Item,
+            Stairs,
+            Trigger,
+            Lock,
+            Key,
+        );
+
+        #[rustfmt::skip]
+        register_components!(
+            Orientation,
+            MapOrigin,
+
+            Chaser,
+        );
+
+        let registry = Rc::new(registry);
+        let world = Py::new(py, ScriptWorld::new(registry.clone())).map_err(|e| {
+            e.print(py);
+            "couldn't give the level script a world".to_string()
+        })?;
+        script
+            .add("world", world.clone_ref(py))
+            .expect("Couldn't insert the world into the level module.");
+
+        // load_map sets the real tick rate, before the script has a chance to schedule anything.
+        let timers = Rc::new(RefCell::new(TimerQueue::new(super::default_tick_rate())));
+        let scheduler: PyObject = Py::new(py, Scheduler::new(timers.clone()))
+            .map_err(|e| {
+                e.print(py);
+                "couldn't give the level script a scheduler".to_string()
+            })?
+            .into();
+        for function in &["after", "every"] {
+            let method = scheduler
+                .getattr(py, *function)
+                .expect("Couldn't get a function from the scheduler.");
+            script
+                .add(*function, method)
+                .expect("Couldn't insert a scheduling function into the level module.");
+        }
+
+        let appearance_record = Rc::new(RefCell::new(AppearanceRecord::default()));
+        let spawner: PyObject = Py::new(py, PrefabSpawner::new(prefabs, appearance_record.clone()))
+            .map_err(|e| {
+                e.print(py);
+                "couldn't give the level script its prefabs".to_string()
+            })?
+            .into();
+        for function in &["prefab", "spawn_prefab"] {
+            let method = spawner
+                .getattr(py, *function)
+                .expect("Couldn't get a function from the prefab spawner.");
+            script
+                .add(*function, method)
+                .expect("Couldn't insert a prefab function into the level module.");
+        }
+
+        Ok(Self {
+            module: script.to_object(py),
+            level,
+            registry,
+            world,
+            timers,
+            appearance_record,
+            seed,
+            random,
+            sandbox,
+        })
+    }
+
+    /// Runs part of the script in the sandbox, with Python's `random` module drawing from
+    /// this level's own generator. That module is shared by every level in the process,
+    /// so the level's state is swapped in for the call and back out afterwards; otherwise
+    /// what a level draws would depend on how many others there are and when they tick.
+    fn run<'p, R>(
+        &self,
+        py: Python<'p>,
+        what: &str,
+        limit: Duration,
+        f: impl FnOnce() -> PyResult<R>,
+    ) -> PyResult<R> {
+        let random = py.import("random")?;
+        let own = self.random.as_ref(py);
+        random.call1("setstate", (own.call_method0("getstate")?,))?;
+        let output = self.sandbox.run(py, what, limit, f);
+        own.call_method1("setstate", (random.call0("getstate")?,))?;
+        output
+    }
+
+    /// The module the level's script was loaded into.
+    fn script<'p>(&'p self, py: Python<'p>) -> &'p PyModule {
+        self.module
+            .cast_as::<PyModule>(py)
+            .expect("Couldn't get level module!")
+    }
+
+    /// The names of the classes this level's script knows what to do with, if it says.
+    ///
+    /// Scripts list these in a `CLASSES` variable, so that classes in the map.json
+    /// that aren't in it can be reported as typos.
+    pub fn known_classes(&self) -> Result<Option<Vec<String>>, String> {
+        let gil = Python::acquire_gil();
+        let py = gil.python();
+
+        match self.script(py).get("CLASSES") {
+            Ok(classes) => classes.extract().map(Some).map_err(|e| {
+                e.print(py);
+                format!("{}.py's CLASSES should be a list of strings", self.level)
+            }),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Runs the script's load_map function on the map,
+    /// returning the list of entities (which are lists of components) it gives back.
+    fn run_load_map<'p>(
+        &self,
+        py: Python<'p>,
+        config: &ServerConfig,
+        entries: Vec<MapEntry>,
+    ) -> Result<Vec<Vec<PyObject>>, String> {
+        let entries = entries
+            .into_iter()
+            .map(|entry| Py::new(py, entry).map(PyObject::from))
+            .collect::<PyResult<Vec<PyObject>>>()
+            .map_err(|e| {
+                e.print(py);
+                "couldn't give the map entries to Python".to_string()
+            })?;
+
+        // grab the level module
+        let script = self.script(py);
+
+        // share some of the config with it.
+        script
+            .add("appearance_record", config.appearance_record.clone())
+            .expect("Couldn't insert the appearance_record into the level module!");
+        *self.appearance_record.borrow_mut() = config.appearance_record.clone();
+        let rules = serde_json::to_string(&config.level_config.rules)
+            .map_err(|e| format!("{}'s rules can't be given to Python: {}", self.level, e))?;
+        py.import("json")
+            .and_then(|json| json.call1("loads", (rules,)))
+            .and_then(|rules| script.add("rules", rules))
+            .map_err(|e| {
+                e.print(py);
+                "couldn't give the level script its rules".to_string()
+            })?;
+
+        // so that the same seed gives the same layout.
+        self.random
+            .call_method1(py, "seed", (self.seed,))
+            .map_err(|e| {
+                e.print(py);
+                "couldn't seed the level script's random generator".to_string()
+            })?;
+
+        self.run(py, "load_map", self.sandbox.limits().load(), || {
+            script.call1("load_map", (entries,))
+        })
+        .map_err(|e| {
+            e.print(py);
+            format!("{}.py's load_map function failed.", self.level)
+        })?
+        .extract::<Vec<Vec<PyObject>>>()
+        .map_err(|e| {
+            e.print(py);
+            format!(
+                concat!(
+                    "{}.py's load_map function gave output in the wrong format, ",
+                    "expected a list of list of components."
+                ),
+                self.level
+            )
+        })
+    }
+
+    /// Runs the script's load_map function without creating any entities,
+    /// and finds any components in its output that can't be inserted.
+    pub fn unregistered_components(
+        &self,
+        config: &ServerConfig,
+        entries: Vec<MapEntry>,
+    ) -> Result<Vec<UnregisteredComponent>, String> {
+        let gil = Python::acquire_gil();
+        let py = gil.python();
+
+        let mut unregistered = Vec::new();
+        for (entity, comps) in self.run_load_map(py, config, entries)?.iter().enumerate() {
+            for comp in comps.iter().filter(|c| !self.registry.accepts(py, c)) {
+                unregistered.push(UnregisteredComponent {
+                    entity,
+                    location: location_of(py, comps),
+                    component: type_name(py, comp),
+                });
+            }
+        }
+
+        Ok(unregistered)
+    }
+
+    /// Creates the entities the script's load_map function asks for, returning them.
+    /// If the script fails or gives invalid output, none of them are created.
+    pub fn load_map(
+        &mut self,
+        world: &mut World,
+        config: &ServerConfig,
+        entries: Vec<MapEntry>,
+        tick: u64,
+    ) -> Result<Vec<Entity>, String> {
+        self.timers.borrow_mut().set_clock(tick, config.tick_rate);
+
+        // Run the python function
+        let gil = Python::acquire_gil();
+        let py = gil.python();
+
+        // run the script and collect the list of entities (which are lists of components) from it.
+        let mut handle = self.world.clone_ref(py);
+        let output = ScriptWorld::enter(&mut handle, py, world, || {
+            self.run_load_map(py, config, entries)
+        });
+
+        // the script may have also spawned some through its world.
+        let mut script_world = self.world.as_mut(py);
+        let mut spawned = script_world.take_spawned();
+        script_world.take_changed();
+
+        // make sure every component can be inserted before inserting any of them.
+        let output = match output.and_then(|output| self.check_output(py, output)) {
+            Ok(output) => output,
+            Err(e) => {
+                let ents = world.entities();
+                for ent in spawned {
+                    ents.delete(ent).ok();
+                }
+                return Err(e);
+            }
+        };
+
+        // prepare to create the entities the script tells us about
+        let ents = world.entities();
+        let lu = world.read_resource::<LazyUpdate>();
+
+        // loop over each entity's list of components
+        for entity_components in output {
+            // make an index for that entity, and add each of the components to it.
+            let ent = ents.create();
+            self.registry.insert_all(py, &entity_components, &lu, ent)?;
+            spawned.push(ent);
+        }
+
+        Ok(spawned)
+    }
+
+    /// Makes sure every component in load_map's output can be inserted.
+    fn check_output<'p>(
+        &self,
+        py: Python<'p>,
+        output: Vec<Vec<PyObject>>,
+    ) -> Result<Vec<Vec<PyObject>>, String> {
+        for (index, comps) in output.iter().enumerate() {
+            if let Some(comp) = comps.iter().find(|c| !self.registry.accepts(py, c)) {
+                return Err(format!(
+                    "{}.py's load_map function gave invalid output for {}: No such component as {}!",
+                    self.level,
+                    describe_entity(py, index, comps),
+                    type_name(py, comp)
+                ));
+            }
+        }
+        Ok(output)
+    }
+
+    /// Hands the LevelEvents that have happened since the last call to the script,
+    /// along with a tick, and calls any timers that are due,
+    /// then carries out the actions they return.
+    ///
+    /// Should be called once after each time the World is dispatched and maintained.
+    pub fn run_hooks(&mut self, world: &mut World, tick: u64) {
+        let events = world.write_resource::<LevelEvents>().drain();
+
+        let gil = Python::acquire_gil();
+        let py = gil.python();
+
+        let mut handle = self.world.clone_ref(py);
+        let script = self.script(py);
+        let actions = ScriptWorld::enter(&mut handle, py, world, || {
+            let mut actions = self.call_hook(py, script, "on_tick", vec![tick.into_py(py)]);
+            for event in events {
+                if let LevelEvent::PlayerJoin { player, info } = &event {
+                    actions.extend(self.spawn_player(py, script, *player, info.clone()));
+                }
+                let (hook, args) = event.hook(py);
+                actions.append(&mut self.call_hook(py, script, hook, args));
+            }
+
+            // the timers are taken out of the queue while they run, so that they can schedule more.
+            let due = self.timers.borrow_mut().advance(tick);
+            for timer in due {
+                if self.timers.borrow().is_cancelled(&timer) {
+                    continue;
+                }
+                let callback = timer.callback.as_ref(py);
+                actions.append(&mut self.call(py, "timer", &callback, Vec::new()));
+                self.timers.borrow_mut().rearm(timer);
+            }
+
+            actions
+        });
+
+        // a hook may have been called for an entity that another hook asked to be despawned,
+        // so nothing is carried out until all of them have run.
+        {
+            let script_world = self.world.as_ref(py);
+            for action in actions {
+                let result = if let Ok(spawn) = action.cast_as::<Spawn>(py) {
+                    script_world
+                        .spawn_entity(py, world, &spawn.components)
+                        .map(|_| ())
+                } else if let Ok(insert) = action.cast_as::<Insert>(py) {
+                    script_world.insert_components(py, world, insert.entity, &insert.components)
+                } else if let Ok(despawn) = action.cast_as::<Despawn>(py) {
+                    ScriptWorld::despawn_entity(world, despawn.entity)
+                } else if let Ok(notice) = action.cast_as::<Notice>(py) {
+                    world
+                        .read_resource::<ConnectionManager>()
+                        .broadcast(&notice.text);
+                    Ok(())
+                } else {
+                    Err(format!(
+                        "{} isn't an action, expected Spawn, Insert, Despawn or Notice",
+                        type_name(py, &action)
+                    ))
+                };
+
+                if let Err(e) = result {
+                    log::error!(
+                        "{}.py's hooks asked for something invalid: {}",
+                        self.level,
+                        e
+                    );
+                }
+            }
+        }
+
+        // now that the components are actually there, everyone can be told about them.
+        world.maintain();
+        let mut script_world = self.world.as_mut(py);
+        for ent in script_world.take_spawned() {
+            net::announce(world, ent);
+        }
+        for ent in script_world.take_changed() {
+            net::send_components(world, ent);
+        }
+    }
+
+    /// Calls the script's `spawn_player` function, if it has one,
+    /// returning an Insert of the components it gives the player.
+    fn spawn_player<'p>(
+        &self,
+        py: Python<'p>,
+        script: &PyModule,
+        player: u32,
+        info: ClientInfo,
+    ) -> Option<PyObject> {
+        let function = script.get("spawn_player").ok()?;
+        let args = vec![player.into_py(py), info.into_py(py)];
+        let components = self.call(py, "spawn_player function", function, args);
+        if components.is_empty() {
+            return None;
+        }
+        match Py::new(
+            py,
+            Insert {
+                entity: player,
+                components,
+            },
+        ) {
+            Ok(insert) => Some(insert.into()),
+            Err(e) => {
+                e.print(py);
+                None
+            }
+        }
+    }
+
+    /// Calls the function in the script with this name, if there is one,
+    /// returning the actions it gives back.
+    fn call_hook<'p>(
+        &self,
+        py: Python<'p>,
+        script: &PyModule,
+        hook: &str,
+        args: Vec<PyObject>,
+    ) -> Vec<PyObject> {
+        match script.get(hook) {
+            Ok(function) => self.call(py, &format!("{} function", hook), function, args),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// Calls a function from the script, returning the actions it gives back.
+    /// Errors are logged rather than returned, so that one broken function doesn't stop the game.
+    fn call<'p>(
+        &self,
+        py: Python<'p>,
+        what: &str,
+        function: &PyAny,
+        args: Vec<PyObject>,
+    ) -> Vec<PyObject> {
+        let args = pyo3::types::PyTuple::new(py, args);
+        let limit = self.sandbox.limits().hook();
+        let output = self.run(py, what, limit, || {
+            let output = function.call1(args)?;
+            if output.is_none() {
+                Ok(Vec::new())
+            } else {
+                output.extract::<Vec<PyObject>>()
+            }
+        });
+
+        output.unwrap_or_else(|e| {
+            e.print(py);
+            log::error!(
+                "{}.py's {} failed or didn't return a list of actions.",
+                self.level,
+                what
+            );
+            Vec::new()
+        })
+    }
+}
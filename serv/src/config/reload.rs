@@ -0,0 +1,79 @@
+//! Notices when a level's files are edited, so that it can be reloaded while people play.
+//! That's everything in the level's folder, its level.toml included, along with
+//! the files it shares with other levels, like the prefabs.toml in the levels folder.
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often the level's folder is looked over for changes.
+const CHECK_EVERY: Duration = Duration::from_secs(1);
+
+/// Every file in a folder and the folders in it, i.e. a dungeon's rooms,
+/// and when each was last modified.
+fn stamps(dir: &Path) -> Vec<(PathBuf, Option<SystemTime>)> {
+    let mut stamps: Vec<_> = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .flat_map(|entry| {
+                    let path = entry.path();
+                    if path.is_dir() {
+                        stamps(&path)
+                    } else {
+                        let modified = entry.metadata().and_then(|m| m.modified()).ok();
+                        vec![(path, modified)]
+                    }
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    stamps.sort();
+    stamps
+}
+
+pub struct LevelWatcher {
+    dir: PathBuf,
+    /// Files outside of the level's folder that it's made from too.
+    shared: Vec<PathBuf>,
+    stamps: Vec<(PathBuf, Option<SystemTime>)>,
+    last_check: Instant,
+}
+impl LevelWatcher {
+    pub fn new(dir: &Path, shared: Vec<PathBuf>) -> Self {
+        let mut watcher = Self {
+            dir: dir.to_path_buf(),
+            shared,
+            stamps: Vec::new(),
+            last_check: Instant::now(),
+        };
+        watcher.stamps = watcher.stamps();
+        watcher
+    }
+
+    fn stamps(&self) -> Vec<(PathBuf, Option<SystemTime>)> {
+        let mut stamps = stamps(&self.dir);
+        for path in self.shared.iter() {
+            let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+            stamps.push((path.clone(), modified));
+        }
+        stamps
+    }
+
+    /// Whether any of the level's files have been added, removed or modified
+    /// since the last time this returned true.
+    /// Only actually looks at the files once every CHECK_EVERY.
+    pub fn changed(&mut self) -> bool {
+        if self.last_check.elapsed() < CHECK_EVERY {
+            return false;
+        }
+        self.last_check = Instant::now();
+
+        let stamps = self.stamps();
+        if stamps != self.stamps {
+            self.stamps = stamps;
+            true
+        } else {
+            false
+        }
+    }
+}
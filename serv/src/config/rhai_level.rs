@@ -0,0 +1,1040 @@
+//! Runs level scripts written in Rhai, a scripting language made to be embedded in Rust,
+//! so that levels can be made and played without Python.
+//!
+//! ```rhai
+//! const CLASSES = ["enemy"];
+//!
+//! fn load_map(entries) {
+//!     let entities = [];
+//!     for entry in entries {
+//!         let obj = basic_components(entry);
+//!         if "enemy" in entry.classes {
+//!             obj += [Alignment("Enemies"), Chaser(Alignment("Players"), 5.0)];
+//!         }
+//!         entities.push(obj);
+//!     }
+//!     entities
+//! }
+//!
+//! fn on_entity_death(entity, name, classes) {
+//!     if "boss" in classes {
+//!         for enemy in world().entities_with_class("enemy") {
+//!             world().despawn(enemy);
+//!         }
+//!         let taunts = every(5, || [Notice("You'll pay for that!")]);
+//!         after(30, || taunts.cancel());
+//!     }
+//!     [Notice("Something died!")]
+//! }
+//! ```
+//!
+//! Components are made the same way as in Python (see comn's script module),
+//! except that Rhai doesn't have keyword arguments, so they're always given in order.
+//! `appearance_of(name)` stands in for Python's `appearance_record.appearance_of(name)`,
+//! and `random_int(min, max)` for `random.randint`, since Rhai has no `random` module.
+//! `level_seed()` stands in for Python's `level_seed`, and `rules()` for `rules`.
+//! `prefab` and `spawn_prefab` take their overrides as an object map, i.e. `#{ speed: 0.1 }`.
+//! `world()` stands in for Python's `world`, and has the same functions (see script_world),
+//! except that `spawn` is `spawn_entity`, since `spawn` is reserved in Rhai.
+//! They give `()` where Python would give None. `after` and `every` take function pointers
+//! instead of Python functions, but otherwise work the same way (see the timers module).
+//! Rhai scripts can't import anything.
+//!
+//! Hooks are given the same arguments as in Python, with `()` in place of `None`.
+use comn::specs::{storage::MaskedStorage, Entities, LazyUpdate, World, WorldExt};
+use specs::{Component, Entity, Join};
+// std
+use std::any::TypeId;
+use std::cell::{Cell, RefCell};
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+// us
+use super::level::{despawn, UnregisteredComponent};
+use super::timers::TimerQueue;
+use super::{
+    ClientInfo, LevelEvent, LevelEvents, MapEntry, MapOrigin, Prefabs, ScriptLimits, ServerConfig,
+};
+use crate::combat::{alignment::Alignment, Chaser};
+use crate::net::{self, prelude::*};
+use crate::triggers::Trigger;
+use comn::art::{Appearance, AppearanceRecord};
+use comn::combat::{Health, Hurtbox};
+use comn::controls::{Heading, Speed};
+use comn::prelude::*;
+use comn::{Elevation, Hitbox, Key, Lock, Orientation, ScriptField, Scriptable, Stairs};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+// script
+use rhai::module_resolvers::DummyModuleResolver;
+use rhai::{Array, Dynamic, Engine, EvalAltResult, FnPtr, Map, Scope, AST, FLOAT, INT};
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// Something a hook asked for.
+#[derive(Clone)]
+enum Action {
+    Spawn(Array),
+    Insert(INT, Array),
+    Despawn(INT),
+    Notice(String),
+}
+
+/// Inserts the component in the Dynamic, if it's the right type.
+type Inserter = fn(&Dynamic, &LazyUpdate, Entity) -> bool;
+
+fn insert<C: Component + Clone + Send + Sync + 'static>(
+    comp: &Dynamic,
+    lu: &LazyUpdate,
+    ent: Entity,
+) -> bool {
+    match comp.clone().try_cast::<C>() {
+        Some(comp) => {
+            lu.insert(ent, comp);
+            true
+        }
+        None => false,
+    }
+}
+
+/// A copy of the entity's component, if it has one.
+type Getter = fn(&World, Entity) -> Option<Dynamic>;
+
+fn get<C: Component + Clone + Send + Sync + 'static>(
+    world: &World,
+    ent: Entity,
+) -> Option<Dynamic> {
+    // if the storage was never registered, no entity can have one.
+    if !world.has_value::<MaskedStorage<C>>() {
+        return None;
+    }
+    world
+        .read_storage::<C>()
+        .get(ent)
+        .cloned()
+        .map(Dynamic::from)
+}
+
+/// Every type of component a Rhai script can give the server, or get from the world.
+#[derive(Default)]
+struct ComponentRegistry {
+    inserters: Vec<(TypeId, Inserter)>,
+    getters: Vec<(&'static str, Getter)>,
+}
+impl ComponentRegistry {
+    fn register<C: Component + Clone + Send + Sync + 'static>(&mut self, name: &'static str) {
+        self.inserters.push((TypeId::of::<C>(), insert::<C>));
+        self.getters.push((name, get::<C>));
+    }
+
+    /// A copy of the component with this name the entity has, or `()` if it doesn't have one.
+    fn get(&self, world: &World, ent: Entity, name: &str) -> Result<Dynamic, String> {
+        let (_, get) = self
+            .getters
+            .iter()
+            .find(|(n, _)| *n == name)
+            .ok_or_else(|| format!("No such component as {}!", name))?;
+        Ok(get(world, ent).unwrap_or(Dynamic::UNIT))
+    }
+
+    fn accepts(&self, comp: &Dynamic) -> bool {
+        self.inserters.iter().any(|(id, _)| *id == comp.type_id())
+    }
+
+    fn insert_all(&self, comps: &Array, lu: &LazyUpdate, ent: Entity) -> Result<(), String> {
+        for comp in comps.iter() {
+            if !self
+                .inserters
+                .iter()
+                .any(|(_, insert)| insert(comp, lu, ent))
+            {
+                return Err(format!("{} isn't a component", comp.type_name()));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn alive(ents: &Entities, id: INT) -> Result<Entity, String> {
+    let ent = u32::try_from(id).map(|id| ents.entity(id));
+    match ent {
+        Ok(ent) if ents.is_alive(ent) => Ok(ent),
+        _ => Err(format!("entity {} doesn't exist", id)),
+    }
+}
+
+/// What `world()` gives a Rhai script, to look at and change the game with.
+/// Changes are made the same way as Python's `world`, so they're only visible
+/// once the script has finished running.
+struct RhaiWorld {
+    /// Only set while the level's script is running, by `RhaiWorld::enter`.
+    world: Cell<*const World>,
+    registry: Rc<ComponentRegistry>,
+    /// Entities created since `take_spawned` was last called.
+    spawned: RefCell<Vec<Entity>>,
+    /// Entities given new components since `take_changed` was last called.
+    changed: RefCell<Vec<Entity>>,
+}
+
+/// Clears the RhaiWorld's pointer to the World when it's dropped,
+/// so that it's cleared even if the script's function panics.
+struct Entered<'w>(&'w RhaiWorld);
+
+impl Drop for Entered<'_> {
+    fn drop(&mut self) {
+        self.0.world.set(std::ptr::null());
+    }
+}
+
+impl RhaiWorld {
+    fn new(registry: Rc<ComponentRegistry>) -> Self {
+        Self {
+            world: Cell::new(std::ptr::null()),
+            registry,
+            spawned: RefCell::new(Vec::new()),
+            changed: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Lets the script use the World for as long as `f` runs.
+    fn enter<R>(&self, world: &World, f: impl FnOnce() -> R) -> R {
+        self.world.set(world);
+        let _entered = Entered(self);
+        f()
+    }
+
+    fn world(&self) -> Result<&World, String> {
+        if self.world.get().is_null() {
+            Err("the world can only be used while load_map or a hook is running".to_string())
+        } else {
+            // enter only sets the pointer for as long as the World is borrowed,
+            // and the script can only reach this while enter is running.
+            Ok(unsafe { &*self.world.get() })
+        }
+    }
+
+    /// The entities the script has created, which the players haven't been told about yet.
+    fn take_spawned(&self) -> Vec<Entity> {
+        self.spawned.replace(Vec::new())
+    }
+
+    /// The entities the script has inserted components into,
+    /// which the players haven't been told about yet.
+    fn take_changed(&self) -> Vec<Entity> {
+        self.changed.replace(Vec::new())
+    }
+
+    /// Creates an entity with these components.
+    fn spawn_entity(&self, world: &World, comps: &Array) -> Result<Entity, String> {
+        let ent = world.entities().create();
+        self.spawned.borrow_mut().push(ent);
+        self.registry
+            .insert_all(comps, &world.read_resource::<LazyUpdate>(), ent)?;
+        Ok(ent)
+    }
+
+    /// Adds these components to an existing entity, replacing any of the same type.
+    fn insert_components(&self, world: &World, id: INT, comps: &Array) -> Result<(), String> {
+        let ent = alive(&world.entities(), id)?;
+        self.changed.borrow_mut().push(ent);
+        self.registry
+            .insert_all(comps, &world.read_resource::<LazyUpdate>(), ent)
+    }
+
+    /// Kills an entity, and tells the players it's gone.
+    fn despawn_entity(world: &World, id: INT) -> Result<(), String> {
+        despawn(world, alive(&world.entities(), id)?);
+        Ok(())
+    }
+}
+
+fn ids(ents: impl Iterator<Item = Entity>) -> Array {
+    ents.map(|ent| Dynamic::from(ent.id() as INT)).collect()
+}
+
+/// Gives the script `world()`, along with the functions it has.
+fn register_world(engine: &mut Engine, world: Rc<RhaiWorld>) {
+    engine
+        .register_type_with_name::<Rc<RhaiWorld>>("World")
+        .register_fn("world", move || world.clone())
+        .register_fn(
+            "spawn_entity",
+            |w: &mut Rc<RhaiWorld>, components: Array| -> ScriptResult<INT> {
+                Ok(w.spawn_entity(w.world()?, &components)?.id() as INT)
+            },
+        )
+        .register_fn(
+            "despawn",
+            |w: &mut Rc<RhaiWorld>, entity: INT| -> ScriptResult<()> {
+                Ok(RhaiWorld::despawn_entity(w.world()?, entity)?)
+            },
+        )
+        .register_fn(
+            "set",
+            |w: &mut Rc<RhaiWorld>, entity: INT, component: Dynamic| -> ScriptResult<()> {
+                Ok(w.insert_components(w.world()?, entity, &vec![component])?)
+            },
+        )
+        .register_fn(
+            "get",
+            |w: &mut Rc<RhaiWorld>, entity: INT, component: &str| -> ScriptResult<Dynamic> {
+                let world = w.world()?;
+                let ent = alive(&world.entities(), entity)?;
+                Ok(w.registry.get(world, ent, component)?)
+            },
+        )
+        .register_fn(
+            "entities_with_class",
+            |w: &mut Rc<RhaiWorld>, class: &str| -> ScriptResult<Array> {
+                let world = w.world()?;
+                Ok(ids((&world.entities(), &world.read_storage::<MapOrigin>())
+                    .join()
+                    .filter(|(_, origin)| origin.classes.iter().any(|c| c == class))
+                    .map(|(ent, _)| ent)))
+            },
+        )
+        .register_fn(
+            "entities_near",
+            |w: &mut Rc<RhaiWorld>, pos: Dynamic, radius: Dynamic| -> ScriptResult<Array> {
+                let world = w.world()?;
+                let center = Vec2::from_rhai(pos)?;
+                let radius = f32::from_rhai(radius)?;
+                Ok(ids((&world.entities(), &world.read_storage::<Pos>())
+                    .join()
+                    .filter(|(_, Pos { iso })| (iso.translation.vector - center).norm() <= radius)
+                    .map(|(ent, _)| ent)))
+            },
+        );
+}
+
+/// Returned by `after` and `every`, so that the timer can be stopped.
+#[derive(Clone)]
+struct TimerHandle {
+    id: u64,
+    queue: Rc<RefCell<TimerQueue<FnPtr>>>,
+}
+
+/// Gives the script `after` and `every`.
+fn register_timers(engine: &mut Engine, queue: Rc<RefCell<TimerQueue<FnPtr>>>) {
+    engine
+        .register_type_with_name::<TimerHandle>("TimerHandle")
+        .register_fn("cancel", |handle: &mut TimerHandle| {
+            handle.queue.borrow_mut().cancel(handle.id)
+        });
+
+    for &(name, repeat) in &[("after", false), ("every", true)] {
+        let queue = queue.clone();
+        engine.register_fn(
+            name,
+            move |seconds: Dynamic, callback: FnPtr| -> ScriptResult<TimerHandle> {
+                let seconds = f32::from_rhai(seconds)?;
+                let id = queue.borrow_mut().schedule(seconds, repeat, callback)?;
+                Ok(TimerHandle {
+                    id,
+                    queue: queue.clone(),
+                })
+            },
+        );
+    }
+}
+
+/// Where the entity is, if one of its components is a Pos.
+fn location_of(comps: &Array) -> Option<Vec2> {
+    comps
+        .iter()
+        .find_map(|comp| comp.clone().try_cast::<Pos>())
+        .map(|pos| pos.iso.translation.vector)
+}
+
+/// Describes an entity from the output of a load_map function, for error messages.
+fn describe_entity(index: usize, comps: &Array) -> String {
+    match location_of(comps) {
+        Some(loc) => format!("entity #{} (at {:.2}, {:.2})", index, loc.x, loc.y),
+        None => format!("entity #{}", index),
+    }
+}
+
+fn strings(strings: Vec<String>) -> Array {
+    strings.into_iter().map(Dynamic::from).collect()
+}
+
+/// The arguments the hook for this event is called with.
+fn hook_args(event: LevelEvent) -> Vec<Dynamic> {
+    let id = |ent: u32| Dynamic::from(ent as INT);
+    let origin_args = |origin: Option<MapOrigin>| {
+        let (name, classes) = match origin {
+            Some(MapOrigin { name, classes }) => (Dynamic::from(name), strings(classes)),
+            None => (Dynamic::UNIT, Array::new()),
+        };
+        vec![name, Dynamic::from(classes)]
+    };
+    match event {
+        LevelEvent::EntityDeath { ent, origin } => {
+            let mut args = vec![id(ent)];
+            args.append(&mut origin_args(origin));
+            args
+        }
+        LevelEvent::ItemPickup { player, item } => vec![id(player), id(item)],
+        LevelEvent::PlayerJoin { player, .. } => vec![id(player)],
+        LevelEvent::PlayerDeath { player } => vec![id(player)],
+        LevelEvent::TriggerEnter {
+            trigger,
+            ent,
+            origin,
+        }
+        | LevelEvent::TriggerStay {
+            trigger,
+            ent,
+            origin,
+        }
+        | LevelEvent::TriggerExit {
+            trigger,
+            ent,
+            origin,
+        } => {
+            let mut args = vec![id(trigger), id(ent)];
+            args.append(&mut origin_args(origin));
+            args
+        }
+        LevelEvent::Unlock {
+            lock,
+            player,
+            origin,
+        } => {
+            let mut args = vec![id(lock), id(player)];
+            args.append(&mut origin_args(origin));
+            args
+        }
+    }
+}
+
+/// Adds the components scripts can make to the engine, along with MapEntry and the actions.
+fn register_api(
+    engine: &mut Engine,
+    registry: &mut ComponentRegistry,
+    appearance_record: Rc<RefCell<AppearanceRecord>>,
+    prefabs: Rc<Prefabs>,
+    rng: Rc<RefCell<StdRng>>,
+    seed: u64,
+    rules: Rc<RefCell<Dynamic>>,
+) {
+    macro_rules! register_scriptable {
+        ( $( $t:tt , )* ) => {
+            $(
+                registry.register::<$t>(stringify!($t));
+                <$t as Scriptable>::register_rhai(engine);
+            )*
+        }
+    }
+
+    macro_rules! register_components {
+        ( $( $t:tt , )* ) => {
+            $(
+                registry.register::<$t>(stringify!($t));
+                engine.register_type_with_name::<$t>(stringify!($t));
+            )*
+        }
+    }
+
+    comn::script::register_rhai_types(engine);
+
+    #[rustfmt::skip]
+    register_scriptable!(
+        Appearance,
+
+        Hitbox,
+        Hurtbox,
+        Health,
+
+        Elevation,
+
+        Speed,
+        Heading,
+        Pos,
+
+        Alignment,
+        Item,
+        Stairs,
+        Trigger,
+        Lock,
+        Key,
+    );
+
+    #[rustfmt::skip]
+    register_components!(
+        Orientation,
+        MapOrigin,
+
+        Chaser,
+    );
+
+    let make_prefab = {
+        let appearance_record = appearance_record.clone();
+        move |name: &str, pos: Dynamic, overrides: Option<Map>| -> ScriptResult<Array> {
+            let overrides = match overrides {
+                Some(map) => Some(
+                    rhai::serde::from_dynamic(&Dynamic::from_map(map))
+                        .map_err(|e| format!("invalid overrides for prefab {:?}: {}", name, e))?,
+                ),
+                None => None,
+            };
+            let built = prefabs.build(name, overrides.as_ref(), &appearance_record.borrow())?;
+
+            let mut comps = vec![Dynamic::from(Pos::vec(Vec2::from_rhai(pos)?))];
+            comps.extend(built.rhai_components());
+            Ok(comps)
+        }
+    };
+    {
+        let make_prefab = make_prefab.clone();
+        engine.register_fn("prefab", move |name: &str, pos: Dynamic| {
+            make_prefab(name, pos, None)
+        });
+    }
+    {
+        let make_prefab = make_prefab.clone();
+        engine.register_fn("prefab", move |name: &str, pos: Dynamic, overrides: Map| {
+            make_prefab(name, pos, Some(overrides))
+        });
+    }
+    {
+        let make_prefab = make_prefab.clone();
+        engine.register_fn("spawn_prefab", move |name: &str, pos: Dynamic| {
+            make_prefab(name, pos, None).map(Action::Spawn)
+        });
+    }
+    engine.register_fn(
+        "spawn_prefab",
+        move |name: &str, pos: Dynamic, overrides: Map| {
+            make_prefab(name, pos, Some(overrides)).map(Action::Spawn)
+        },
+    );
+
+    engine
+        .register_fn(
+            "appearance_of",
+            move |name: &str| -> ScriptResult<Appearance> {
+                Ok(appearance_record.borrow().try_appearance_of(name)?)
+            },
+        )
+        .register_fn(
+            "Orientation",
+            |quaternion: Dynamic| -> ScriptResult<Orientation> {
+                let quaternion = Vec::<f32>::from_rhai(quaternion)?;
+                if quaternion.len() != 4 {
+                    return Err(format!(
+                        "expected a quaternion with 4 numbers, found {:?}",
+                        quaternion
+                    )
+                    .into());
+                }
+                Ok(Orientation { quaternion })
+            },
+        )
+        .register_fn(
+            "Chaser",
+            |target: Dynamic, distance: Dynamic| -> ScriptResult<Chaser> {
+                Ok(Chaser::chasing(
+                    Alignment::from_rhai(target)?,
+                    f32::from_rhai(distance)?,
+                ))
+            },
+        );
+
+    engine
+        .register_type_with_name::<MapEntry>("MapEntry")
+        .register_get("name", |e: &mut MapEntry| e.name.clone())
+        .register_get("classes", |e: &mut MapEntry| strings(e.classes.clone()))
+        .register_get("appearance", |e: &mut MapEntry| e.appearance.clone())
+        .register_get("location", |e: &mut MapEntry| {
+            e.location
+                .iter()
+                .map(|&n| Dynamic::from(n as FLOAT))
+                .collect::<Array>()
+        })
+        .register_get("z_rotation", |e: &mut MapEntry| e.z_rotation as FLOAT)
+        .register_fn(
+            "basic_components",
+            |e: &mut MapEntry| -> ScriptResult<Array> {
+                let basic = e.components()?;
+                let mut comps = vec![
+                    Dynamic::from(basic.pos),
+                    Dynamic::from(basic.appearance),
+                    Dynamic::from(basic.origin),
+                ];
+                comps.extend(basic.hitbox.map(Dynamic::from));
+                comps.extend(basic.elevation.map(Dynamic::from));
+                comps.extend(basic.orientation.map(Dynamic::from));
+                comps.extend(
+                    basic
+                        .prefab
+                        .map(|p| p.rhai_components())
+                        .unwrap_or_default(),
+                );
+                Ok(comps)
+            },
+        );
+
+    engine
+        .register_type_with_name::<Action>("Action")
+        .register_fn("Spawn", |components: Array| Action::Spawn(components))
+        .register_fn("Insert", |entity: INT, components: Array| {
+            Action::Insert(entity, components)
+        })
+        .register_fn("Despawn", |entity: INT| Action::Despawn(entity))
+        .register_fn("Notice", |text: &str| Action::Notice(text.to_string()));
+
+    engine.register_fn(
+        "random_int",
+        move |min: INT, max: INT| -> ScriptResult<INT> {
+            if min > max {
+                return Err(
+                    format!("random_int's min {} is more than its max {}", min, max).into(),
+                );
+            }
+            Ok(rng.borrow_mut().gen_range(min, max + 1))
+        },
+    );
+    engine.register_fn("level_seed", move || seed as INT);
+    engine.register_fn("rules", move || rules.borrow().clone());
+}
+
+/// A level whose script is written in Rhai.
+pub struct RhaiLevel {
+    level: String,
+    script_path: PathBuf,
+    engine: Engine,
+    /// The functions the script defines; everything else in it is run once, when it's loaded.
+    functions: AST,
+    /// The variables the script defined when it was loaded, i.e. CLASSES.
+    scope: RefCell<Scope<'static>>,
+    registry: Rc<ComponentRegistry>,
+    /// What the script sees as `world()`.
+    world: Rc<RhaiWorld>,
+    /// The functions the script has scheduled with `after` and `every`.
+    timers: Rc<RefCell<TimerQueue<FnPtr>>>,
+    limits: ScriptLimits,
+    /// When the script should be stopped, if it's still running.
+    deadline: Rc<Cell<Instant>>,
+    /// The appearances the `Appearance` function can make,
+    /// set from the ServerConfig before load_map is called.
+    appearance_record: Rc<RefCell<AppearanceRecord>>,
+    /// Where `random_int` draws from; reset to the seed before load_map is called.
+    rng: Rc<RefCell<StdRng>>,
+    seed: u64,
+    /// The `[rules]` from the level's level.toml, set before load_map is called.
+    rules: Rc<RefCell<Dynamic>>,
+}
+
+impl RhaiLevel {
+    /// Interprets the source of the script for the level with this name.
+    pub fn load(
+        level: String,
+        script_path: &Path,
+        script_src: String,
+        limits: &ScriptLimits,
+        prefabs: Rc<Prefabs>,
+        seed: u64,
+    ) -> Result<Self, String> {
+        let deadline = Rc::new(Cell::new(Instant::now()));
+        let appearance_record = Rc::new(RefCell::new(AppearanceRecord::default()));
+        let rng = Rc::new(RefCell::new(StdRng::seed_from_u64(seed)));
+        let rules = Rc::new(RefCell::new(Dynamic::from(Map::new())));
+
+        let mut engine = Engine::new();
+        engine.set_module_resolver(DummyModuleResolver::new());
+        // debug builds of Rhai otherwise allow half as much nesting as release builds,
+        // which a closure handed to `after` inside a hook quickly runs into.
+        engine.set_max_expr_depths(64, 32);
+        {
+            let deadline = deadline.clone();
+            engine.on_progress(move |_| {
+                if Instant::now() > deadline.get() {
+                    Some(Dynamic::UNIT)
+                } else {
+                    None
+                }
+            });
+        }
+        {
+            let level = level.clone();
+            engine.on_print(move |text| log::info!("{}.rhai: {}", level, text));
+        }
+
+        let mut registry = ComponentRegistry::default();
+        register_api(
+            &mut engine,
+            &mut registry,
+            appearance_record.clone(),
+            prefabs,
+            rng.clone(),
+            seed,
+            rules.clone(),
+        );
+        let registry = Rc::new(registry);
+        let world = Rc::new(RhaiWorld::new(registry.clone()));
+        register_world(&mut engine, world.clone());
+        let timers = Rc::new(RefCell::new(TimerQueue::new(super::default_tick_rate())));
+        register_timers(&mut engine, timers.clone());
+
+        let ast = engine.compile(&script_src).map_err(|e| {
+            format!(
+                "Couldn't load Rhai script for level {} at {}: {}",
+                &level,
+                script_path.display(),
+                e
+            )
+        })?;
+
+        let rhai_level = Self {
+            functions: ast.clone_functions_only(),
+            level,
+            script_path: script_path.to_path_buf(),
+            engine,
+            scope: RefCell::new(Scope::new()),
+            registry,
+            world,
+            timers,
+            limits: limits.clone(),
+            deadline,
+            appearance_record,
+            rng,
+            seed,
+            rules,
+        };
+
+        let limit = rhai_level.limits.load();
+        rhai_level.deadline.set(Instant::now() + limit);
+        let loaded = rhai_level
+            .engine
+            .run_ast_with_scope(&mut rhai_level.scope.borrow_mut(), &ast);
+        loaded.map_err(|e| rhai_level.describe_error("loading the script", limit, *e))?;
+
+        Ok(rhai_level)
+    }
+
+    fn describe_error(&self, what: &str, limit: Duration, e: EvalAltResult) -> String {
+        match e {
+            EvalAltResult::ErrorTerminated(..) => format!(
+                "{} was interrupted: {} took longer than {:?}",
+                self.script_path.display(),
+                what,
+                limit
+            ),
+            e => format!("{}.rhai's {} failed: {}", self.level, what, e),
+        }
+    }
+
+    fn has_function(&self, name: &str) -> bool {
+        self.functions.iter_functions().any(|f| f.name == name)
+    }
+
+    /// Calls the function in the script with this name,
+    /// stopping it if it takes longer than `limit`.
+    fn call(
+        &self,
+        what: &str,
+        limit: Duration,
+        name: &str,
+        args: Vec<Dynamic>,
+    ) -> Result<Dynamic, String> {
+        self.deadline.set(Instant::now() + limit);
+        self.engine
+            .call_fn::<Dynamic>(&mut self.scope.borrow_mut(), &self.functions, name, args)
+            .map_err(|e| self.describe_error(what, limit, *e))
+    }
+
+    /// The names of the classes this level's script knows what to do with, if it says.
+    pub fn known_classes(&self) -> Result<Option<Vec<String>>, String> {
+        let classes = match self.scope.borrow().get_value::<Array>("CLASSES") {
+            Some(classes) => classes,
+            None => return Ok(None),
+        };
+        classes
+            .into_iter()
+            .map(|class| class.into_string().ok())
+            .collect::<Option<Vec<String>>>()
+            .map(Some)
+            .ok_or_else(|| {
+                format!(
+                    "{}.rhai's CLASSES should be an array of strings.",
+                    self.level
+                )
+            })
+    }
+
+    /// Calls the script's load_map function, returning the components of each entity.
+    fn run_load_map(
+        &self,
+        config: &ServerConfig,
+        entries: Vec<MapEntry>,
+    ) -> Result<Vec<Array>, String> {
+        if !self.has_function("load_map") {
+            return Err(format!("{}.rhai has no load_map function.", self.level));
+        }
+        *self.appearance_record.borrow_mut() = config.appearance_record.clone();
+        *self.rng.borrow_mut() = StdRng::seed_from_u64(self.seed);
+        *self.rules.borrow_mut() = rhai::serde::to_dynamic(&config.level_config.rules)
+            .map_err(|e| format!("{}'s rules can't be given to Rhai: {}", self.level, e))?;
+
+        let entries: Array = entries.into_iter().map(Dynamic::from).collect();
+        let output = self.call(
+            "load_map function",
+            self.limits.load(),
+            "load_map",
+            vec![Dynamic::from(entries)],
+        )?;
+
+        output
+            .try_cast::<Array>()
+            .and_then(|entities| {
+                entities
+                    .into_iter()
+                    .map(|entity| entity.try_cast::<Array>())
+                    .collect::<Option<Vec<Array>>>()
+            })
+            .ok_or_else(|| {
+                format!(
+                    concat!(
+                        "{}.rhai's load_map function gave output in the wrong format, ",
+                        "expected an array of arrays of components."
+                    ),
+                    self.level
+                )
+            })
+    }
+
+    /// Runs the script's load_map function without creating any entities,
+    /// and finds any components in its output that can't be inserted.
+    pub fn unregistered_components(
+        &self,
+        config: &ServerConfig,
+        entries: Vec<MapEntry>,
+    ) -> Result<Vec<UnregisteredComponent>, String> {
+        let output = self.run_load_map(config, entries)?;
+        Ok(output
+            .iter()
+            .enumerate()
+            .flat_map(|(entity, comps)| {
+                comps
+                    .iter()
+                    .filter(|comp| !self.registry.accepts(comp))
+                    .map(move |comp| UnregisteredComponent {
+                        entity,
+                        location: location_of(comps),
+                        component: self.engine.map_type_name(comp.type_name()).to_string(),
+                    })
+            })
+            .collect())
+    }
+
+    /// Creates the entities the script's load_map function asks for, returning them.
+    /// If the script fails, or any of them have a component the server doesn't know about,
+    /// none of them are created.
+    pub fn load_map(
+        &mut self,
+        world: &mut World,
+        config: &ServerConfig,
+        entries: Vec<MapEntry>,
+        tick: u64,
+    ) -> Result<Vec<Entity>, String> {
+        self.timers.borrow_mut().set_clock(tick, config.tick_rate);
+
+        let output = self
+            .world
+            .enter(world, || self.run_load_map(config, entries));
+
+        // the script may have also spawned some through its world.
+        let mut spawned = self.world.take_spawned();
+        self.world.take_changed();
+
+        // make sure every component can be inserted before inserting any of them.
+        let output = match output.and_then(|output| self.check_output(output)) {
+            Ok(output) => output,
+            Err(e) => {
+                let ents = world.entities();
+                for ent in spawned {
+                    ents.delete(ent).ok();
+                }
+                return Err(e);
+            }
+        };
+
+        let ents = world.entities();
+        let lu = world.read_resource::<LazyUpdate>();
+        for comps in output {
+            let ent = ents.create();
+            self.registry.insert_all(&comps, &lu, ent)?;
+            spawned.push(ent);
+        }
+
+        Ok(spawned)
+    }
+
+    /// Makes sure every component in load_map's output can be inserted.
+    fn check_output(&self, output: Vec<Array>) -> Result<Vec<Array>, String> {
+        for (index, comps) in output.iter().enumerate() {
+            if let Some(comp) = comps.iter().find(|comp| !self.registry.accepts(comp)) {
+                return Err(format!(
+                    "{}.rhai's load_map function gave {} a {}, which isn't a component.",
+                    self.level,
+                    describe_entity(index, comps),
+                    self.engine.map_type_name(comp.type_name())
+                ));
+            }
+        }
+        Ok(output)
+    }
+
+    /// Hands the LevelEvents that have happened since the last call to the script,
+    /// along with a tick, and calls any timers that are due,
+    /// then carries out the actions they return.
+    pub fn run_hooks(&mut self, world: &mut World, tick: u64) {
+        let events = world.write_resource::<LevelEvents>().drain();
+
+        let actions = self.world.enter(world, || {
+            let mut actions = self.call_hook("on_tick", vec![Dynamic::from(tick as INT)]);
+            for event in events {
+                if let LevelEvent::PlayerJoin { player, info } = &event {
+                    actions.extend(self.spawn_player(*player, info));
+                }
+                let hook = event.hook_name();
+                actions.append(&mut self.call_hook(hook, hook_args(event)));
+            }
+
+            // the timers are taken out of the queue while they run, so that they can schedule more.
+            let due = self.timers.borrow_mut().advance(tick);
+            for timer in due {
+                if self.timers.borrow().is_cancelled(&timer) {
+                    continue;
+                }
+                actions.append(&mut self.call_timer(&timer.callback));
+                self.timers.borrow_mut().rearm(timer);
+            }
+
+            actions
+        });
+
+        // a hook may have been called for an entity that another hook asked to be despawned,
+        // so nothing is carried out until all of them have run.
+        for action in actions {
+            let result = match action {
+                Action::Spawn(comps) => self.world.spawn_entity(world, &comps).map(|_| ()),
+                Action::Insert(id, comps) => self.world.insert_components(world, id, &comps),
+                Action::Despawn(id) => RhaiWorld::despawn_entity(world, id),
+                Action::Notice(text) => {
+                    world.read_resource::<ConnectionManager>().broadcast(&text);
+                    Ok(())
+                }
+            };
+
+            if let Err(e) = result {
+                log::error!(
+                    "{}.rhai's hooks asked for something invalid: {}",
+                    self.level,
+                    e
+                );
+            }
+        }
+
+        // now that the components are actually there, everyone can be told about them.
+        world.maintain();
+        for ent in self.world.take_spawned() {
+            net::announce(world, ent);
+        }
+        for ent in self.world.take_changed() {
+            net::send_components(world, ent);
+        }
+    }
+
+    /// Calls the script's `spawn_player` function, if it has one,
+    /// returning an Insert of the components it gives the player.
+    fn spawn_player(&self, player: u32, info: &ClientInfo) -> Option<Action> {
+        if !self.has_function("spawn_player") {
+            return None;
+        }
+
+        let mut client_info = Map::new();
+        client_info.insert("address".into(), Dynamic::from(info.address.clone()));
+        client_info.insert("arrived".into(), Dynamic::from(info.arrived));
+        let args = vec![Dynamic::from(player as INT), Dynamic::from(client_info)];
+
+        let what = "spawn_player function";
+        let output = self.call(what, self.limits.hook(), "spawn_player", args);
+        let comps = output.and_then(|output| {
+            if output.is_unit() {
+                return Ok(None);
+            }
+            output
+                .try_cast::<Array>()
+                .filter(|comps| comps.iter().all(|c| self.registry.accepts(c)))
+                .map(Some)
+                .ok_or_else(|| {
+                    format!(
+                        "{}.rhai's {} didn't return an array of components.",
+                        self.level, what
+                    )
+                })
+        });
+
+        match comps {
+            Ok(comps) => comps.map(|comps| Action::Insert(player as INT, comps)),
+            Err(e) => {
+                log::error!("{}", e);
+                None
+            }
+        }
+    }
+
+    /// Calls the function in the script with this name, if there is one,
+    /// returning the actions it gives back.
+    fn call_hook(&self, hook: &str, args: Vec<Dynamic>) -> Vec<Action> {
+        if !self.has_function(hook) {
+            return Vec::new();
+        }
+
+        let what = format!("{} function", hook);
+        let output = self.call(&what, self.limits.hook(), hook, args);
+        self.actions(&what, output)
+    }
+
+    /// Calls a function the script scheduled with `after` or `every`,
+    /// returning the actions it gives back.
+    fn call_timer(&self, callback: &FnPtr) -> Vec<Action> {
+        let limit = self.limits.hook();
+        self.deadline.set(Instant::now() + limit);
+        let output = callback
+            .call::<Dynamic>(&self.engine, &self.functions, ())
+            .map_err(|e| self.describe_error("timer", limit, *e));
+        self.actions("timer", output)
+    }
+
+    /// The actions a hook or timer gave back, if it gave back an array of them.
+    /// Errors are logged rather than returned, so that one broken hook doesn't stop the game.
+    fn actions(&self, what: &str, output: Result<Dynamic, String>) -> Vec<Action> {
+        let actions = output.and_then(|output| {
+            if output.is_unit() {
+                return Ok(Vec::new());
+            }
+            output
+                .try_cast::<Array>()
+                .and_then(|actions| {
+                    actions
+                        .into_iter()
+                        .map(|action| action.try_cast::<Action>())
+                        .collect::<Option<Vec<Action>>>()
+                })
+                .ok_or_else(|| {
+                    format!(
+                        "{}.rhai's {} didn't return an array of actions.",
+                        self.level, what
+                    )
+                })
+        });
+
+        actions.unwrap_or_else(|e| {
+            log::error!("{}", e);
+            Vec::new()
+        })
+    }
+}
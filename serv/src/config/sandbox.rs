@@ -0,0 +1,222 @@
+//! Keeps a level's script from hanging the server or reaching outside of its level.
+//!
+//! Each call into the script gets a time limit, and if it's still running once that's up,
+//! a TimeoutError is raised inside of it, wherever it is. Python can only be interrupted
+//! between bytecode instructions though, so a single long call into C,
+//! i.e. `sum(range(10 ** 12))`, still runs until it's done.
+//!
+//! Scripts can also only import the modules listed in the config, and go without `open`
+//! and the other builtins that deal with the outside world. That's enough to catch
+//! honest mistakes, but CPython wasn't built to be sandboxed,
+//! so it's no substitute for reading a level before running it.
+use super::ScriptLimits;
+use pyo3::{ffi, prelude::*, types::PyModule};
+use std::cell::Cell;
+use std::os::raw::c_long;
+use std::path::PathBuf;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// Builds the builtins a level's module is given in place of the real ones.
+const SANDBOX: &'static str = r#"
+import builtins
+
+def sandboxed_builtins(allowed, script_path):
+    real_import = builtins.__import__
+
+    def restricted_import(name, globals=None, locals=None, fromlist=(), level=0):
+        if level == 0 and name.partition(".")[0] in allowed:
+            return real_import(name, globals, locals, fromlist, level)
+        raise ImportError(
+            "{} isn't allowed to import {!r}, only {}".format(
+                script_path, name, ", ".join(allowed)
+            )
+        )
+
+    sandboxed = dict(builtins.__dict__)
+    sandboxed["__import__"] = restricted_import
+    for name in ("open", "input", "breakpoint", "exit", "quit"):
+        sandboxed.pop(name, None)
+    return sandboxed
+"#;
+
+/// A call into the script that's being timed.
+struct Watch {
+    call: u64,
+    /// Python's id for the thread the script is running on.
+    thread: c_long,
+    deadline: Instant,
+    /// Whether a TimeoutError has been raised for this call already.
+    fired: bool,
+}
+
+#[derive(Default)]
+struct WatchdogState {
+    watch: Option<Watch>,
+    shutdown: bool,
+}
+
+#[derive(Default)]
+struct Watchdog {
+    state: Mutex<WatchdogState>,
+    wake: Condvar,
+}
+
+/// Runs on its own thread, interrupting any call into the script that runs past its deadline.
+fn watch(dog: Arc<Watchdog>) {
+    let mut state = dog.state.lock().unwrap();
+    loop {
+        if state.shutdown {
+            return;
+        }
+
+        let pending = state
+            .watch
+            .as_ref()
+            .filter(|w| !w.fired)
+            .map(|w| (w.call, w.thread, w.deadline));
+        let (call, thread) = match pending {
+            Some((call, thread, deadline)) => {
+                let now = Instant::now();
+                if now < deadline {
+                    state = dog.wake.wait_timeout(state, deadline - now).unwrap().0;
+                    continue;
+                }
+                (call, thread)
+            }
+            None => {
+                state = dog.wake.wait(state).unwrap();
+                continue;
+            }
+        };
+
+        // The script holds the GIL while it runs, but lets other threads have it every so often.
+        // The lock can't be held while waiting for it, since the script's thread needs the
+        // lock to finish up a call, and it'll have the GIL while it does that.
+        drop(state);
+        let gil = Python::acquire_gil();
+        state = dog.state.lock().unwrap();
+        if let Some(w) = state.watch.as_mut().filter(|w| w.call == call) {
+            unsafe { ffi::PyThreadState_SetAsyncExc(thread, ffi::PyExc_TimeoutError) };
+            w.fired = true;
+        }
+        drop(gil);
+    }
+}
+
+/// Loads a level's script and times every call into it.
+pub struct Sandbox {
+    script_path: PathBuf,
+    limits: ScriptLimits,
+    watchdog: Arc<Watchdog>,
+    calls: Cell<u64>,
+}
+
+impl Sandbox {
+    pub fn new(script_path: PathBuf, limits: ScriptLimits) -> Self {
+        let watchdog = Arc::new(Watchdog::default());
+        let dog = watchdog.clone();
+        std::thread::Builder::new()
+            .name("script watchdog".to_string())
+            .spawn(move || watch(dog))
+            .expect("couldn't start the script watchdog");
+
+        Self {
+            script_path,
+            limits,
+            watchdog,
+            calls: Cell::new(0),
+        }
+    }
+
+    pub fn limits(&self) -> &ScriptLimits {
+        &self.limits
+    }
+
+    /// Runs the script's source as a new module with this name,
+    /// which can then be found with `PyModule::import`.
+    pub fn load_module<'p>(&self, py: Python<'p>, name: &str, src: &str) -> PyResult<&'p PyModule> {
+        let path = self.script_path.display().to_string();
+        let module = PyModule::new(py, name)?;
+
+        let sandboxed_builtins = PyModule::from_code(py, SANDBOX, "sandbox.py", "level_sandbox")?
+            .call1(
+            "sandboxed_builtins",
+            (self.limits.allowed_imports.clone(), path.clone()),
+        )?;
+        module.add("__builtins__", sandboxed_builtins.to_object(py))?;
+        module.add("__file__", path.clone())?;
+        py.import("sys")?.get("modules")?.set_item(name, module)?;
+
+        // compiling it separately gives the tracebacks the right filename.
+        let builtins = py.import("builtins")?;
+        let code = builtins.call1("compile", (src, path, "exec"))?;
+        let limit = self.limits.load();
+        self.run(py, "loading the script", limit, || {
+            builtins
+                .call1("exec", (code.to_object(py), module.dict().to_object(py)))
+                .map(|_| ())
+        })?;
+
+        Ok(module)
+    }
+
+    /// Calls `f`, interrupting whatever Python it's running if it takes longer than `limit`.
+    /// `what` describes the call, for the error logged if that happens.
+    pub fn run<'p, R>(
+        &self,
+        py: Python<'p>,
+        what: &str,
+        limit: Duration,
+        f: impl FnOnce() -> PyResult<R>,
+    ) -> PyResult<R> {
+        let thread = py
+            .import("threading")?
+            .call0("get_ident")?
+            .extract::<i64>()? as c_long;
+        let call = self.calls.get() + 1;
+        self.calls.set(call);
+        {
+            let mut state = self.watchdog.state.lock().unwrap();
+            state.watch = Some(Watch {
+                call,
+                thread,
+                deadline: Instant::now() + limit,
+                fired: false,
+            });
+            self.watchdog.wake.notify_one();
+        }
+
+        let output = f();
+
+        let fired = self
+            .watchdog
+            .state
+            .lock()
+            .unwrap()
+            .watch
+            .take()
+            .map(|w| w.fired)
+            .unwrap_or(false);
+        if fired {
+            // in case it went off too late to interrupt anything,
+            // so that it doesn't go off in the next call instead.
+            unsafe { ffi::PyThreadState_SetAsyncExc(thread, std::ptr::null_mut()) };
+            log::error!(
+                "{} was interrupted: {} took longer than {:?}",
+                self.script_path.display(),
+                what,
+                limit
+            );
+        }
+
+        output
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        self.watchdog.state.lock().unwrap().shutdown = true;
+        self.watchdog.wake.notify_one();
+    }
+}
@@ -0,0 +1,192 @@
+//! The `world` object a level's script can use to look at and change the game.
+//!
+//! ```python
+//! def on_player_join(player):
+//!     for goblin in world.entities_near(world.get(player, "Pos").iso.loc, 10.0):
+//!         if world.get(goblin, "Chaser") is not None:
+//!             world.set(goblin, Speed(0.5))
+//! ```
+//!
+//! Entities are passed around as the same ids the hooks are given.
+//! Changes are made the same way the actions hooks return are carried out,
+//! so they're only visible once the script has finished running.
+use super::level::despawn;
+use super::py_level::ComponentFactoryRegistry;
+use super::MapOrigin;
+use comn::prelude::*;
+use comn::specs::{Entities, LazyUpdate, World, WorldExt};
+use pyo3::exceptions::{RuntimeError, ValueError};
+use pyo3::prelude::*;
+use specs::{Entity, Join};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+#[pyclass(name=World)]
+pub struct ScriptWorld {
+    /// Only set while the level's script is running, by `ScriptWorld::enter`.
+    world: *const World,
+    registry: Rc<ComponentFactoryRegistry>,
+    /// Entities created since `take_spawned` was last called.
+    spawned: RefCell<Vec<Entity>>,
+    /// Entities given new components since `take_changed` was last called.
+    changed: RefCell<Vec<Entity>>,
+}
+
+/// Clears the ScriptWorld's pointer to the World when it's dropped,
+/// so that it's cleared even if the script's function panics.
+struct Entered<'h, 'p> {
+    handle: &'h mut Py<ScriptWorld>,
+    py: Python<'p>,
+}
+
+impl Drop for Entered<'_, '_> {
+    fn drop(&mut self) {
+        self.handle.as_mut(self.py).world = std::ptr::null();
+    }
+}
+
+impl ScriptWorld {
+    pub fn new(registry: Rc<ComponentFactoryRegistry>) -> Self {
+        Self {
+            world: std::ptr::null(),
+            registry,
+            spawned: RefCell::new(Vec::new()),
+            changed: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Lets the script use the World for as long as `f` runs.
+    pub fn enter<'p, R>(
+        handle: &mut Py<Self>,
+        py: Python<'p>,
+        world: &World,
+        f: impl FnOnce() -> R,
+    ) -> R {
+        handle.as_mut(py).world = world;
+        let _entered = Entered { handle, py };
+        f()
+    }
+
+    /// The entities the script has created, which the players haven't been told about yet.
+    pub fn take_spawned(&mut self) -> Vec<Entity> {
+        std::mem::replace(self.spawned.get_mut(), Vec::new())
+    }
+
+    /// The entities the script has inserted components into,
+    /// which the players haven't been told about yet.
+    pub fn take_changed(&mut self) -> Vec<Entity> {
+        std::mem::replace(self.changed.get_mut(), Vec::new())
+    }
+
+    /// Creates an entity with these components.
+    pub fn spawn_entity<'p>(
+        &self,
+        py: Python<'p>,
+        world: &World,
+        components: &[PyObject],
+    ) -> Result<Entity, String> {
+        let ent = world.entities().create();
+        self.spawned.borrow_mut().push(ent);
+        self.registry
+            .insert_all(py, components, &world.read_resource::<LazyUpdate>(), ent)?;
+        Ok(ent)
+    }
+
+    /// Adds these components to an existing entity, replacing any of the same type.
+    pub fn insert_components<'p>(
+        &self,
+        py: Python<'p>,
+        world: &World,
+        id: u32,
+        components: &[PyObject],
+    ) -> Result<(), String> {
+        let ent = alive(&world.entities(), id)?;
+        self.changed.borrow_mut().push(ent);
+        self.registry
+            .insert_all(py, components, &world.read_resource::<LazyUpdate>(), ent)
+    }
+
+    /// Kills an entity, and tells the players it's gone.
+    pub fn despawn_entity(world: &World, id: u32) -> Result<(), String> {
+        despawn(world, alive(&world.entities(), id)?);
+        Ok(())
+    }
+
+    fn world(&self) -> PyResult<&World> {
+        if self.world.is_null() {
+            Err(RuntimeError::py_err(
+                "the world can only be used while load_map or a hook is running",
+            ))
+        } else {
+            // enter only sets the pointer for as long as the World is borrowed,
+            // and the script can only reach this while enter is running.
+            Ok(unsafe { &*self.world })
+        }
+    }
+}
+
+fn alive(ents: &Entities, id: u32) -> Result<Entity, String> {
+    let ent = ents.entity(id);
+    if ents.is_alive(ent) {
+        Ok(ent)
+    } else {
+        Err(format!("entity {} doesn't exist", id))
+    }
+}
+
+#[pymethods]
+impl ScriptWorld {
+    /// Creates a new entity with these components, returning its id.
+    fn spawn(&self, py: Python, components: Vec<PyObject>) -> PyResult<u32> {
+        let world = self.world()?;
+        self.spawn_entity(py, world, &components)
+            .map(|ent| ent.id())
+            .map_err(ValueError::py_err)
+    }
+
+    /// Removes an entity from the game.
+    fn despawn(&self, entity: u32) -> PyResult<()> {
+        Self::despawn_entity(self.world()?, entity).map_err(ValueError::py_err)
+    }
+
+    /// Adds a component to an entity, replacing any it already has of the same type.
+    fn set(&self, py: Python, entity: u32, component: PyObject) -> PyResult<()> {
+        let world = self.world()?;
+        self.insert_components(py, world, entity, &[component])
+            .map_err(ValueError::py_err)
+    }
+
+    /// A copy of the entity's component with this name, i.e. "Health",
+    /// or None if it doesn't have one.
+    fn get(&self, py: Python, entity: u32, component: &str) -> PyResult<Option<PyObject>> {
+        let world = self.world()?;
+        let ent = alive(&world.entities(), entity).map_err(ValueError::py_err)?;
+        self.registry
+            .py_get(py, world, ent, component)
+            .map_err(ValueError::py_err)
+    }
+
+    /// The entities made from map entries with this class.
+    fn entities_with_class(&self, class: &str) -> PyResult<Vec<u32>> {
+        let world = self.world()?;
+        Ok((&world.entities(), &world.read_storage::<MapOrigin>())
+            .join()
+            .filter(|(_, origin)| origin.classes.iter().any(|c| c == class))
+            .map(|(ent, _)| ent.id())
+            .collect())
+    }
+
+    /// The entities whose positions are within `radius` of `pos`.
+    fn entities_near(&self, pos: Vec<f32>, radius: f32) -> PyResult<Vec<u32>> {
+        let world = self.world()?;
+        if pos.len() < 2 {
+            return Err(ValueError::py_err("expected a position like [x, y]"));
+        }
+        let center = Vec2::new(pos[0], pos[1]);
+        Ok((&world.entities(), &world.read_storage::<Pos>())
+            .join()
+            .filter(|(_, Pos { iso })| (iso.translation.vector - center).norm() <= radius)
+            .map(|(ent, _)| ent.id())
+            .collect())
+    }
+}
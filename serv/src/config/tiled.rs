@@ -0,0 +1,498 @@
+//! Reads a level's map from a `map.tmj`, a map saved in the JSON format of the Tiled editor,
+//! instead of from a map.json.
+//!
+//! Every tile in a tile layer becomes an entry, named i.e. "Floor/3,7" after its layer and
+//! column and row. Its appearance is the tile's `appearance` property, or else its type
+//! (called its class since Tiled 1.9). If the layer has a `solid` property set to true,
+//! its tiles are walls: neighboring tiles are merged into as few rectangles as possible,
+//! and each rectangle's hitbox is given to the entry for the tile in its top left corner.
+//!
+//! Every object in an object layer becomes an entry too, named after the object.
+//! Its type is its first class, and every boolean property it has set to true is another,
+//! as is each name in a comma separated `classes` property. Tile objects take their appearance
+//! from their tile, and any other object needs an `appearance` property. Rectangles and tile
+//! objects with a `solid` property set to true are given a hitbox as big as they are.
+//!
+//! Either kind of layer can have a `classes` property, which every entry from it is given,
+//! and an `elevation` property, which each is raised by. Objects can have their own.
+//! A map's `tile_size` property says how wide each tile is in the game; it's 1 if left out.
+//! Tiled's y axis points down, so it's flipped.
+//!
+//! Tilesets can be embedded in the map or saved as `.tsj` files next to it.
+//! Infinite maps and compressed or base64 encoded layers aren't supported.
+use super::MapEntry;
+use serde::Deserialize;
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+/// The bits of a tile's id that say whether it's flipped, which don't matter here.
+const FLIP_FLAGS: u32 = 0xF000_0000;
+
+#[derive(Deserialize, Debug, Default)]
+struct Property {
+    name: String,
+    value: Value,
+}
+
+fn property<'a>(properties: &'a [Property], name: &str) -> Option<&'a Value> {
+    properties.iter().find(|p| p.name == name).map(|p| &p.value)
+}
+
+fn is_set(properties: &[Property], name: &str) -> bool {
+    property(properties, name)
+        .and_then(Value::as_bool)
+        .unwrap_or(false)
+}
+
+/// The classes named in a `classes` property.
+fn listed_classes(properties: &[Property]) -> impl Iterator<Item = String> + '_ {
+    property(properties, "classes")
+        .and_then(Value::as_str)
+        .unwrap_or("")
+        .split(',')
+        .map(str::trim)
+        .filter(|class| !class.is_empty())
+        .map(str::to_string)
+}
+
+fn elevation(properties: &[Property]) -> f32 {
+    property(properties, "elevation")
+        .and_then(Value::as_f64)
+        .unwrap_or(0.0) as f32
+}
+
+#[derive(Deserialize, Debug)]
+struct Tile {
+    id: u32,
+    #[serde(default, rename = "type", alias = "class")]
+    kind: String,
+    #[serde(default)]
+    properties: Vec<Property>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct Tileset {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    tiles: Vec<Tile>,
+}
+
+#[derive(Deserialize, Debug)]
+struct TilesetRef {
+    firstgid: u32,
+    /// Where the tileset is saved, if it isn't embedded in the map.
+    source: Option<String>,
+    #[serde(flatten)]
+    tileset: Tileset,
+}
+
+#[derive(Deserialize, Debug)]
+struct Object {
+    id: u32,
+    #[serde(default)]
+    name: String,
+    #[serde(default, rename = "type", alias = "class")]
+    kind: String,
+    x: f32,
+    y: f32,
+    #[serde(default)]
+    width: f32,
+    #[serde(default)]
+    height: f32,
+    /// In degrees, clockwise.
+    #[serde(default)]
+    rotation: f32,
+    /// Set if this is a tile object.
+    gid: Option<u32>,
+    #[serde(default)]
+    point: bool,
+    #[serde(default)]
+    properties: Vec<Property>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Layer {
+    TileLayer {
+        name: String,
+        /// A list of tile ids, unless the layer is encoded.
+        #[serde(default)]
+        data: Value,
+        encoding: Option<String>,
+        #[serde(default)]
+        properties: Vec<Property>,
+    },
+    ObjectGroup {
+        name: String,
+        #[serde(default)]
+        objects: Vec<Object>,
+        #[serde(default)]
+        properties: Vec<Property>,
+    },
+    Group {
+        #[serde(default)]
+        layers: Vec<Layer>,
+    },
+    ImageLayer {},
+}
+
+#[derive(Deserialize, Debug)]
+struct TiledMap {
+    width: usize,
+    height: usize,
+    tilewidth: f32,
+    tileheight: f32,
+    #[serde(default)]
+    infinite: bool,
+    layers: Vec<Layer>,
+    #[serde(default)]
+    tilesets: Vec<TilesetRef>,
+    #[serde(default)]
+    properties: Vec<Property>,
+}
+
+/// A map that's been read in, along with its tilesets, ready to be turned into entries.
+struct Importer {
+    map: TiledMap,
+    /// How wide each tile is in the game.
+    tile_size: f32,
+}
+
+impl Importer {
+    /// Where a point `x` and `y` pixels from the map's top left corner is in the game.
+    fn world(&self, x: f32, y: f32) -> [f32; 2] {
+        [
+            x / self.map.tilewidth * self.tile_size,
+            -y / self.map.tileheight * self.tile_size,
+        ]
+    }
+
+    /// How big something `w` by `h` pixels is in the game.
+    fn extents(&self, w: f32, h: f32) -> [f32; 2] {
+        [
+            w / self.map.tilewidth * self.tile_size,
+            h / self.map.tileheight * self.tile_size,
+        ]
+    }
+
+    /// The appearance of the tile with this global id.
+    fn appearance_of(&self, gid: u32) -> Result<String, String> {
+        let gid = gid & !FLIP_FLAGS;
+        let tileset_ref = self
+            .map
+            .tilesets
+            .iter()
+            .filter(|t| t.firstgid <= gid)
+            .max_by_key(|t| t.firstgid)
+            .ok_or_else(|| format!("no tileset has tile #{}", gid))?;
+        let tileset = &tileset_ref.tileset;
+        let id = gid - tileset_ref.firstgid;
+
+        let tile = tileset.tiles.iter().find(|t| t.id == id);
+        tile.and_then(|t| property(&t.properties, "appearance"))
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .or_else(|| tile.map(|t| t.kind.clone()).filter(|k| !k.is_empty()))
+            .ok_or_else(|| {
+                format!(
+                    "tile {} of tileset {:?} has no appearance property or type",
+                    id, tileset.name
+                )
+            })
+    }
+
+    fn entries(&self) -> Result<Vec<MapEntry>, String> {
+        let mut entries = Vec::new();
+        for layer in &self.map.layers {
+            self.add_layer(layer, &mut entries)?;
+        }
+        Ok(entries)
+    }
+
+    fn add_layer(&self, layer: &Layer, entries: &mut Vec<MapEntry>) -> Result<(), String> {
+        match layer {
+            Layer::TileLayer {
+                name,
+                data,
+                encoding,
+                properties,
+            } => {
+                if let Some(encoding) = encoding.as_ref().filter(|e| *e != "csv") {
+                    return Err(format!(
+                        "layer {:?} is {} encoded; save it as CSV instead",
+                        name, encoding
+                    ));
+                }
+                let tiles: Vec<u32> = serde_json::from_value(data.clone())
+                    .map_err(|e| format!("layer {:?} has malformed tiles: {}", name, e))?;
+                if tiles.len() != self.map.width * self.map.height {
+                    return Err(format!(
+                        "layer {:?} has {} tiles, expected {} by {}",
+                        name,
+                        tiles.len(),
+                        self.map.width,
+                        self.map.height
+                    ));
+                }
+                entries.append(&mut self.tile_entries(name, &tiles, properties)?);
+            }
+            Layer::ObjectGroup {
+                name,
+                objects,
+                properties,
+            } => {
+                for object in objects {
+                    let mut entry = self
+                        .object_entry(name, object)
+                        .map_err(|e| format!("object {} in layer {:?}: {}", object.id, name, e))?;
+                    entry.classes.extend(listed_classes(properties));
+                    entry.location[2] += elevation(properties);
+                    entries.push(entry);
+                }
+            }
+            Layer::Group { layers } => {
+                for layer in layers {
+                    self.add_layer(layer, entries)?;
+                }
+            }
+            Layer::ImageLayer {} => {}
+        }
+        Ok(())
+    }
+
+    fn tile_entries(
+        &self,
+        layer: &str,
+        tiles: &[u32],
+        properties: &[Property],
+    ) -> Result<Vec<MapEntry>, String> {
+        let width = self.map.width;
+        let classes: Vec<String> = listed_classes(properties).collect();
+        let z = elevation(properties);
+
+        let mut entries = Vec::new();
+        // which entry each tile became, if it isn't empty.
+        let mut at = vec![None; tiles.len()];
+        for (index, &gid) in tiles.iter().enumerate().filter(|(_, gid)| **gid != 0) {
+            let (col, row) = (index % width, index / width);
+            let [x, y] = self.world(
+                (col as f32 + 0.5) * self.map.tilewidth,
+                (row as f32 + 0.5) * self.map.tileheight,
+            );
+            at[index] = Some(entries.len());
+            entries.push(MapEntry::new(
+                format!("{}/{},{}", layer, col, row),
+                classes.clone(),
+                [x, y, z],
+                self.appearance_of(gid)?,
+            ));
+        }
+
+        if is_set(properties, "solid") {
+            let solid: Vec<bool> = tiles.iter().map(|&gid| gid != 0).collect();
+            for (col, row, w, h) in merge_rects(&solid, width) {
+                let corner = &mut entries[at[row * width + col].expect("solid tiles aren't empty")];
+                let [w, h] = [w as f32 * self.tile_size, h as f32 * self.tile_size];
+                corner.hitbox_dimensions = Some([w, h, 0.0]);
+                // from the center of the corner tile to the center of the rectangle.
+                corner.hitbox_offset =
+                    Some([(w - self.tile_size) / 2.0, -(h - self.tile_size) / 2.0, 0.0]);
+            }
+        }
+
+        Ok(entries)
+    }
+
+    fn object_entry(&self, layer: &str, object: &Object) -> Result<MapEntry, String> {
+        let name = if object.name.is_empty() {
+            format!("{}/{}", layer, object.id)
+        } else {
+            object.name.clone()
+        };
+
+        let mut classes: Vec<String> = Some(object.kind.clone())
+            .filter(|kind| !kind.is_empty())
+            .into_iter()
+            .chain(
+                object
+                    .properties
+                    .iter()
+                    .filter(|p| p.name != "solid" && p.value == Value::Bool(true))
+                    .map(|p| p.name.clone()),
+            )
+            .collect();
+        classes.extend(listed_classes(&object.properties));
+
+        let appearance = match property(&object.properties, "appearance").and_then(Value::as_str) {
+            Some(appearance) => appearance.to_string(),
+            None => match object.gid {
+                Some(gid) => self.appearance_of(gid)?,
+                None => return Err("it needs an appearance property".to_string()),
+            },
+        };
+
+        // Tiled puts rectangles by their top left corner, and tile objects by their bottom left,
+        // and rotates them around that corner.
+        let (dx, dy) = match (object.point, object.gid) {
+            (true, _) => (0.0, 0.0),
+            (false, Some(_)) => (object.width / 2.0, -object.height / 2.0),
+            (false, None) => (object.width / 2.0, object.height / 2.0),
+        };
+        let (sin, cos) = object.rotation.to_radians().sin_cos();
+        let [x, y] = self.world(
+            object.x + dx * cos - dy * sin,
+            object.y + dx * sin + dy * cos,
+        );
+
+        let mut entry = MapEntry::new(
+            name,
+            classes,
+            [x, y, elevation(&object.properties)],
+            appearance,
+        );
+        // clockwise in Tiled is clockwise in the game too, once the y axis is flipped.
+        entry.z_rotation = -object.rotation.to_radians();
+        if !object.point && is_set(&object.properties, "solid") {
+            let [w, h] = self.extents(object.width, object.height);
+            entry.hitbox_dimensions = Some([w, h, 0.0]);
+        }
+        Ok(entry)
+    }
+}
+
+/// Covers the cells in a grid this many cells wide that are set with as few rectangles
+/// as it can find, returning the column and row of each one's top left corner,
+/// then its width and height.
+fn merge_rects(cells: &[bool], width: usize) -> Vec<(usize, usize, usize, usize)> {
+    let height = cells.len() / width;
+    let mut covered = vec![false; cells.len()];
+    let free = |covered: &[bool], col: usize, row: usize| {
+        cells[row * width + col] && !covered[row * width + col]
+    };
+
+    let mut rects = Vec::new();
+    for row in 0..height {
+        for col in 0..width {
+            if !free(&covered, col, row) {
+                continue;
+            }
+            let w = (col..width).take_while(|&c| free(&covered, c, row)).count();
+            let h = (row..height)
+                .take_while(|&r| (col..col + w).all(|c| free(&covered, c, r)))
+                .count();
+
+            for r in row..row + h {
+                for c in col..col + w {
+                    covered[r * width + c] = true;
+                }
+            }
+            rects.push((col, row, w, h));
+        }
+    }
+    rects
+}
+
+/// Reads in a Tiled map, along with any tilesets saved next to it,
+/// and turns its layers into map entries.
+pub fn read(path: &Path) -> Result<Vec<MapEntry>, String> {
+    let json =
+        fs::read_to_string(path).map_err(|e| format!("couldn't read {}: {}", path.display(), e))?;
+    let mut map: TiledMap = serde_json::from_str(&json)
+        .map_err(|e| format!("{} is malformed: {}", path.display(), e))?;
+
+    for tileset_ref in map.tilesets.iter_mut() {
+        if let Some(source) = &tileset_ref.source {
+            let tileset_path = path.with_file_name(source);
+            let json = fs::read_to_string(&tileset_path).map_err(|e| {
+                format!(
+                    "couldn't read tileset {} (only .tsj tilesets can be used): {}",
+                    tileset_path.display(),
+                    e
+                )
+            })?;
+            tileset_ref.tileset = serde_json::from_str(&json)
+                .map_err(|e| format!("{} is malformed: {}", tileset_path.display(), e))?;
+        }
+    }
+
+    import(map).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+fn import(map: TiledMap) -> Result<Vec<MapEntry>, String> {
+    if map.infinite {
+        return Err("infinite maps can't be loaded; turn off \"Infinite\" in Tiled".to_string());
+    }
+    let tile_size = property(&map.properties, "tile_size")
+        .and_then(Value::as_f64)
+        .unwrap_or(1.0) as f32;
+
+    Importer { map, tile_size }.entries()
+}
+
+#[test]
+fn test_merge_rects() {
+    #[rustfmt::skip]
+    let cells = [
+        1, 1, 1, 0,
+        1, 1, 1, 0,
+        1, 0, 0, 1,
+    ].iter().map(|&c| c == 1).collect::<Vec<_>>();
+
+    assert_eq!(
+        merge_rects(&cells, 4),
+        vec![(0, 0, 3, 2), (0, 2, 1, 1), (3, 2, 1, 1)]
+    );
+}
+
+#[test]
+fn test_tiled_import() {
+    let map: TiledMap = serde_json::from_str(
+        r#"{
+            "width": 2, "height": 2, "tilewidth": 32, "tileheight": 32,
+            "properties": [{ "name": "tile_size", "type": "float", "value": 2.0 }],
+            "tilesets": [{
+                "firstgid": 1, "name": "terrain",
+                "tiles": [
+                    { "id": 0, "type": "StoneWall" },
+                    { "id": 1, "properties": [
+                        { "name": "appearance", "type": "string", "value": "Skeleton" }
+                    ] }
+                ]
+            }],
+            "layers": [
+                {
+                    "type": "tilelayer", "name": "Walls", "data": [1, 1, 0, 1],
+                    "properties": [{ "name": "solid", "type": "bool", "value": true }]
+                },
+                {
+                    "type": "objectgroup", "name": "Enemies",
+                    "objects": [{
+                        "id": 7, "type": "enemy", "x": 32, "y": 64, "width": 32, "height": 32,
+                        "gid": 2,
+                        "properties": [{ "name": "boss", "type": "bool", "value": true }]
+                    }]
+                }
+            ]
+        }"#,
+    )
+    .unwrap();
+    let entries = import(map).unwrap();
+    assert_eq!(entries.len(), 4);
+
+    let corner = &entries[0];
+    assert_eq!(corner.name, "Walls/0,0");
+    assert_eq!(corner.appearance, "StoneWall");
+    assert_eq!(corner.location, [1.0, -1.0, 0.0]);
+    assert_eq!(corner.hitbox_dimensions, Some([4.0, 2.0, 0.0]));
+    assert_eq!(corner.hitbox_offset, Some([1.0, 0.0, 0.0]));
+    assert_eq!(entries[1].hitbox_dimensions, None);
+    assert_eq!(entries[2].hitbox_dimensions, Some([2.0, 2.0, 0.0]));
+
+    let skeleton = &entries[3];
+    assert_eq!(skeleton.name, "Enemies/7");
+    assert_eq!(skeleton.appearance, "Skeleton");
+    assert_eq!(skeleton.classes, vec!["enemy", "boss"]);
+    assert_eq!(skeleton.location, [3.0, -3.0, 0.0]);
+}
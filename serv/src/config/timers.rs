@@ -0,0 +1,230 @@
+//! Lets a level's script schedule functions to be called later on.
+//!
+//! ```python
+//! def on_entity_death(entity, name, classes):
+//!     if "boss" in classes:
+//!         taunts = every(5, lambda: [Notice("You'll pay for that!")])
+//!         after(30, taunts.cancel)
+//! ```
+//!
+//! `after(seconds, fn)` calls `fn` once, and `every(seconds, fn)` calls it over and over,
+//! until its handle is cancelled. Like the hooks, `fn` can return a list
+//! of actions. Time is counted in server ticks rather than read off of a clock,
+//! so a level plays out the same way no matter how long each tick actually takes.
+//!
+//! Rhai scripts have the same two functions, which take a function pointer,
+//! i.e. `after(30, Fn("taunt"))` or `every(5, || [Notice("You'll pay for that!")])`.
+#[cfg(feature = "python")]
+use pyo3::exceptions::{TypeError, ValueError};
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+#[cfg(feature = "python")]
+use std::cell::RefCell;
+use std::collections::HashSet;
+#[cfg(feature = "python")]
+use std::rc::Rc;
+
+/// A function the script has scheduled; the callback is a PyObject for Python scripts,
+/// or a FnPtr for Rhai ones.
+pub struct Timer<C> {
+    id: u64,
+    /// The tick on which the callback should next be called.
+    due: u64,
+    /// How many ticks to wait between calls, if the callback should be called repeatedly.
+    period: Option<u64>,
+    pub callback: C,
+}
+
+/// Every timer that the script has scheduled and hasn't been called or cancelled yet.
+pub struct TimerQueue<C> {
+    tick_rate: f32,
+    /// The last tick given to `advance`.
+    now: u64,
+    next_id: u64,
+    timers: Vec<Timer<C>>,
+    /// Timers cancelled since the last call to `advance`,
+    /// in case one is cancelled while its own callback is running.
+    cancelled: HashSet<u64>,
+}
+
+impl<C> TimerQueue<C> {
+    pub fn new(tick_rate: f32) -> Self {
+        Self {
+            tick_rate,
+            now: 0,
+            next_id: 0,
+            timers: Vec::new(),
+            cancelled: HashSet::new(),
+        }
+    }
+
+    /// Which tick it is, and how many happen per second,
+    /// used to convert the times scripts give.
+    pub fn set_clock(&mut self, now: u64, tick_rate: f32) {
+        self.now = now;
+        self.tick_rate = tick_rate;
+    }
+
+    /// How many ticks it takes for this many seconds to pass; at least one.
+    fn ticks(&self, seconds: f32) -> Result<u64, String> {
+        if !seconds.is_finite() || seconds < 0.0 {
+            return Err(format!(
+                "expected a positive number of seconds, found {}",
+                seconds
+            ));
+        }
+        Ok(((seconds * self.tick_rate).round() as u64).max(1))
+    }
+
+    /// Calls `callback` in `seconds`, and then every `seconds` after that if it should `repeat`,
+    /// returning an id it can be cancelled with.
+    pub fn schedule(&mut self, seconds: f32, repeat: bool, callback: C) -> Result<u64, String> {
+        let ticks = self.ticks(seconds)?;
+        let id = self.next_id;
+        self.next_id += 1;
+        self.timers.push(Timer {
+            id,
+            due: self.now + ticks,
+            period: if repeat { Some(ticks) } else { None },
+            callback,
+        });
+        Ok(id)
+    }
+
+    pub fn cancel(&mut self, id: u64) {
+        self.timers.retain(|t| t.id != id);
+        self.cancelled.insert(id);
+    }
+
+    /// Removes and returns the timers that are due by this tick,
+    /// in the order they were meant to go off.
+    pub fn advance(&mut self, tick: u64) -> Vec<Timer<C>> {
+        self.now = tick;
+        self.cancelled.clear();
+
+        let (mut due, waiting) = std::mem::replace(&mut self.timers, Vec::new())
+            .into_iter()
+            .partition::<Vec<_>, _>(|t| t.due <= tick);
+        self.timers = waiting;
+        due.sort_by_key(|t| (t.due, t.id));
+        due
+    }
+
+    /// Whether a timer returned by `advance` has been cancelled since,
+    /// i.e. by the callback of another timer that was due on the same tick.
+    pub fn is_cancelled(&self, timer: &Timer<C>) -> bool {
+        self.cancelled.contains(&timer.id)
+    }
+
+    /// Puts a timer returned by `advance` back in the queue,
+    /// if it's meant to be called again and it hasn't been cancelled.
+    pub fn rearm(&mut self, mut timer: Timer<C>) {
+        if let Some(period) = timer.period {
+            if !self.cancelled.contains(&timer.id) {
+                timer.due += period;
+                self.timers.push(timer);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "python")]
+#[pyclass]
+/// Returned by `after` and `every`, so that the timer can be stopped.
+pub struct TimerHandle {
+    id: u64,
+    queue: Rc<RefCell<TimerQueue<PyObject>>>,
+}
+#[cfg(feature = "python")]
+#[pymethods]
+impl TimerHandle {
+    /// Makes sure the callback isn't called again.
+    fn cancel(&self) {
+        self.queue.borrow_mut().cancel(self.id);
+    }
+}
+
+#[cfg(feature = "python")]
+#[pyclass]
+/// Owns the script's `after` and `every` functions.
+pub struct Scheduler {
+    queue: Rc<RefCell<TimerQueue<PyObject>>>,
+}
+#[cfg(feature = "python")]
+impl Scheduler {
+    pub fn new(queue: Rc<RefCell<TimerQueue<PyObject>>>) -> Self {
+        Self { queue }
+    }
+
+    fn schedule(
+        &self,
+        py: Python,
+        seconds: f32,
+        repeat: bool,
+        callback: PyObject,
+    ) -> PyResult<Py<TimerHandle>> {
+        if !callback.as_ref(py).is_callable() {
+            return Err(TypeError::py_err("expected a function to call"));
+        }
+        let id = self
+            .queue
+            .borrow_mut()
+            .schedule(seconds, repeat, callback)
+            .map_err(ValueError::py_err)?;
+        Py::new(
+            py,
+            TimerHandle {
+                id,
+                queue: self.queue.clone(),
+            },
+        )
+    }
+}
+#[cfg(feature = "python")]
+#[pymethods]
+impl Scheduler {
+    /// Calls `callback` once, `seconds` from now.
+    fn after(&self, py: Python, seconds: f32, callback: PyObject) -> PyResult<Py<TimerHandle>> {
+        self.schedule(py, seconds, false, callback)
+    }
+
+    /// Calls `callback` every `seconds`, starting `seconds` from now.
+    fn every(&self, py: Python, seconds: f32, callback: PyObject) -> PyResult<Py<TimerHandle>> {
+        self.schedule(py, seconds, true, callback)
+    }
+}
+
+#[test]
+fn test_timer_queue() {
+    let mut queue = TimerQueue::new(10.0);
+    let once = queue.schedule(0.5, false, "once").unwrap();
+    let every = queue.schedule(0.2, true, "every").unwrap();
+    assert!(queue.schedule(-1.0, false, "never").is_err());
+    // even no time at all waits for the next tick.
+    queue.schedule(0.0, false, "soon").unwrap();
+
+    let names = |due: &[Timer<&'static str>]| due.iter().map(|t| t.callback).collect::<Vec<_>>();
+    let due = queue.advance(1);
+    assert_eq!(names(&due), vec!["soon"]);
+    assert!(queue.advance(1).is_empty());
+
+    let due = queue.advance(2);
+    assert_eq!(names(&due), vec!["every"]);
+    for timer in due {
+        queue.rearm(timer);
+    }
+    assert!(queue.advance(3).is_empty());
+
+    // missed ticks are caught up on, in the order the timers were meant to go off.
+    let due = queue.advance(6);
+    assert_eq!(names(&due), vec!["every", "once"]);
+    assert_eq!(due[1].id, once);
+
+    // cancelling a timer that was already taken out of the queue keeps it from being rearmed.
+    queue.cancel(every);
+    assert!(due.iter().all(|t| queue.is_cancelled(t) == (t.id == every)));
+    for timer in due {
+        queue.rearm(timer);
+    }
+    assert!(queue.advance(100).is_empty());
+}
@@ -0,0 +1,423 @@
+//! Checks levels for mistakes before anyone tries to play them.
+//!
+//! Some problems (like a typo in an appearance name) would otherwise only show up
+//! as a crash halfway through loading a map, and others (like a typo in a class name)
+//! wouldn't show up at all; the entry would just quietly lack whatever behavior
+//! that class was supposed to give it.
+use super::{gltf, tiled, Level, MapEntry, Prefabs, ServerConfig};
+use comn::art::AppearanceRecord;
+use std::{collections::HashSet, fmt, fs, path::Path};
+
+fn read_entries(path: &Path) -> Result<Vec<MapEntry>, String> {
+    let json =
+        fs::read_to_string(path).map_err(|e| format!("couldn't read {}: {}", path.display(), e))?;
+    MapEntry::parse_all(&json).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// A map entry's name and classes, so that it can be found in the map;
+/// many entries can share a name, but far fewer share a name and classes.
+struct Entry<'a>(&'a str, &'a [String]);
+impl fmt::Display for Entry<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Entry(name, classes) = self;
+        write!(f, "{:?}", name)?;
+        if !classes.is_empty() {
+            write!(f, " (classes: {})", classes.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Problems with a particular entry have its name and classes as `entry` and `classes`.
+#[derive(Debug, PartialEq)]
+pub enum Problem {
+    /// The level couldn't be checked any further than this.
+    Fatal(String),
+    UnknownAppearance {
+        entry: String,
+        classes: Vec<String>,
+        appearance: String,
+    },
+    BadHitbox {
+        entry: String,
+        classes: Vec<String>,
+        dimensions: [f32; 3],
+    },
+    UnknownClass {
+        entry: String,
+        classes: Vec<String>,
+        class: String,
+    },
+    UnregisteredComponent {
+        /// The entry's name, or the entity's number if it can't be tied to an entry.
+        entity: String,
+        classes: Vec<String>,
+        component: String,
+    },
+    StairsToNowhere {
+        prefab: String,
+        to: String,
+    },
+    BadStartingItems {
+        class: String,
+        reason: String,
+    },
+}
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::Fatal(e) => write!(f, "{}", e),
+            Problem::UnknownAppearance {
+                entry,
+                classes,
+                appearance,
+            } => write!(
+                f,
+                "{} has appearance {:?}, which isn't in the appearance_record",
+                Entry(entry, classes),
+                appearance
+            ),
+            Problem::BadHitbox {
+                entry,
+                classes,
+                dimensions,
+            } => write!(
+                f,
+                "{} has hitbox dimensions {:?}, expected a positive width and height",
+                Entry(entry, classes),
+                dimensions
+            ),
+            Problem::UnknownClass {
+                entry,
+                classes,
+                class,
+            } => write!(
+                f,
+                "{} has class {:?}, which isn't in the script's CLASSES",
+                Entry(entry, classes),
+                class
+            ),
+            Problem::UnregisteredComponent {
+                entity,
+                classes,
+                component,
+            } => {
+                write!(f, "load_map gave {}", entity)?;
+                if !classes.is_empty() {
+                    write!(f, " (classes: {})", classes.join(", "))?;
+                }
+                write!(f, " a {}, which isn't a component", component)
+            }
+            Problem::StairsToNowhere { prefab, to } => write!(
+                f,
+                "prefab {:?} has stairs to {:?}, which isn't a level",
+                prefab, to
+            ),
+            Problem::BadStartingItems { class, reason } => write!(
+                f,
+                "class {:?} can't be given its starting items here: {}",
+                class, reason
+            ),
+        }
+    }
+}
+
+/// Everything wrong with a level.
+pub struct Report {
+    pub level: String,
+    pub entries: usize,
+    pub problems: Vec<Problem>,
+    /// Things that aren't wrong, but are worth knowing about.
+    pub notes: Vec<String>,
+}
+impl Report {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "level {}: {} map entries, {} problem(s)",
+            self.level,
+            self.entries,
+            self.problems.len()
+        )?;
+        for problem in self.problems.iter() {
+            write!(f, "\n  error: {}", problem)?;
+        }
+        for note in self.notes.iter() {
+            write!(f, "\n  note: {}", note)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks the level named in the config's map and script against
+/// the appearance_record and the components the server knows about.
+pub fn validate(config: &ServerConfig) -> Report {
+    let mut report = Report {
+        level: config.level.clone(),
+        entries: 0,
+        problems: Vec::new(),
+        notes: Vec::new(),
+    };
+    if let Err(e) = check_level(config, &mut report) {
+        report.problems.push(Problem::Fatal(e));
+    }
+
+    match unused_appearances(config) {
+        Ok(unused) => {
+            for (record, names) in unused {
+                report.notes.push(format!(
+                    "these appearances in {} aren't used by any level's map: {}",
+                    record,
+                    names.join(", ")
+                ));
+            }
+        }
+        Err(e) => report
+            .notes
+            .push(format!("couldn't check for unused appearances: {}", e)),
+    }
+
+    report
+}
+
+fn check_level(config: &ServerConfig, report: &mut Report) -> Result<(), String> {
+    let config = &config.for_level(&config.level)?;
+    let seed = config.seed_or_random();
+    report
+        .notes
+        .push(format!("the level's script was run with seed {}", seed));
+    let level = Level::from_name(
+        &config.levels_dir,
+        config.level.clone(),
+        &config.scripts,
+        seed,
+    )?;
+    let entries = level.map_entries()?;
+    report.entries = entries.len();
+
+    let known_classes = level.known_classes()?;
+    if known_classes.is_none() {
+        report.notes.push(format!(
+            "{}'s script has no CLASSES list, so classes can't be checked",
+            config.level
+        ));
+    }
+
+    report.problems.extend(check_entries(
+        &entries,
+        &config.appearance_record,
+        known_classes.as_ref().map(Vec::as_slice),
+    ));
+
+    let prefabs = Prefabs::load(&config.levels_dir, level.dir())?;
+    for (prefab, to) in prefabs.stairs() {
+        if !config.levels_dir.join(to).is_dir() {
+            report.problems.push(Problem::StairsToNowhere {
+                prefab: prefab.to_string(),
+                to: to.to_string(),
+            });
+        }
+    }
+    for (id, class) in config.classes.iter() {
+        if let Err(reason) = class.items(&prefabs, &config.appearance_record) {
+            report.problems.push(Problem::BadStartingItems {
+                class: id.clone(),
+                reason,
+            });
+        }
+    }
+
+    // the script is bound to choke on the problems above,
+    // and those error messages are more helpful than whatever it'd say.
+    if !report.is_ok() {
+        return Ok(());
+    }
+
+    for unregistered in level.unregistered_components(config)? {
+        // the entity's position is the best clue as to which entry it came from.
+        let from_entry = unregistered
+            .location
+            .and_then(|loc| entries.iter().find(|e| (e.loc() - loc).norm() < 0.001));
+        report.problems.push(Problem::UnregisteredComponent {
+            entity: match from_entry {
+                Some(entry) => format!("{:?}", entry.name),
+                None => format!("entity #{}", unregistered.entity),
+            },
+            classes: from_entry.map(|e| e.classes.clone()).unwrap_or_default(),
+            component: unregistered.component,
+        });
+    }
+
+    Ok(())
+}
+
+/// The problems each entry has on its own, without running the script.
+/// Classes are only checked if the script lists the ones it knows.
+fn check_entries(
+    entries: &[MapEntry],
+    appearance_record: &AppearanceRecord,
+    known_classes: Option<&[String]>,
+) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    for entry in entries.iter() {
+        if !appearance_record.names.contains(&entry.appearance) {
+            problems.push(Problem::UnknownAppearance {
+                entry: entry.name.clone(),
+                classes: entry.classes.clone(),
+                appearance: entry.appearance.clone(),
+            });
+        }
+
+        if let Some(dimensions) = entry.hitbox_dimensions {
+            if !dimensions[..2].iter().all(|d| d.is_finite() && *d > 0.0) {
+                problems.push(Problem::BadHitbox {
+                    entry: entry.name.clone(),
+                    classes: entry.classes.clone(),
+                    dimensions,
+                });
+            }
+        }
+
+        if let Some(known) = known_classes {
+            for class in entry.classes.iter().filter(|c| !known.contains(c)) {
+                problems.push(Problem::UnknownClass {
+                    entry: entry.name.clone(),
+                    classes: entry.classes.clone(),
+                    class: class.clone(),
+                });
+            }
+        }
+    }
+    problems
+}
+
+/// The names in each appearance_record that no level using it has in its maps or prefabs,
+/// along with where that appearance_record is from. Levels with an appearance_record
+/// in their level.toml use that one, and every other level uses the server config's.
+fn unused_appearances(config: &ServerConfig) -> Result<Vec<(String, Vec<String>)>, String> {
+    let mut levels = fs::read_dir(&config.levels_dir)
+        .map_err(|e| format!("couldn't read {}: {}", config.levels_dir.display(), e))?
+        .map(|level| level.map(|l| l.path()).map_err(|e| e.to_string()))
+        .collect::<Result<Vec<_>, String>>()?;
+    levels.sort();
+
+    let mut shared_used = HashSet::new();
+    let mut unused = Vec::new();
+    for level_dir in levels.iter().filter(|dir| dir.is_dir()) {
+        let name = level_dir
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| format!("{} isn't a level name", level_dir.display()))?;
+        let used = used_appearances(config, level_dir)?;
+
+        let level_config = config.for_level(name)?;
+        if level_config.level_config.appearance_record.is_some() {
+            let record = format!("{}'s level.toml", name);
+            unused.push((record, not_in(&level_config.appearance_record, &used)));
+        } else {
+            shared_used.extend(used);
+        }
+    }
+    let record = "the server config".to_string();
+    unused.insert(0, (record, not_in(&config.appearance_record, &shared_used)));
+
+    unused.retain(|(_, names)| !names.is_empty());
+    Ok(unused)
+}
+
+/// The names in the appearance_record that aren't in `used`.
+fn not_in(record: &AppearanceRecord, used: &HashSet<String>) -> Vec<String> {
+    record
+        .names
+        .iter()
+        .filter(|name| !used.contains(*name))
+        .cloned()
+        .collect()
+}
+
+/// Every appearance the level's maps or prefabs use.
+fn used_appearances(config: &ServerConfig, level_dir: &Path) -> Result<HashSet<String>, String> {
+    let mut used = HashSet::new();
+
+    let mut maps = vec![level_dir.join("map.json")];
+    // levels with a dungeon.toml are made out of these instead.
+    if let Ok(rooms) = fs::read_dir(level_dir.join("rooms")) {
+        maps.extend(
+            rooms
+                .filter_map(|room| room.ok())
+                .map(|room| room.path())
+                .filter(|path| path.extension().map_or(false, |ext| ext == "json")),
+        );
+    }
+    for map_path in maps.iter().filter(|path| path.exists()) {
+        used.extend(read_entries(map_path)?.into_iter().map(|e| e.appearance));
+    }
+    let tiled_path = level_dir.join("map.tmj");
+    if tiled_path.exists() {
+        used.extend(tiled::read(&tiled_path)?.into_iter().map(|e| e.appearance));
+    }
+    if let Some(scene_path) = gltf::scene_path(level_dir) {
+        used.extend(gltf::read(&scene_path)?.into_iter().map(|e| e.appearance));
+    }
+    let prefabs = Prefabs::load(&config.levels_dir, level_dir)?;
+    used.extend(prefabs.appearances().map(str::to_string));
+
+    Ok(used)
+}
+
+#[test]
+fn test_check_entries() {
+    let entry = |name: &str, classes: &[&str], appearance: &str| {
+        let classes = classes.iter().map(|c| c.to_string()).collect();
+        MapEntry::new(name.to_string(), classes, [0.0; 3], appearance.to_string())
+    };
+    let appearance_record = AppearanceRecord {
+        names: vec!["Wall".to_string(), "Skeleton".to_string()],
+    };
+    let known = vec!["door".to_string(), "boss".to_string()];
+
+    let mut wall = entry("Wall", &["door", "lockd"], "Wall");
+    wall.hitbox_dimensions = Some([2.0, 0.0, 1.0]);
+    let entries = vec![
+        wall,
+        entry("Wall", &[], "Wall"),
+        entry("Skeleton", &["boss"], "Skeleton"),
+        entry("Goblin", &["boss"], "Goblin"),
+    ];
+
+    let door = vec!["door".to_string(), "lockd".to_string()];
+    let problems = check_entries(&entries, &appearance_record, Some(&known));
+    assert_eq!(
+        problems,
+        vec![
+            Problem::BadHitbox {
+                entry: "Wall".to_string(),
+                classes: door.clone(),
+                dimensions: [2.0, 0.0, 1.0],
+            },
+            Problem::UnknownClass {
+                entry: "Wall".to_string(),
+                classes: door,
+                class: "lockd".to_string(),
+            },
+            Problem::UnknownAppearance {
+                entry: "Goblin".to_string(),
+                classes: vec!["boss".to_string()],
+                appearance: "Goblin".to_string(),
+            },
+        ]
+    );
+    // which of the walls is wrong is told apart by their classes.
+    assert_eq!(
+        problems[1].to_string(),
+        r#""Wall" (classes: door, lockd) has class "lockd", which isn't in the script's CLASSES"#
+    );
+
+    // without a CLASSES list, classes aren't checked.
+    assert_eq!(check_entries(&entries, &appearance_record, None).len(), 2);
+}
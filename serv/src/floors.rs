@@ -0,0 +1,503 @@
+//! Players can be on more than one level at once, i.e. as they go down the Stairs
+//! into the next floor of a dungeon.
+//!
+//! Each floor is its own World, with its own Systems and level script, and is only loaded
+//! once the first player reaches it. Every client comes in through the floor for the level
+//! an instance was started with, the entrance, and their messages are passed on to whichever
+//! floor they're on from then on. The entrance lets players in from the JoinQueue every
+//! instance shares, which counts everyone on every floor.
+//!
+//! Each floor runs with its level's level.toml merged into the ServerConfig,
+//! and so is ticked as often as its own tick rate says to.
+use crate::config::{CharacterClasses, Level, LevelSeed, LevelWatcher, ServerConfig};
+use crate::net::{self, prelude::*};
+use comn::art::{Appearance, AppearanceRecord};
+use comn::combat::Health;
+use comn::item::{Inventory, PickupRequest, MAX_INTERACTION_DISTANCE_SQUARED};
+use comn::{na::Translation2, prelude::*, vec_of_pos, Key, NetComponent, NetMessage, Stairs};
+use crossbeam_channel::Sender;
+use fixedstep::FixedStep;
+use log::*;
+use specs::prelude::*;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+/// An item in the Inventory of a player who's leaving a floor,
+/// along with the id its entity had there.
+pub struct CarriedItem {
+    id: u32,
+    item: Item,
+    /// The name of its appearance, since each floor can have its own AppearanceRecord.
+    appearance: Option<String>,
+    /// Keys still open the same Locks on other floors.
+    key: Option<Key>,
+}
+
+/// A player who's gone down (or up) the Stairs, and everything they're bringing with them.
+pub struct Departure {
+    addr: SocketAddr,
+    /// The name of the level they're going to.
+    to: String,
+    inventory: Inventory,
+    items: Vec<CarriedItem>,
+    health: Option<Health>,
+    /// The class they spawned as, which they'll spawn as again on arrival.
+    class: Option<String>,
+}
+
+/// The players who've left this floor since it was last ticked.
+#[derive(Default)]
+pub struct Departures(pub Vec<Departure>);
+
+/// Takes players who ask to pick up Stairs off of this floor,
+/// so that they can be sent to the level the Stairs lead to.
+///
+/// This has to run before ItemPickupDrop, which clears away every PickupRequest.
+pub struct TakeStairs;
+impl<'a> System<'a> for TakeStairs {
+    type SystemData = (
+        Entities<'a>,
+        Write<'a, ConnectionManager>,
+        Write<'a, Departures>,
+        Read<'a, LazyUpdate>,
+        Read<'a, AppearanceRecord>,
+        ReadStorage<'a, PickupRequest>,
+        ReadStorage<'a, Stairs>,
+        ReadStorage<'a, Pos>,
+        WriteStorage<'a, Client>,
+        ReadStorage<'a, Inventory>,
+        ReadStorage<'a, Health>,
+        ReadStorage<'a, Item>,
+        ReadStorage<'a, Appearance>,
+        ReadStorage<'a, Key>,
+        ReadStorage<'a, net::ChosenClass>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            ents,
+            mut cm,
+            mut departures,
+            lu,
+            record,
+            picks,
+            stairs,
+            poses,
+            mut clients,
+            inventories,
+            healths,
+            items,
+            appearances,
+            keys,
+            chosen_classes,
+        ): Self::SystemData,
+    ) {
+        let leaving = (&*ents, &picks, &poses, &clients, &inventories)
+            .join()
+            .filter_map(
+                |(player, PickupRequest { id }, &vec_of_pos!(p_loc), &Client(addr), inventory)| {
+                    // if it isn't stairs, it's an item, which ItemPickupDrop takes care of.
+                    let stairs_ent = ents.entity(*id);
+                    let Stairs { to } = stairs.get(stairs_ent)?;
+                    let &vec_of_pos!(s_loc) = poses.get(stairs_ent)?;
+
+                    if (p_loc - s_loc).magnitude_squared() < MAX_INTERACTION_DISTANCE_SQUARED {
+                        Some((player, addr, to.clone(), inventory.clone()))
+                    } else {
+                        trace!("Player[{}] is too far away from the stairs", player.id());
+                        None
+                    }
+                },
+            )
+            .collect::<Vec<_>>();
+
+        for (player, addr, to, inventory) in leaving {
+            info!("Player[{}] is going to {}", player.id(), to);
+
+            // their items go with them, so they don't need to be here anymore.
+            let carried = inventory
+                .reserved()
+                .chain(inventory.loose())
+                .filter_map(|(_, slot)| *slot)
+                .filter_map(|id| {
+                    let item_ent = ents.entity(id);
+                    lu.insert(item_ent, Dead);
+                    Some(CarriedItem {
+                        id,
+                        item: items.get(item_ent)?.clone(),
+                        appearance: appearances
+                            .get(item_ent)
+                            .map(|Appearance { index }| record.names[*index].clone()),
+                        key: keys.get(item_ent).cloned(),
+                    })
+                })
+                .collect();
+
+            // everyone else on this floor needs to stop rendering them,
+            // and nothing else on this floor should be sent to them.
+            clients.remove(player);
+            for Client(other) in (&clients).join() {
+                cm.insert_comp(*other, player, Dead);
+            }
+            cm.addr_to_ent.remove(&addr);
+            lu.insert(player, Dead);
+
+            departures.0.push(Departure {
+                addr,
+                to,
+                inventory,
+                items: carried,
+                health: healths.get(player).cloned(),
+                class: chosen_classes
+                    .get(player)
+                    .map(|net::ChosenClass(class)| class.clone()),
+            });
+        }
+    }
+}
+
+/// One level that players are on, and everything needed to run it.
+pub struct Floor {
+    pub world: World,
+    dispatcher: Dispatcher<'static, 'static>,
+    level: Level,
+    /// The ServerConfig, with the level's level.toml merged in.
+    config: ServerConfig,
+    fixedstep: FixedStep,
+    /// How many times the floor has been ticked.
+    ticks: u64,
+    /// The messages from the clients on this floor are passed along through this.
+    to_floor: Sender<(SocketAddr, NetMessage)>,
+    watcher: Option<LevelWatcher>,
+}
+
+impl Floor {
+    fn open(
+        config: &ServerConfig,
+        level: &str,
+        seed: u64,
+        cm: &ConnectionManager,
+    ) -> Result<Self, String> {
+        let config = config.for_level(level)?;
+        let (floor_cm, to_floor) = cm.branch();
+        let (world, dispatcher, level) = crate::setup(&config, seed, floor_cm)?;
+        let watcher = if config.hot_reload {
+            Some(LevelWatcher::new(
+                level.dir(),
+                vec![config.levels_dir.join("prefabs.toml")],
+            ))
+        } else {
+            None
+        };
+
+        Ok(Self {
+            world,
+            dispatcher,
+            level,
+            fixedstep: FixedStep::start(config.tick_rate.into()),
+            config,
+            ticks: 0,
+            to_floor,
+            watcher,
+        })
+    }
+
+    fn send(&self, addr: SocketAddr, msg: NetMessage) {
+        self.to_floor
+            .send((addr, msg))
+            .expect("Couldn't pass NetMessage along to floor!");
+    }
+
+    fn tick(&mut self, name: &str, config: &ServerConfig) {
+        self.dispatcher.dispatch(&mut self.world);
+        self.world.maintain();
+        self.level.run_hooks(&mut self.world);
+        self.ticks += 1;
+
+        if self.watcher.as_mut().map(|w| w.changed()).unwrap_or(false) {
+            match self.reload(config) {
+                Ok(()) => info!("reloaded level {}", name),
+                Err(e) => error!("couldn't reload level {}, keeping the old one: {}", name, e),
+            }
+        }
+    }
+
+    /// Reads the level's level.toml back in, then its script and map.
+    /// Players have already been sent the AppearanceRecord, so changes to that
+    /// only take effect the next time the floor is loaded.
+    fn reload(&mut self, config: &ServerConfig) -> Result<(), String> {
+        let mut new = config.for_level(&self.config.level)?;
+        new.appearance_record = self.config.appearance_record.clone();
+        self.level.reload(&mut self.world, &new)?;
+
+        if new.tick_rate != self.config.tick_rate {
+            self.fixedstep = FixedStep::start(new.tick_rate.into());
+            self.world.insert(comn::Fps(new.tick_rate));
+        }
+        self.config = new;
+        Ok(())
+    }
+
+    /// Welcomes a player who's come from another floor, giving them back their things.
+    /// They're spawned once their client asks to be, like any other player.
+    fn arrive(&mut self, departure: Departure) {
+        let Departure {
+            addr,
+            mut inventory,
+            items,
+            health,
+            class,
+            ..
+        } = departure;
+
+        let player = {
+            let (mut cm, ents, record, seed, classes, mut clients, mut logging_ins) =
+                self.world.system_data::<(
+                    Write<ConnectionManager>,
+                    Entities,
+                    Read<AppearanceRecord>,
+                    ReadExpect<LevelSeed>,
+                    Read<CharacterClasses>,
+                    WriteStorage<Client>,
+                    WriteStorage<LoggingIn>,
+                )>();
+            net::welcome(
+                addr,
+                &mut cm,
+                &ents,
+                &record,
+                &seed,
+                &classes,
+                &mut clients,
+                &mut logging_ins,
+            )
+        };
+
+        // they'll only be told about the items on the ground,
+        // so the ones they're carrying have to be sent separately.
+        let mut ids = HashMap::new();
+        for CarriedItem {
+            id,
+            item,
+            appearance,
+            key,
+        } in items
+        {
+            let appearance = appearance.and_then(|name| {
+                let record = self.world.read_resource::<AppearanceRecord>();
+                let appearance = record.try_appearance_of(&name).ok();
+                if appearance.is_none() {
+                    warn!("level {} has no {} appearance", self.config.level, name);
+                }
+                appearance
+            });
+
+            let mut builder = self.world.create_entity().with(item.clone());
+            if let Some(appearance) = appearance.clone() {
+                builder = builder.with(appearance);
+            }
+            if let Some(key) = key {
+                builder = builder.with(key);
+            }
+            let item_ent = builder.build();
+            ids.insert(id, item_ent.id());
+
+            let cm = self.world.read_resource::<ConnectionManager>();
+            cm.new_ent(addr, item_ent);
+            cm.insert_comp(addr, item_ent, item);
+            if let Some(appearance) = appearance {
+                cm.insert_comp(addr, item_ent, appearance);
+            }
+        }
+        inventory.map_ids(|id| ids.get(&id).copied());
+
+        self.world
+            .write_storage::<Inventory>()
+            .insert(player, inventory)
+            .expect("couldn't give arriving player their inventory");
+        if let Some(health) = health {
+            self.world
+                .write_storage::<Health>()
+                .insert(player, health)
+                .expect("couldn't give arriving player their health");
+        }
+        if let Some(class) = class {
+            self.world
+                .write_storage::<net::ChosenClass>()
+                .insert(player, net::ChosenClass(class))
+                .expect("couldn't remember arriving player's class");
+        }
+    }
+}
+
+/// The seed for the level with this name, below an entrance with `seed`.
+///
+/// It's the same no matter which order players reach the floors in,
+/// so that the same seed always gives the same dungeon. The name is hashed with FNV-1a,
+/// rather than Rust's own hasher, which could change from one version to the next.
+fn floor_seed(seed: u64, level: &str) -> u64 {
+    let hash = level
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325, |hash: u64, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+    seed.wrapping_add(hash)
+}
+
+/// Every floor players have reached, and which one each player is on.
+pub struct Floors {
+    /// Every client's messages come in through this,
+    /// and are passed along to the floor they're on.
+    cm: ConnectionManager,
+    /// The level everyone starts out on.
+    entrance: String,
+    /// The entrance's seed; every other floor's is made from it with `floor_seed`.
+    seed: u64,
+    floors: HashMap<String, Floor>,
+    /// Which level each client is on, if they've left the entrance.
+    on_floor: HashMap<SocketAddr, String>,
+}
+
+impl Floors {
+    /// Loads the entrance, the level with this name,
+    /// which players whose messages come through `cm` start out on,
+    /// once they've been let in from `line`.
+    pub fn open(
+        config: &ServerConfig,
+        level: &str,
+        cm: ConnectionManager,
+        line: net::JoinLine,
+    ) -> Result<Self, String> {
+        let seed = config.seed_or_random();
+        info!(
+            "generating level {} from seed {}; pass --seed {} to play it again",
+            level, seed, seed
+        );
+
+        let mut entrance = Floor::open(config, level, seed, &cm)?;
+        entrance.world.insert(line);
+        let mut floors = HashMap::new();
+        floors.insert(level.to_string(), entrance);
+
+        Ok(Self {
+            cm,
+            entrance: level.to_string(),
+            seed,
+            floors,
+            on_floor: HashMap::new(),
+        })
+    }
+
+    /// Every floor's World, along with the name of its level, the entrance's first.
+    pub fn worlds(&mut self) -> Vec<(&str, &mut World)> {
+        let entrance = self.entrance.as_str();
+        let mut worlds: Vec<(&str, &mut World)> = self
+            .floors
+            .iter_mut()
+            .map(|(name, floor)| (name.as_str(), &mut floor.world))
+            .collect();
+        worlds.sort_by(|(a, _), (b, _)| (*a != entrance, a).cmp(&(*b != entrance, b)));
+        worlds
+    }
+
+    /// The ServerConfig the entrance runs with, its level.toml and all.
+    pub fn config(&self) -> &ServerConfig {
+        &self.floors[&self.entrance].config
+    }
+
+    /// Which floor this client is on, and how many times it had been ticked so far,
+    /// so that it can be told later whether that floor has been ticked since.
+    pub fn clock(&self, addr: SocketAddr) -> (String, u64) {
+        let name = self.on_floor.get(&addr).unwrap_or(&self.entrance);
+        (name.clone(), self.floors[name].ticks)
+    }
+
+    /// Whether the floor has been ticked since `clock` was called, or isn't loaded anymore.
+    pub fn ticked_since(&self, (name, ticks): &(String, u64)) -> bool {
+        self.floors
+            .get(name)
+            .map(|floor| floor.ticks > *ticks)
+            .unwrap_or(true)
+    }
+
+    /// Runs each floor for as many ticks as are due, then moves the players who took the stairs.
+    pub fn tick(&mut self, config: &ServerConfig) {
+        self.route();
+
+        let mut departures = Vec::new();
+        for (name, floor) in self.floors.iter_mut() {
+            while floor.fixedstep.update() {
+                floor.tick(name, config);
+            }
+            // so that it only gives up on catching up if it's more than a few ticks behind at once.
+            floor.fixedstep.render_delta();
+
+            let mut left = floor.world.write_resource::<Departures>();
+            departures.extend(left.0.drain(..).map(|d| (name.clone(), d)));
+        }
+
+        for (from, departure) in departures {
+            self.descend(config, from, departure);
+        }
+    }
+
+    /// Passes the messages from each client along to the floor they're on.
+    fn route(&mut self) {
+        while let Ok((addr, msg)) = self.cm.from_clients.try_recv() {
+            let name = self.on_floor.get(&addr).unwrap_or(&self.entrance);
+            let floor = &self.floors[name];
+
+            // the entrance still counts players who log off from deeper floors,
+            // so it needs to hear about it to let in whoever's waiting.
+            if let NetMessage::InsertComp(_, NetComponent::Dead(_)) = msg {
+                if *name != self.entrance {
+                    self.floors[&self.entrance].send(addr, NetMessage::InsertComp(0, Dead.into()));
+                }
+                floor.send(addr, msg);
+                self.on_floor.remove(&addr);
+            } else {
+                floor.send(addr, msg);
+            }
+        }
+    }
+
+    /// Moves a player to the level they're going to, loading it if nobody's there yet.
+    /// If it can't be loaded, they're sent back to where they came from.
+    fn descend(&mut self, config: &ServerConfig, from: String, departure: Departure) {
+        let mut to = departure.to.clone();
+        if !self.floors.contains_key(&to) {
+            let seed = floor_seed(self.seed, &to);
+            match Floor::open(config, &to, seed, &self.cm) {
+                Ok(floor) => {
+                    info!("loaded level {} from seed {}", to, seed);
+                    self.floors.insert(to.clone(), floor);
+                }
+                Err(e) => {
+                    error!("couldn't load level {} for stairs to lead to: {}", to, e);
+                    self.cm.send(
+                        departure.addr,
+                        NetMessage::Notice(format!("The stairs to {} are blocked.", to)),
+                    );
+                    to = from;
+                }
+            }
+        }
+
+        self.on_floor.insert(departure.addr, to.clone());
+        self.floors
+            .get_mut(&to)
+            .expect("floor was just loaded")
+            .arrive(departure);
+    }
+}
+
+#[test]
+fn test_floor_seed() {
+    assert_eq!(floor_seed(0, ""), 0xcbf2_9ce4_8422_2325);
+    assert_eq!(floor_seed(1, "a"), 0xaf63_dc4c_8601_ec8c + 1);
+
+    assert_eq!(floor_seed(7, "crypt"), floor_seed(7, "crypt"));
+    assert_ne!(floor_seed(7, "crypt"), floor_seed(7, "catacombs"));
+    assert_ne!(floor_seed(7, "crypt"), floor_seed(8, "crypt"));
+}
@@ -0,0 +1,302 @@
+//! One server can run several instances at once, i.e. so that two parties can each
+//! go through their own copy of a dungeon. Each instance has its own floors, and so its own
+//! Worlds and level scripts, and they're all ticked together. They share one JoinQueue,
+//! so that `max_players` is how many can play on the whole server.
+//!
+//! Clients start out in the main instance, which runs the level in the ServerConfig and is
+//! never closed. They can list, create and join instances with LobbyCommands, which their
+//! client sends when they type i.e. `/create crypt-run dungeon` or `/join crypt-run`.
+//! Any other instance is closed once everyone has left it.
+use crate::config::ServerConfig;
+use crate::floors::Floors;
+use crate::net::{ConnectionManager, JoinLine, JoinQueue};
+use comn::{net::LobbyCommand, specs::World, Dead, NetComponent, NetMessage};
+use crossbeam_channel::Sender;
+use log::*;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+/// What the instance everyone starts out in is called.
+pub const MAIN: &str = "main";
+
+struct Instance {
+    floors: Floors,
+    /// The level the instance was started with.
+    level: String,
+    /// The messages from the clients in this instance are passed along through this.
+    to_instance: Sender<(SocketAddr, NetMessage)>,
+}
+
+impl Instance {
+    fn open(
+        config: &ServerConfig,
+        level: &str,
+        cm: &ConnectionManager,
+        line: JoinLine,
+    ) -> Result<Self, String> {
+        let (instance_cm, to_instance) = cm.branch();
+
+        Ok(Self {
+            floors: Floors::open(config, level, instance_cm, line)?,
+            level: level.to_string(),
+            to_instance,
+        })
+    }
+
+    fn send(&self, addr: SocketAddr, msg: NetMessage) {
+        self.to_instance
+            .send((addr, msg))
+            .expect("Couldn't pass NetMessage along to instance!");
+    }
+}
+
+/// Every instance that's running, and which one each client is in.
+pub struct Instances {
+    /// Every client's messages come in through this,
+    /// and are passed along to the instance they're in.
+    cm: ConnectionManager,
+    /// The JoinQueue every instance's entrance lets players in from.
+    line: JoinLine,
+    instances: HashMap<String, Instance>,
+    in_instance: HashMap<SocketAddr, String>,
+    /// Clients who are moving to another instance, who are let into it
+    /// once the one they left has been ticked and so has let go of them.
+    joining: Vec<Joining>,
+}
+
+struct Joining {
+    addr: SocketAddr,
+    /// The name of the instance they're moving to.
+    to: String,
+    /// The name of the instance they left.
+    from: String,
+    /// The floor they left, and how many times it had been ticked when they did.
+    clock: (String, u64),
+}
+
+impl Instances {
+    /// Starts the main instance, which clients connecting through `cm` start out in.
+    /// Everyone waits in line with the main level's `max_players` and `spectate_while_queued`.
+    pub fn open(config: &ServerConfig, cm: ConnectionManager) -> Result<Self, String> {
+        let main = config.for_level(&config.level)?;
+        let line = JoinLine::new(
+            JoinQueue::new(main.max_players, main.spectate_while_queued),
+            MAIN,
+        );
+
+        let mut instances = HashMap::new();
+        instances.insert(
+            MAIN.to_string(),
+            Instance::open(config, &config.level, &cm, line.clone())?,
+        );
+
+        Ok(Self {
+            cm,
+            line,
+            instances,
+            in_instance: HashMap::new(),
+            joining: Vec::new(),
+        })
+    }
+
+    /// Every floor of every instance, named like "main/crypt",
+    /// starting with the World players start out in.
+    pub fn worlds(&mut self) -> Vec<(String, &mut World)> {
+        let mut instances: Vec<(&String, &mut Instance)> = self.instances.iter_mut().collect();
+        instances.sort_by(|(a, _), (b, _)| (*a != MAIN, a).cmp(&(*b != MAIN, b)));
+
+        instances
+            .into_iter()
+            .flat_map(|(name, instance)| {
+                instance
+                    .floors
+                    .worlds()
+                    .into_iter()
+                    .map(move |(level, world)| (format!("{}/{}", name, level), world))
+            })
+            .collect()
+    }
+
+    /// Runs every instance for as many ticks as are due,
+    /// then lets the clients who are moving into their new one.
+    pub fn tick(&mut self, config: &ServerConfig) {
+        self.route(config);
+
+        for instance in self.instances.values_mut() {
+            instance.floors.tick(config);
+        }
+
+        // to the instance they're joining, they look like they've just connected.
+        let instances = &self.instances;
+        self.joining.retain(|joining| {
+            let left = instances
+                .get(&joining.from)
+                .map(|from| from.floors.ticked_since(&joining.clock))
+                .unwrap_or(true);
+            if left {
+                instances[&joining.to].send(joining.addr, NetMessage::NewEnt(0));
+            }
+            !left
+        });
+
+        let in_instance = &self.in_instance;
+        self.instances.retain(|name, _| {
+            let empty = name != MAIN && !in_instance.values().any(|n| n == name);
+            if empty {
+                info!("closing instance {}, since everyone has left it", name);
+            }
+            !empty
+        });
+    }
+
+    /// Carries out lobby commands, and passes every other message
+    /// along to the instance the client that sent it is in.
+    fn route(&mut self, config: &ServerConfig) {
+        while let Ok((addr, msg)) = self.cm.from_clients.try_recv() {
+            match msg {
+                NetMessage::Lobby(command) => {
+                    let reply = self.run(config, addr, command).unwrap_or_else(|e| e);
+                    self.cm.send(addr, NetMessage::Notice(reply));
+                }
+
+                NetMessage::NewEnt(_) => {
+                    let name = self
+                        .in_instance
+                        .entry(addr)
+                        .or_insert_with(|| MAIN.to_string());
+                    self.instances[name.as_str()].send(addr, msg);
+                }
+
+                // when someone logs off, they're taken out of the instance they were in.
+                NetMessage::InsertComp(_, NetComponent::Dead(_)) => {
+                    let name = match self.in_instance.remove(&addr) {
+                        Some(name) => name,
+                        None => continue,
+                    };
+
+                    // if they were on their way to another instance,
+                    // the one they left already knows they're gone.
+                    match self.joining.iter().position(|j| j.addr == addr) {
+                        Some(index) => {
+                            self.joining.remove(index);
+                        }
+                        None => self.instances[&name].send(addr, msg),
+                    }
+                }
+
+                msg => {
+                    let name = self.in_instance.get(&addr).map(String::as_str);
+                    self.instances[name.unwrap_or(MAIN)].send(addr, msg);
+                }
+            }
+        }
+    }
+
+    /// Carries out a lobby command for this client, returning what to tell them.
+    fn run(
+        &mut self,
+        config: &ServerConfig,
+        addr: SocketAddr,
+        command: LobbyCommand,
+    ) -> Result<String, String> {
+        match command {
+            LobbyCommand::List => Ok(self.list()),
+            LobbyCommand::Create { name, level } => {
+                self.create(config, &name, &level)?;
+                info!("{} started instance {} of level {}", addr, name, level);
+                self.join(addr, &name)
+            }
+            LobbyCommand::Join(name) => self.join(addr, &name),
+        }
+    }
+
+    fn list(&self) -> String {
+        let mut names: Vec<&String> = self.instances.keys().collect();
+        names.sort();
+
+        let instances: Vec<String> = names
+            .into_iter()
+            .map(|name| {
+                let players = self.in_instance.values().filter(|n| *n == name).count();
+                let instance = &self.instances[name];
+                let level = &instance.floors.config().level_config;
+                format!(
+                    "{} ({}, {} player(s))",
+                    name,
+                    level.summary(&instance.level),
+                    players
+                )
+            })
+            .collect();
+        format!("instances: {}", instances.join(", "))
+    }
+
+    fn create(&mut self, config: &ServerConfig, name: &str, level: &str) -> Result<(), String> {
+        if self.instances.contains_key(name) {
+            return Err(format!("there's already an instance named {}", name));
+        }
+        if self.instances.len() >= config.max_instances {
+            return Err(format!(
+                "the server can't run more than {} instances at once",
+                config.max_instances
+            ));
+        }
+        // level names come from players here, so they mustn't lead out of the levels folder.
+        let valid = |s: &str| {
+            !s.is_empty()
+                && s.chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        };
+        if !valid(name) || !valid(level) {
+            return Err(
+                "instance and level names can only have letters, numbers, - and _".to_string(),
+            );
+        }
+
+        let line = self.line.join(name);
+        let instance = Instance::open(config, level, &self.cm, line).map_err(|e| {
+            error!("couldn't start an instance of level {}: {}", level, e);
+            format!("couldn't start an instance of level {}", level)
+        })?;
+        self.instances.insert(name.to_string(), instance);
+
+        Ok(())
+    }
+
+    fn join(&mut self, addr: SocketAddr, name: &str) -> Result<String, String> {
+        if !self.instances.contains_key(name) {
+            return Err(format!("there's no instance named {}", name));
+        }
+
+        // they may have asked to join another instance since this tick started,
+        // in which case the one they were in already knows they're leaving.
+        match self.joining.iter().position(|j| j.addr == addr) {
+            Some(index) => self.joining[index].to = name.to_string(),
+            None => {
+                let current = self.in_instance.get(&addr).map(String::as_str);
+                let current = current.unwrap_or(MAIN);
+                if current == name {
+                    return Err(format!("you're already in {}", name));
+                }
+
+                // the instance they're leaving hears about it the same way it would
+                // if they'd logged off.
+                let from = &self.instances[current];
+                from.send(addr, NetMessage::InsertComp(0, Dead.into()));
+                self.joining.push(Joining {
+                    addr,
+                    to: name.to_string(),
+                    from: current.to_string(),
+                    clock: from.floors.clock(addr),
+                });
+            }
+        }
+        self.in_instance.insert(addr, name.to_string());
+
+        let level = &self.instances[name].floors.config().level_config;
+        Ok(match &level.description {
+            Some(description) => format!("joining {}: {}", name, description),
+            None => format!("joining {}", name),
+        })
+    }
+}
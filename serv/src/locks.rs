@@ -0,0 +1,190 @@
+//! Locks keep doors and chests shut until a player carrying the matching Key uses them,
+//! the same way items are picked up, from within the same distance.
+//!
+//! Anything locked that has a Hitbox is a door, and is taken out of the level once it's
+//! unlocked, so that players can walk through where it was. Anything else, like a chest,
+//! only loses its Lock, and every client is told to forget it too. What's inside is up to
+//! the level's script, which hears about it through `on_unlock(lock, player, name, classes)`,
+//! where `name` and `classes` come from the map entry the Lock was made from,
+//! like in `on_entity_death`.
+//!
+//! Map entries are given Locks and Keys by the prefabs their classes name, i.e.
+//!
+//! ```toml
+//! [prefab.locked]
+//! lock = { key = "boss", consume = true }
+//!
+//! [prefab.key]
+//! item = "Misc"
+//! appearance = "Key"
+//! key = "boss"
+//! ```
+//!
+//! and scripts can hand them out like any other component, i.e. `Lock("boss")` and `Key("boss")`.
+use crate::config::{LevelEvent, LevelEvents, MapOrigin};
+use crate::net::prelude::*;
+use comn::item::{Inventory, PickupRequest, SlotIndex, MAX_INTERACTION_DISTANCE_SQUARED};
+use comn::{na::Translation2, prelude::*, vec_of_pos, Hitbox, Key, Lock, NetMessage};
+use log::*;
+use specs::prelude::*;
+
+/// Where in the Inventory the Key that opens this Lock is, and the id of its entity.
+fn find_key<'k>(
+    inventory: &Inventory,
+    lock: &Lock,
+    key_of: impl Fn(u32) -> Option<&'k Key>,
+) -> Option<(SlotIndex, u32)> {
+    inventory
+        .reserved()
+        .chain(inventory.loose())
+        .filter_map(|(index, slot)| Some((index, (*slot)?)))
+        .find(|&(_, id)| key_of(id).map(|k| k.id == lock.key).unwrap_or(false))
+        .map(|(index, id)| (index.clone(), id))
+}
+
+/// Opens the Locks players ask to pick up, if they're carrying the Key.
+///
+/// This has to run before ItemPickupDrop, which clears away every PickupRequest.
+pub struct UnlockLocks;
+impl<'a> System<'a> for UnlockLocks {
+    type SystemData = (
+        Entities<'a>,
+        Read<'a, ConnectionManager>,
+        Read<'a, LazyUpdate>,
+        Write<'a, LevelEvents>,
+        ReadStorage<'a, PickupRequest>,
+        WriteStorage<'a, Lock>,
+        ReadStorage<'a, Key>,
+        WriteStorage<'a, Inventory>,
+        ReadStorage<'a, Pos>,
+        ReadStorage<'a, Hitbox>,
+        ReadStorage<'a, Client>,
+        ReadStorage<'a, MapOrigin>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            ents,
+            cm,
+            lu,
+            mut events,
+            picks,
+            mut locks,
+            keys,
+            mut inventories,
+            poses,
+            hitboxes,
+            clients,
+            origins,
+        ): Self::SystemData,
+    ) {
+        let unlocking = (&*ents, &picks, &poses)
+            .join()
+            .filter_map(|(player, PickupRequest { id }, &vec_of_pos!(p_loc))| {
+                // if it isn't locked, it's an item or stairs, which other systems take care of.
+                let lock_ent = ents.entity(*id);
+                locks.get(lock_ent)?;
+                let &vec_of_pos!(l_loc) = poses.get(lock_ent)?;
+
+                if (p_loc - l_loc).magnitude_squared() < MAX_INTERACTION_DISTANCE_SQUARED {
+                    Some((player, lock_ent))
+                } else {
+                    trace!("Player[{}] is too far away from the lock", player.id());
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+
+        for (player, lock_ent) in unlocking {
+            // someone else might've unlocked it already this tick.
+            let lock = match locks.get(lock_ent) {
+                Some(lock) => lock.clone(),
+                None => continue,
+            };
+            let addr = clients.get(player).map(|&Client(addr)| addr);
+            let inventory = match inventories.get_mut(player) {
+                Some(inventory) => inventory,
+                None => continue,
+            };
+
+            let key_of = |id: u32| keys.get(ents.entity(id));
+            let (index, key_id) = match find_key(inventory, &lock, key_of) {
+                Some(found) => found,
+                None => {
+                    if let Some(addr) = addr {
+                        cm.send(addr, NetMessage::Notice("It's locked.".to_string()));
+                    }
+                    continue;
+                }
+            };
+            info!(
+                "Player[{}] unlocked Entity[{}] with key {:?}",
+                player.id(),
+                lock_ent.id(),
+                lock.key
+            );
+            locks.remove(lock_ent);
+
+            if lock.consume {
+                inventory
+                    .clear(&index)
+                    .expect("the key's slot vanished from the inventory");
+                let key_ent = ents.entity(key_id);
+                lu.insert(key_ent, Dead);
+                for Client(addr) in (&clients).join() {
+                    cm.insert_comp(*addr, key_ent, Dead);
+                }
+                if let Some(addr) = addr {
+                    cm.insert_comp(addr, player, inventory.clone());
+                }
+            }
+
+            // doors get out of the way,
+            // and everything else stops being something players can use.
+            if hitboxes.contains(lock_ent) {
+                lu.insert(lock_ent, Dead);
+                for Client(addr) in (&clients).join() {
+                    cm.insert_comp(*addr, lock_ent, Dead);
+                }
+            } else {
+                for Client(addr) in (&clients).join() {
+                    cm.remove_comp(*addr, lock_ent, lock.clone());
+                }
+            }
+
+            events.push(LevelEvent::Unlock {
+                lock: lock_ent.id(),
+                player: player.id(),
+                origin: origins.get(lock_ent).cloned(),
+            });
+        }
+    }
+}
+
+#[test]
+fn test_find_key() {
+    let boss_key = Key {
+        id: "boss".to_string(),
+    };
+    let crypt_key = Key {
+        id: "crypt".to_string(),
+    };
+    let key_of = |id: u32| match id {
+        1 => Some(&crypt_key),
+        2 => Some(&boss_key),
+        _ => None,
+    };
+    let lock = Lock {
+        key: "boss".to_string(),
+        consume: false,
+    };
+
+    let mut inventory = Inventory::character();
+    inventory.insert(0, &Item::Weapon).unwrap();
+    inventory.insert(1, &Item::Misc).unwrap();
+    assert!(find_key(&inventory, &lock, key_of).is_none());
+
+    let index = inventory.insert(2, &Item::Misc).unwrap();
+    assert_eq!(find_key(&inventory, &lock, key_of), Some((index, 2)));
+}
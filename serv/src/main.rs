@@ -1,12 +1,21 @@
 #![feature(stmt_expr_attributes)]
-use comn::specs::{self, prelude::*};
+use comn::specs::WorldExt;
+use comn::specs::{prelude::*, World};
 use log::*;
-use specs::WorldExt;
+mod admin;
+mod cli;
 mod combat;
 mod config;
+mod floors;
+mod instances;
+mod locks;
+mod moderation;
 mod net;
 mod pickup;
+mod spawn;
+mod triggers;
 use config::{Level, ServerConfig};
+use structopt::StructOpt;
 
 // launch webserver to serve client files
 #[cfg(feature = "webserver")]
@@ -44,50 +53,95 @@ fn main() {
             .init();
     }
 
-    #[cfg(feature = "webserver")]
-    std::thread::spawn(|| host_client());
+    let opt = cli::Opt::from_args();
+    if let Err(e) = opt.command.unwrap_or_default().run() {
+        error!("{}", e);
+        std::process::exit(1);
+    }
+}
 
-    let mut world = specs::World::new();
-    world.insert(comn::Fps(20.0));
+/// Builds the World and the Systems that run on it,
+/// and loads the config's level into it, generated from that seed.
+/// The config should already have that level's level.toml merged in, see `for_level`.
+/// The World gets a JoinQueue of its own, which Floors swaps out for the one
+/// every instance shares if it's an entrance.
+pub fn setup(
+    config: &ServerConfig,
+    seed: u64,
+    cm: net::ConnectionManager,
+) -> Result<(World, Dispatcher<'static, 'static>, Level), String> {
+    let mut world = World::new();
+    world.insert(comn::Fps(config.tick_rate));
+    world.insert(cm);
+    world.insert(moderation::Moderation::new(config.moderation.clone()));
+    world.insert(config.classes.clone());
+    world.insert(net::JoinLine::new(
+        net::JoinQueue::new(config.max_players, config.spectate_while_queued),
+        instances::MAIN,
+    ));
     #[rustfmt::skip]
     let mut dispatcher = DispatcherBuilder::new()
         // combat
-        .with(combat::LaunchAttacks,        "attack",           &[])
-        .with(combat::DealDamage,           "damage",           &["attack"])
-        .with(combat::Chase,                "chase",            &[])
+        .with(combat::LaunchAttacks,         "attack",           &[])
+        .with(combat::DealDamage,            "damage",           &["attack"])
+        .with(combat::Chase,                 "chase",            &[])
         // phys
-        .with(comn::phys::Collision,        "collision",        &[])
-        .with(comn::controls::MoveHeadings, "heading",          &[])
+        .with(comn::phys::Collision,         "collision",        &[])
+        .with(comn::controls::MoveHeadings,  "heading",          &[])
+        .with(triggers::TrackTriggers::default(), "triggers",    &["collision", "heading"])
         // net/util
-        .with(net::SendWorldToNewPlayers,   "send world",       &[])
-        .with(net::HandleClientPackets,     "client packets",   &["send world"])
-        .with(net::SpawnNewPlayers,         "new players",      &["client packets"])
-        .with(comn::dead::ClearDead,        "clear dead",       &["client packets"])
-        .with(net::SendNewPositions,        "send pos",         &["clear dead"])
+        .with(net::SendWorldToNewPlayers,    "send world",       &[])
+        .with(net::HandleClientPackets,      "client packets",   &["send world"])
+        .with(net::SpawnNewPlayers,          "new players",      &["client packets"])
+        .with(comn::dead::ClearDead,         "clear dead",       &["client packets"])
+        .with(net::SendNewPositions,         "send pos",         &["clear dead"])
         // misc
-        .with(comn::art::UpdateAnimations,  "animate",          &[])
-        .with(pickup::ItemPickupDrop,       "pickup",           &[])
+        .with(comn::art::UpdateAnimations,   "animate",          &[])
+        .with(floors::TakeStairs,            "stairs",           &[])
+        .with(locks::UnlockLocks,            "locks",            &[])
+        .with(pickup::ItemPickupDrop,        "pickup",           &["stairs", "locks"])
+        .with(moderation::EnforceModeration, "moderation",       &["pickup"])
         .build();
 
     dispatcher.setup(&mut world);
 
-    // parsing config file
-    let config = ServerConfig::parse();
-    let mut level = Level::from_name(config.level.clone());
+    let mut level = Level::from_name(
+        &config.levels_dir,
+        config.level.clone(),
+        &config.scripts,
+        seed,
+    )?;
     level
-        .load_map(&mut world, &config)
-        .unwrap_or_else(|e| panic!("Couldn't load map: {}", e));
+        .load_map(&mut world, config)
+        .map_err(|e| format!("Couldn't load map: {}", e))?;
 
-    world.insert(config.appearance_record);
+    world.insert(config.appearance_record.clone());
+    world.insert(config::LevelSeed(seed));
 
-    info!("starting game loop!");
+    Ok((world, dispatcher, level))
+}
 
-    let mut fixedstep = fixedstep::FixedStep::start(20.0); // 20.0Hz
+/// Hosts the game until the process is stopped.
+pub fn serve(config: ServerConfig) -> Result<(), String> {
+    #[cfg(feature = "webserver")]
+    std::thread::spawn(|| host_client());
+
+    let cm = net::ConnectionManager::new(
+        config.port,
+        moderation::BanList::load(&config.moderation.ban_file),
+    )?;
+    let mut instances = instances::Instances::open(&config, cm)?;
+
+    let admin = config.admin.as_ref().map(admin::AdminConsole::listen);
 
+    info!("starting game loop!");
+
+    // each floor keeps its own time, since levels can have their own tick rates.
     loop {
-        while fixedstep.update() {
-            dispatcher.dispatch(&mut world);
-            world.maintain();
+        instances.tick(&config);
+
+        if let Some(admin) = &admin {
+            admin.run_pending(&mut instances);
         }
     }
 }
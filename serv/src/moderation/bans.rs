@@ -0,0 +1,138 @@
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs, io,
+    net::IpAddr,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Ban {
+    pub ip: IpAddr,
+    pub reason: String,
+    /// Seconds since the UNIX_EPOCH at which this ban is lifted.
+    /// Bans without one never are.
+    pub until: Option<u64>,
+}
+impl Ban {
+    #[inline]
+    fn expired(&self, now: u64) -> bool {
+        self.until.map(|until| until <= now).unwrap_or(false)
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+/// How a BanList is stored on disk; a TOML file with a `[[ban]]` table for each Ban.
+struct BanFile {
+    #[serde(default)]
+    ban: Vec<Ban>,
+}
+
+#[derive(Default)]
+/// The addresses that aren't allowed to connect to the server.
+///
+/// If it was loaded from a file, every change made to it is written back to that file,
+/// so that bans survive restarts.
+pub struct BanList {
+    bans: HashMap<IpAddr, Ban>,
+    path: Option<PathBuf>,
+}
+impl BanList {
+    /// Reads the ban list from the file at `path`, which doesn't have to exist yet.
+    pub fn load(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let bans = match fs::read_to_string(&path) {
+            Ok(text) => {
+                toml::from_str::<BanFile>(&text)
+                    .unwrap_or_else(|e| panic!("{} isn't a proper ban list: {}", path.display(), e))
+                    .ban
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => panic!("couldn't read the ban list at {}: {}", path.display(), e),
+        };
+
+        let now = now();
+        let bans = bans
+            .into_iter()
+            .filter(|ban| !ban.expired(now))
+            .map(|ban| (ban.ip, ban))
+            .collect::<HashMap<_, _>>();
+        info!("{} address(es) banned", bans.len());
+
+        Self {
+            bans,
+            path: Some(path),
+        }
+    }
+
+    /// Returns the Ban keeping this address out, if there is one.
+    pub fn get(&self, ip: &IpAddr) -> Option<&Ban> {
+        self.bans.get(ip).filter(|ban| !ban.expired(now()))
+    }
+
+    /// Bans this address for the given amount of time, or forever if no duration is provided.
+    pub fn ban(&mut self, ip: IpAddr, reason: &str, duration: Option<Duration>) {
+        info!("banning {} ({}) for {:?}", ip, reason, duration);
+        self.bans.insert(
+            ip,
+            Ban {
+                ip,
+                reason: reason.to_string(),
+                until: duration.map(|d| now() + d.as_secs()),
+            },
+        );
+        self.save();
+    }
+
+    /// Returns whether or not the address was banned in the first place.
+    pub fn unban(&mut self, ip: &IpAddr) -> bool {
+        let was_banned = self.bans.remove(ip).is_some();
+        if was_banned {
+            self.save();
+        }
+        was_banned
+    }
+
+    fn save(&mut self) {
+        let now = now();
+        self.bans.retain(|_, ban| !ban.expired(now));
+
+        if let Some(path) = &self.path {
+            let mut ban = self.bans.values().cloned().collect::<Vec<_>>();
+            ban.sort_by_key(|b| b.ip);
+
+            let written = toml::to_string(&BanFile { ban })
+                .map_err(|e| e.to_string())
+                .and_then(|text| fs::write(path, text).map_err(|e| e.to_string()));
+            if let Err(e) = written {
+                error!("couldn't save the ban list to {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system clock is before the UNIX_EPOCH")
+        .as_secs()
+}
+
+#[test]
+fn test_ban_expiry() {
+    let mut bans = BanList::default();
+    let ip: IpAddr = "10.0.0.1".parse().unwrap();
+
+    bans.ban(ip, "testing", Some(Duration::from_secs(60)));
+    assert!(bans.get(&ip).is_some());
+
+    bans.ban(ip, "testing", Some(Duration::from_secs(0)));
+    assert!(bans.get(&ip).is_none());
+
+    bans.ban(ip, "testing", None);
+    assert!(bans.get(&ip).is_some());
+    assert!(bans.unban(&ip));
+    assert!(bans.get(&ip).is_none());
+}
@@ -0,0 +1,220 @@
+//! Keeps track of clients who send requests that the real client never would,
+//! and escalates from warning them, to kicking them, to banning them
+//! as they keep doing it.
+//!
+//! Systems that notice something fishy report it as a Violation to the Moderation
+//! Resource, and the EnforceModeration System decides what's to be done about it.
+//! Each World has its own Moderation, but the Records they add to are kept on the
+//! ConnectionManager that every floor of every instance shares.
+use crate::net::prelude::*;
+use comn::{item::SlotIndex, specs::prelude::*, NetMessage};
+use log::*;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fmt,
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+
+mod bans;
+pub use bans::BanList;
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+/// Found in the `[moderation]` section of `hauntfall_server_config.toml`.
+///
+/// The thresholds are numbers of Violations; once a client has committed that many,
+/// the corresponding punishment is dealt out for each Violation after that.
+/// Leaving a threshold out disables that punishment.
+pub struct ModerationConfig {
+    /// Where banned addresses are remembered between restarts.
+    pub ban_file: String,
+    pub warn_at: Option<u32>,
+    pub kick_at: Option<u32>,
+    pub ban_at: Option<u32>,
+    /// How long the bans handed out for Violations last.
+    pub ban_minutes: u64,
+}
+impl Default for ModerationConfig {
+    fn default() -> Self {
+        Self {
+            ban_file: "./bans.toml".to_string(),
+            warn_at: Some(1),
+            kick_at: Some(3),
+            ban_at: Some(5),
+            ban_minutes: 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Punishment {
+    Warn,
+    Kick,
+    TempBan,
+}
+impl ModerationConfig {
+    /// The harshest punishment deserved by someone with that many Violations.
+    pub fn punishment(&self, violations: u32) -> Option<Punishment> {
+        let reached = |threshold: Option<u32>| threshold.map(|t| violations >= t).unwrap_or(false);
+
+        if reached(self.ban_at) {
+            Some(Punishment::TempBan)
+        } else if reached(self.kick_at) {
+            Some(Punishment::Kick)
+        } else if reached(self.warn_at) {
+            Some(Punishment::Warn)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Something a client requested that their client wouldn't have let them.
+pub enum Violation {
+    /// They tried to drop an item out of a slot that's empty or doesn't exist.
+    InvalidDrop { index: SlotIndex },
+    /// They tried to pick up an item that's too far away.
+    PickupOutOfRange { item: u32, distance: f32 },
+    /// They asked to be spawned when they already had been,
+    /// which would put them back on a spawn point.
+    SpawnedAgain,
+}
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Violation::InvalidDrop { index } => {
+                write!(f, "tried to drop an item from invalid slot {:?}", index)
+            }
+            Violation::PickupOutOfRange { item, distance } => write!(
+                f,
+                "tried to pick up item {} from {:.2} units away",
+                item, distance
+            ),
+            Violation::SpawnedAgain => write!(f, "tried to spawn again"),
+        }
+    }
+}
+
+#[derive(Default, Debug)]
+/// Every Violation committed from a given IP since the server started.
+pub struct Record {
+    pub violations: Vec<Violation>,
+}
+
+/// These are kept by IP rather than by connection,
+/// so that reconnecting doesn't wipe the slate clean.
+pub type Records = HashMap<IpAddr, Record>;
+
+#[derive(Default)]
+pub struct Moderation {
+    pub config: ModerationConfig,
+    /// Violations that haven't been dealt with yet.
+    reports: Vec<(SocketAddr, Violation)>,
+}
+impl Moderation {
+    pub fn new(config: ModerationConfig) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    /// Records that the client at this address has done something suspicious.
+    /// They'll be dealt with the next time EnforceModeration runs.
+    pub fn report(&mut self, addr: SocketAddr, violation: Violation) {
+        warn!("POSSIBLE HACKING from {}: {}", addr, violation);
+        self.reports.push((addr, violation));
+    }
+}
+
+/// This System metes out punishments for any Violations that have been reported.
+pub struct EnforceModeration;
+impl<'a> System<'a> for EnforceModeration {
+    type SystemData = (Read<'a, ConnectionManager>, Write<'a, Moderation>);
+
+    fn run(&mut self, (cm, mut moderation): Self::SystemData) {
+        let Moderation { config, reports } = &mut *moderation;
+        let mut records = cm.records.lock().expect("couldn't get violation records");
+
+        for (addr, violation) in reports.drain(..) {
+            let record = records.entry(addr.ip()).or_default();
+            let reason = violation.to_string();
+            record.violations.push(violation);
+            let count = record.violations.len() as u32;
+
+            match config.punishment(count) {
+                Some(Punishment::TempBan) => cm.ban(
+                    addr,
+                    &format!("banned for {} minutes: {}", config.ban_minutes, reason),
+                    Some(Duration::from_secs(config.ban_minutes * 60)),
+                ),
+                Some(Punishment::Kick) => cm.kick(addr, &format!("kicked: {}", reason)),
+                Some(Punishment::Warn) => cm.send(
+                    addr,
+                    NetMessage::Notice(format!(
+                        "Warning #{}: you {}. Keep it up and you'll be removed.",
+                        count, reason
+                    )),
+                ),
+                None => {}
+            }
+        }
+    }
+}
+
+#[test]
+fn test_punishment_escalation() {
+    let config = ModerationConfig {
+        warn_at: Some(1),
+        kick_at: Some(3),
+        ban_at: None,
+        ..Default::default()
+    };
+
+    assert_eq!(config.punishment(0), None);
+    assert_eq!(config.punishment(1), Some(Punishment::Warn));
+    assert_eq!(config.punishment(2), Some(Punishment::Warn));
+    assert_eq!(config.punishment(3), Some(Punishment::Kick));
+    assert_eq!(config.punishment(100), Some(Punishment::Kick));
+}
+
+#[test]
+fn test_records_shared_between_worlds() {
+    let (to_clients, sent) = crossbeam_channel::unbounded();
+    let server = ConnectionManager {
+        to_clients,
+        ..ConnectionManager::offline()
+    };
+    let config = ModerationConfig {
+        warn_at: Some(1),
+        kick_at: Some(2),
+        ban_at: None,
+        ..Default::default()
+    };
+    let addr = SocketAddr::from(([10, 0, 0, 1], 3012));
+
+    // one Violation on each floor is still two Violations.
+    for _ in 0..2 {
+        let mut world = World::new();
+        world.insert(server.branch().0);
+        let mut moderation = Moderation::new(config.clone());
+        moderation.report(
+            addr,
+            Violation::PickupOutOfRange {
+                item: 1,
+                distance: 9.0,
+            },
+        );
+        world.insert(moderation);
+        EnforceModeration.run_now(&world);
+    }
+
+    let sent: Vec<NetMessage> = sent.try_iter().map(|(_, msg)| msg).collect();
+    match sent.as_slice() {
+        [NetMessage::Notice(_), NetMessage::Kicked(_)] => {}
+        other => panic!("expected a warning then a kick, got {:?}", other),
+    }
+}
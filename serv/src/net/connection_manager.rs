@@ -1,6 +1,11 @@
 // networking
 use std::net::{SocketAddr, TcpListener};
-use tungstenite::{accept_hdr, handshake::server::Request, Message};
+use tungstenite::{
+    accept_hdr,
+    handshake::server::{ErrorResponse, Request},
+    http::StatusCode,
+    Message,
+};
 // util
 use crossbeam_channel::{unbounded, Receiver, Sender};
 use log::*;
@@ -10,24 +15,36 @@ use std::{
     thread::spawn,
 };
 // reexports/main lib
+use crate::moderation::{BanList, Records};
 use comn::{rmps, specs, Dead, NetComponent, NetMessage};
 
 pub struct ConnectionManager {
     pub from_clients: Receiver<(SocketAddr, NetMessage)>,
     pub to_clients: Sender<(SocketAddr, NetMessage)>,
     pub addr_to_ent: HashMap<SocketAddr, u32>,
+    /// Connections from these addresses are refused during the websocket handshake.
+    pub bans: Arc<Mutex<BanList>>,
+    /// Every Violation committed from each IP. Shared between branches like the bans,
+    /// so that taking the stairs or changing instances doesn't wipe the slate clean.
+    pub records: Arc<Mutex<Records>>,
 }
 
 impl ConnectionManager {
-    fn new() -> Self {
+    /// Starts listening for clients on the given port.
+    pub fn new(port: u16, bans: BanList) -> Result<Self, String> {
+        let server = TcpListener::bind(("127.0.0.1", port))
+            .map_err(|e| format!("couldn't listen on port {}: {}", port, e))?;
+        info!("listening for clients on port {}", port);
+
         let (to_thread, from_clients) = unbounded();
         let (to_clients, from_thread) = unbounded();
 
+        let bans = Arc::new(Mutex::new(bans));
+
         let msgs_for_srv = to_thread.clone();
         let msgs_to_send = from_thread.clone();
+        let thread_bans = bans.clone();
         spawn(move || {
-            let server = TcpListener::bind("127.0.0.1:3012").unwrap();
-
             let channels: Arc<Mutex<HashMap<SocketAddr, Sender<NetMessage>>>> =
                 Arc::new(Mutex::new(HashMap::new()));
 
@@ -64,8 +81,20 @@ impl ConnectionManager {
 
                 trace!("Sender inserted into channel recorder!");
 
+                let bans = thread_bans.clone();
                 spawn(move || {
                     let callback = |req: &Request| {
+                        if let Some(ban) =
+                            bans.lock().expect("couldn't get ban list").get(&addr.ip())
+                        {
+                            info!("Refusing handshake from banned address {}", addr);
+                            return Err(ErrorResponse {
+                                error_code: StatusCode::FORBIDDEN,
+                                headers: None,
+                                body: Some(ban.reason.clone()),
+                            });
+                        }
+
                         println!("Received a new ws handshake");
                         println!("The request's path is: {}", req.path);
                         println!("The request's headers are:");
@@ -88,8 +117,13 @@ impl ConnectionManager {
                     // (so we can send output too)
                     stream.set_nonblocking(true).expect("can't set unblocking");
 
-                    let mut websocket =
-                        accept_hdr(stream, callback).expect("couldn't accept handshake");
+                    let mut websocket = match accept_hdr(stream, callback) {
+                        Ok(websocket) => websocket,
+                        Err(e) => {
+                            debug!("couldn't complete handshake with {}: {}", addr, e);
+                            return;
+                        }
+                    };
 
                     // tell the game thread that a connection with this client has been established.
                     msgs_for_srv
@@ -115,6 +149,11 @@ impl ConnectionManager {
 
                         while let Ok(msg) = msgs_to_send.try_recv() {
                             trace!("got {:#?} for {:#?}", msg, addr);
+                            let kicked = if let NetMessage::Kicked(_) = msg {
+                                true
+                            } else {
+                                false
+                            };
 
                             // if the call succeeds, all is well, but if it fails we need
                             // to tell the game loop that happened and then stop listening for
@@ -130,19 +169,73 @@ impl ConnectionManager {
                                 // stop listening for their messages
                                 break 'poll;
                             }
+
+                            // they've been told why, now show them the door.
+                            if kicked {
+                                if let Err(e) = websocket
+                                    .close(None)
+                                    .and_then(|_| websocket.write_pending())
+                                {
+                                    trace!(
+                                        "couldn't cleanly close kicked client's websocket: {}",
+                                        e
+                                    );
+                                }
+
+                                msgs_for_srv
+                                    .send((addr.clone(), NetMessage::InsertComp(0, Dead.into())))
+                                    .expect("Couldn't send log-off message over channel!");
+
+                                break 'poll;
+                            }
                         }
                     }
                 });
             }
         });
 
+        Ok(Self {
+            from_clients,
+            to_clients,
+            addr_to_ent: HashMap::new(),
+            bans,
+            records: Arc::new(Mutex::new(Records::new())),
+        })
+    }
+
+    /// A ConnectionManager that never binds a socket, so no clients ever connect to it.
+    /// Useful for running the game without anyone playing it.
+    pub fn offline() -> Self {
+        let (_, from_clients) = unbounded();
+        let (to_clients, _) = unbounded();
+
         Self {
             from_clients,
             to_clients,
             addr_to_ent: HashMap::new(),
+            bans: Arc::new(Mutex::new(BanList::default())),
+            records: Arc::new(Mutex::new(Records::new())),
         }
     }
 
+    /// A ConnectionManager that sends to the same clients as this one,
+    /// but only hears the messages that are passed into the returned Sender.
+    /// This lets the clients be split up between more than one World.
+    pub fn branch(&self) -> (Self, Sender<(SocketAddr, NetMessage)>) {
+        let (to_branch, from_clients) = unbounded();
+
+        (
+            Self {
+                from_clients,
+                to_clients: self.to_clients.clone(),
+                addr_to_ent: HashMap::new(),
+                bans: self.bans.clone(),
+                records: self.records.clone(),
+            },
+            to_branch,
+        )
+    }
+
     #[inline]
     pub fn send(&self, addr: SocketAddr, msg: NetMessage) {
         self.to_clients
@@ -164,10 +257,45 @@ impl ConnectionManager {
     ) {
         self.send(addr, NetMessage::InsertComp(ent.id(), comp.into()));
     }
+
+    /// Tells the client to remove the component of `comp`'s type from the entity.
+    #[inline]
+    pub fn remove_comp<C: Into<NetComponent>>(
+        &self,
+        addr: SocketAddr,
+        ent: specs::Entity,
+        comp: C,
+    ) {
+        self.send(addr, NetMessage::RemoveComp(ent.id(), comp.into()));
+    }
+
+    /// Sends a Notice to every connected client.
+    pub fn broadcast(&self, text: &str) {
+        for addr in self.addr_to_ent.keys() {
+            self.send(*addr, NetMessage::Notice(text.to_string()));
+        }
+    }
+
+    /// Tells the client why they're being disconnected, then disconnects them.
+    /// The game loop hears about it the same way it hears about a client logging off.
+    #[inline]
+    pub fn kick(&self, addr: SocketAddr, reason: &str) {
+        self.send(addr, NetMessage::Kicked(reason.to_string()));
+    }
+
+    /// Refuses any future connections from this client's IP
+    /// for the given amount of time (or forever), then kicks them.
+    pub fn ban(&self, addr: SocketAddr, reason: &str, duration: Option<std::time::Duration>) {
+        self.bans
+            .lock()
+            .expect("couldn't get ban list")
+            .ban(addr.ip(), reason, duration);
+        self.kick(addr, reason);
+    }
 }
 
 impl Default for ConnectionManager {
     fn default() -> Self {
-        Self::new()
+        Self::offline()
     }
 }
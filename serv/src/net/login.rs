@@ -1,7 +1,11 @@
 // our code
-use super::prelude::*;
+use super::{prelude::*, ChosenClass};
+use crate::config::{CharacterClasses, ClientInfo, LevelEvent, LevelEvents, MapOrigin, Prefabs};
+use crate::moderation::{Moderation, Violation};
+use crate::spawn;
 use comn::prelude::*;
 use comn::specs::prelude::*;
+use comn::NetMessage;
 // crates
 use log::*;
 
@@ -16,27 +20,60 @@ impl<'a> System<'a> for SendWorldToNewPlayers {
         // things we need to tell new players about
         Entities<'a>,
         ReadStorage<'a, comn::Hitbox>,
+        ReadStorage<'a, comn::Elevation>,
+        ReadStorage<'a, comn::Orientation>,
         ReadStorage<'a, comn::art::Appearance>,
         ReadStorage<'a, comn::art::Animate>,
         ReadStorage<'a, Item>,
+        ReadStorage<'a, comn::Stairs>,
+        ReadStorage<'a, comn::Lock>,
         ReadStorage<'a, Pos>,
     );
 
     fn run(
         &mut self,
-        (cm, mut logging_ins, clients, ents, hitboxes, appearances, animates, items, isos): Self::SystemData,
+        (
+            cm,
+            mut logging_ins,
+            clients,
+            ents,
+            hitboxes,
+            elevations,
+            orientations,
+            appearances,
+            animates,
+            items,
+            stairs,
+            locks,
+            isos,
+        ): Self::SystemData,
     ) {
         for (_, Client(addr)) in (logging_ins.drain(), &clients).join() {
             debug!("We're about to tell a new player about the world.");
             // tell them about each new entity they need to add, and about
             // some crucial components it has.
-            for (iso, ent, hitbox, appearance, animate, item) in (
+            for (
+                iso,
+                ent,
+                hitbox,
+                elevation,
+                orientation,
+                appearance,
+                animate,
+                item,
+                stairs,
+                lock,
+            ) in (
                 &isos,
                 &*ents,
                 hitboxes.maybe(),
+                elevations.maybe(),
+                orientations.maybe(),
                 appearances.maybe(),
                 animates.maybe(),
                 items.maybe(),
+                stairs.maybe(),
+                locks.maybe(),
             )
                 .join()
             {
@@ -49,6 +86,12 @@ impl<'a> System<'a> for SendWorldToNewPlayers {
                 if let Some(hitbox) = hitbox {
                     cm.insert_comp(*addr, ent, hitbox.clone());
                 }
+                if let Some(elevation) = elevation {
+                    cm.insert_comp(*addr, ent, elevation.clone());
+                }
+                if let Some(orientation) = orientation {
+                    cm.insert_comp(*addr, ent, orientation.clone());
+                }
                 if let Some(appearance) = appearance {
                     cm.insert_comp(*addr, ent, appearance.clone());
                 }
@@ -58,6 +101,12 @@ impl<'a> System<'a> for SendWorldToNewPlayers {
                 if let Some(animate) = animate {
                     cm.insert_comp(*addr, ent, animate.clone());
                 }
+                if let Some(stairs) = stairs {
+                    cm.insert_comp(*addr, ent, stairs.clone());
+                }
+                if let Some(lock) = lock {
+                    cm.insert_comp(*addr, ent, lock.clone());
+                }
             }
         }
     }
@@ -69,51 +118,257 @@ impl<'a> System<'a> for SpawnNewPlayers {
         Entities<'a>,
         Read<'a, ConnectionManager>,
         Read<'a, comn::art::AppearanceRecord>,
+        ReadExpect<'a, Prefabs>,
+        Read<'a, CharacterClasses>,
         Read<'a, LazyUpdate>,
+        Write<'a, LevelEvents>,
+        Write<'a, Moderation>,
         WriteStorage<'a, comn::net::SpawnPlayer>,
         ReadStorage<'a, Client>,
+        ReadStorage<'a, ChosenClass>,
+        ReadStorage<'a, comn::item::Inventory>,
+        ReadStorage<'a, comn::combat::Health>,
+        ReadStorage<'a, Pos>,
+        ReadStorage<'a, comn::Hitbox>,
+        ReadStorage<'a, MapOrigin>,
     );
 
     fn run(
         &mut self,
-        (ents, cm, appear_record, lu, mut players_to_spawn, clients): Self::SystemData,
+        (
+            ents,
+            cm,
+            appear_record,
+            prefabs,
+            classes,
+            lu,
+            mut events,
+            mut moderation,
+            mut players_to_spawn,
+            clients,
+            chosen_classes,
+            inventories,
+            healths,
+            poses,
+            hitboxes,
+            origins,
+        ): Self::SystemData,
     ) {
-        use crate::combat;
-        use comn::{art, combat::Health, controls, item, Hitbox};
-        for (_, ent, Client(new_player_addr)) in (players_to_spawn.drain(), &*ents, &clients).join()
+        use comn::{art, item};
+
+        // where the players could go, and what could be in their way.
+        let mut spawn_points = Vec::new();
+        for (ent, Pos { iso }, origin) in (&*ents, &poses, &origins).join() {
+            if origin.classes.iter().any(|c| c == spawn::SPAWN_CLASS) {
+                spawn_points.push((ent, iso.translation.vector));
+            }
+        }
+        let mut obstacles = Vec::new();
+        for (ent, Pos { iso }, hitbox) in (&*ents, &poses, &hitboxes).join() {
+            if !spawn_points.iter().any(|&(spawn, _)| spawn == ent) {
+                obstacles.push((*iso, hitbox.clone()));
+            }
+        }
+        let mut teammates: Vec<Vec2> = (&poses, &clients)
+            .join()
+            .map(|(Pos { iso }, _)| iso.translation.vector)
+            .collect();
+        let spawn_points: Vec<Vec2> = spawn_points.into_iter().map(|(_, p)| p).collect();
+
+        for (comn::net::SpawnPlayer { class }, ent, Client(new_player_addr)) in
+            (players_to_spawn.drain(), &*ents, &clients).join()
         {
+            // LoggingIn is gone as soon as they've been sent the world, well before they've
+            // picked a class, so it's having a body already that gives them away.
+            if poses.contains(ent) {
+                moderation.report(*new_player_addr, Violation::SpawnedAgain);
+                continue;
+            }
             trace!("spawning new player!");
-            // these are the components the entity will have.
-            let appearance = appear_record.try_appearance_of("Player").unwrap();
-            let iso = Pos::vec(Vec2::new(1.0, 1.0));
-            let speed = controls::Speed { speed: 0.115 };
-            //let animate = Animate::new();
-            let hitbox = Hitbox::vec(Vec2::new(0.5, 0.25));
+            // players who've come down the stairs stay the class they were,
+            // whichever one their client asks for.
+            let id = match chosen_classes.get(ent) {
+                Some(ChosenClass(chosen)) => chosen.clone(),
+                None => class,
+            };
+            let class = match classes.pick(&id) {
+                Ok(class) => class,
+                Err(e) => {
+                    warn!(
+                        "{} picked a class that can't be played: {}",
+                        new_player_addr, e
+                    );
+                    cm.send(*new_player_addr, NetMessage::Notice(e));
+                    continue;
+                }
+            };
+            // these are the components the entity will have,
+            // besides the ones that make it a player rather than something that looks like one.
+            let mut player = match prefabs.player(&appear_record) {
+                Ok(player) => player,
+                Err(e) => {
+                    error!("couldn't spawn a new player: {}", e);
+                    continue;
+                }
+            };
+            if let Some(class) = class {
+                class.apply(&mut player);
+            }
+            // players who've come down the stairs keep the Health and Inventory they had.
+            let arrived = healths.contains(ent);
+            if arrived {
+                player.health = None;
+            }
+            let at = spawn::pick(
+                &spawn_points,
+                player.hitbox.as_ref(),
+                &obstacles,
+                &teammates,
+            );
+            // so that anyone else spawned this tick doesn't land on top of them.
+            if let Some(hitbox) = &player.hitbox {
+                obstacles.push((Iso2::new(at, 0.0), hitbox.clone()));
+            }
+            teammates.push(at);
+            let iso = Pos::vec(at);
+            let net_comps = player.net_components();
+            let anim = art::PlayerAnimationController {
+                attack: class.map(|c| c.animation).unwrap_or_default(),
+            };
 
             // give them player components
             lu.insert(ent, iso.clone());
-            lu.insert(ent, speed.clone());
-            lu.insert(ent, appearance.clone());
-            //lu.insert(ent, animate.clone());
-            lu.insert(ent, hitbox.clone());
-            lu.insert(ent, art::PlayerAnimationController);
-            lu.insert(ent, combat::Alignment::Players);
-            lu.insert(ent, Health::full(5));
-            lu.insert(ent, item::Inventory::character());
+            player.insert(ent, &lu);
+            lu.insert(ent, anim.clone());
+            lu.insert(ent, ChosenClass(id));
+            let inventory = match inventories.get(ent) {
+                Some(inventory) => inventory.clone(),
+                None => {
+                    // newcomers start out with whatever their class carries.
+                    let (mut inventory, starting_items) = match class {
+                        Some(class) => match class.items(&prefabs, &appear_record) {
+                            Ok(items) => (class.inventory(), items),
+                            Err(e) => {
+                                error!("couldn't give a new player their items: {}", e);
+                                (class.inventory(), vec![])
+                            }
+                        },
+                        None => (item::Inventory::character(), vec![]),
+                    };
+                    for starting_item in starting_items {
+                        let item_comp = starting_item
+                            .item
+                            .clone()
+                            .expect("class items are checked for an Item");
+                        let item_ent = ents.create();
+                        if let Err(e) = inventory.insert(item_ent.id(), &item_comp) {
+                            error!("couldn't fit a new player's starting item: {:?}", e);
+                            ents.delete(item_ent)
+                                .expect("couldn't delete item that didn't fit");
+                            continue;
+                        }
+
+                        // everyone needs to know what the item looks like.
+                        let item_comps = starting_item.net_components();
+                        for Client(addr) in (&clients).join() {
+                            cm.new_ent(*addr, item_ent);
+                            for comp in item_comps.iter() {
+                                cm.insert_comp(*addr, item_ent, comp.clone());
+                            }
+                        }
+                        starting_item.insert(item_ent, &lu);
+                    }
+                    lu.insert(ent, inventory.clone());
+                    inventory
+                }
+            };
 
             // tell everyone 'bout the new kid on the block
             for Client(addr) in (&clients).join() {
                 cm.new_ent(*addr, ent);
                 cm.insert_comp(*addr, ent, iso.clone());
-                cm.insert_comp(*addr, ent, speed.clone());
-                cm.insert_comp(*addr, ent, appearance.clone());
-                //cm.insert_comp(*addr, ent, animate.clone());
-                cm.insert_comp(*addr, ent, hitbox.clone());
-                cm.insert_comp(*addr, ent, art::PlayerAnimationController);
+                for comp in net_comps.iter() {
+                    cm.insert_comp(*addr, ent, comp.clone());
+                }
+                cm.insert_comp(*addr, ent, anim.clone());
                 if addr == new_player_addr {
                     debug!("so we did tell them about themself");
                 }
             }
+            cm.insert_comp(*new_player_addr, ent, inventory);
+
+            events.push(LevelEvent::PlayerJoin {
+                player: ent.id(),
+                info: ClientInfo {
+                    address: new_player_addr.to_string(),
+                    arrived,
+                },
+            });
+        }
+    }
+}
+
+/// Tells every client that's already in the game about an entity that was
+/// created between dispatches, i.e. by the admin console.
+///
+/// Note that any components inserted through LazyUpdate won't be visible
+/// here until the World has been maintained.
+pub fn announce(world: &World, ent: Entity) {
+    let cm = world.read_resource::<ConnectionManager>();
+    let clients = world.read_storage::<Client>();
+    let logging_ins = world.read_storage::<LoggingIn>();
+
+    for (Client(addr), _) in (&clients, !&logging_ins).join() {
+        cm.new_ent(*addr, ent);
+    }
+
+    send_components(world, ent);
+}
+
+/// Sends every client that's already in the game the components on this entity
+/// that they care about, i.e. after they've been changed between dispatches.
+pub fn send_components(world: &World, ent: Entity) {
+    let cm = world.read_resource::<ConnectionManager>();
+    let clients = world.read_storage::<Client>();
+    let logging_ins = world.read_storage::<LoggingIn>();
+
+    let isos = world.read_storage::<Pos>();
+    let hitboxes = world.read_storage::<comn::Hitbox>();
+    let elevations = world.read_storage::<comn::Elevation>();
+    let orientations = world.read_storage::<comn::Orientation>();
+    let appearances = world.read_storage::<comn::art::Appearance>();
+    let animates = world.read_storage::<comn::art::Animate>();
+    let items = world.read_storage::<Item>();
+    let stairs = world.read_storage::<comn::Stairs>();
+    let locks = world.read_storage::<comn::Lock>();
+
+    for (Client(addr), _) in (&clients, !&logging_ins).join() {
+        if let Some(iso) = isos.get(ent) {
+            cm.insert_comp(*addr, ent, iso.clone());
+        }
+        if let Some(hitbox) = hitboxes.get(ent) {
+            cm.insert_comp(*addr, ent, hitbox.clone());
+        }
+        if let Some(elevation) = elevations.get(ent) {
+            cm.insert_comp(*addr, ent, elevation.clone());
+        }
+        if let Some(orientation) = orientations.get(ent) {
+            cm.insert_comp(*addr, ent, orientation.clone());
+        }
+        if let Some(appearance) = appearances.get(ent) {
+            cm.insert_comp(*addr, ent, appearance.clone());
+        }
+        if let Some(item) = items.get(ent) {
+            cm.insert_comp(*addr, ent, item.clone());
+        }
+        if let Some(animate) = animates.get(ent) {
+            cm.insert_comp(*addr, ent, animate.clone());
+        }
+        if let Some(stairs) = stairs.get(ent) {
+            cm.insert_comp(*addr, ent, stairs.clone());
+        }
+        if let Some(lock) = locks.get(ent) {
+            cm.insert_comp(*addr, ent, lock.clone());
         }
     }
 }
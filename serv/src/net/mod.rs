@@ -2,13 +2,16 @@ mod connection_manager;
 mod login;
 mod packets;
 mod phys;
+mod queue;
 
 pub use connection_manager::ConnectionManager;
+pub use queue::{JoinLine, JoinQueue};
 
 // main.rs needs to put these Systems in the graph
 pub use login::SendWorldToNewPlayers;
 pub use login::SpawnNewPlayers;
-pub use packets::HandleClientPackets;
+pub use login::{announce, send_components};
+pub use packets::{welcome, HandleClientPackets};
 pub use phys::SendNewPositions;
 
 // next we define a few components we'll need to do networking.
@@ -19,6 +22,18 @@ use specs_derive::Component;
 #[storage(NullStorage)]
 pub struct LoggingIn;
 
+#[derive(Component, Clone, Debug, Default)]
+#[storage(NullStorage)]
+/// Clients with this component are waiting in the JoinQueue,
+/// and are watching the game until it's their turn to play.
+pub struct Spectator;
+
+#[derive(Component, Clone, Debug)]
+/// The character class a Spectator picked, so that they can be spawned as it
+/// once it's their turn, or the one a player spawned as, so that they stay it
+/// when they take the stairs.
+pub struct ChosenClass(pub String);
+
 #[derive(Component, Clone, Debug)]
 #[storage(DenseVecStorage)]
 pub struct Client(pub std::net::SocketAddr);
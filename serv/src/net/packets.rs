@@ -1,29 +1,86 @@
-use super::prelude::*;
-use comn::{specs::prelude::*, NetMessage};
+use super::{prelude::*, ChosenClass, JoinLine, Spectator};
+use crate::config::{CharacterClasses, LevelSeed};
+use comn::art::AppearanceRecord;
+use comn::{
+    specs::{prelude::*, world::EntitiesRes},
+    Dead, NetComponent, NetMessage, Pos,
+};
 use log::*;
+use std::net::SocketAddr;
+
+/// Gives a newly connected client an entity,
+/// and gets them ready to be sent the world.
+/// Players who've just come down the stairs are welcomed to the new floor the same way.
+#[allow(clippy::too_many_arguments)]
+pub fn welcome(
+    addr: SocketAddr,
+    cm: &mut ConnectionManager,
+    ents: &EntitiesRes,
+    appear_record: &AppearanceRecord,
+    seed: &LevelSeed,
+    classes: &CharacterClasses,
+    clients: &mut WriteStorage<Client>,
+    logging_ins: &mut WriteStorage<LoggingIn>,
+) -> Entity {
+    let ent = ents.create();
+    info!("New Player joined, assigned entity {}", ent.id());
+
+    // first send some vital information to that new player
+    cm.send(
+        addr,
+        NetMessage::Establishment {
+            local_player: ent.id(),
+            appearance_record: appear_record.clone(),
+            level_seed: seed.0,
+            classes: classes.choices(),
+        },
+    );
+
+    clients.insert(ent, Client(addr.clone())).unwrap();
+    logging_ins.insert(ent, LoggingIn).unwrap();
+    cm.addr_to_ent.insert(addr, ent.id());
+
+    ent
+}
 
 pub struct HandleClientPackets;
 impl<'a> System<'a> for HandleClientPackets {
     type SystemData = (
         Write<'a, ConnectionManager>,
+        Read<'a, JoinLine>,
         Entities<'a>,
         Read<'a, LazyUpdate>,
-        Read<'a, comn::art::AppearanceRecord>,
+        Read<'a, AppearanceRecord>,
+        ReadExpect<'a, LevelSeed>,
+        Read<'a, CharacterClasses>,
         WriteStorage<'a, Client>,
         WriteStorage<'a, LoggingIn>,
+        WriteStorage<'a, Spectator>,
+        WriteStorage<'a, ChosenClass>,
+        ReadStorage<'a, Pos>,
     );
 
     fn run(
         &mut self,
         (
             mut cm,
+            line,
             ents,
             lu,
             appear_record,
+            seed,
+            classes,
             mut clients,
             mut logging_ins, /*, mut register_players*/
+            mut spectators,
+            mut chosen_classes,
+            poses,
         ): Self::SystemData,
     ) {
+        // whether or not the people waiting in line need to be told where they are now.
+        let mut line_moved = false;
+        let mut queue = line.queue.lock().expect("join queue poisoned");
+
         while let Ok((addr, net_msg)) = cm.from_clients.try_recv() {
             match net_msg {
                 // The internal networking system sends this over the channel
@@ -31,34 +88,103 @@ impl<'a> System<'a> for HandleClientPackets {
                 NetMessage::NewEnt(_) => {
                     // if we've already registered their address... they're already connected.
                     // ignore it.
-                    if cm.addr_to_ent.get(&addr).is_none() {
-                        // otherwise, welcome!
-                        let ent = ents.create();
-                        info!("New Player joined, assigned entity {}", ent.id());
+                    if cm.addr_to_ent.get(&addr).is_some() || queue.contains(&addr) {
+                        continue;
+                    }
 
-                        // first send some vital information to that new player
-                        cm.send(
+                    if queue.has_room() {
+                        // otherwise, welcome!
+                        queue.players += 1;
+                        welcome(
                             addr,
-                            NetMessage::Establishment {
-                                local_player: ent.id(),
-                                appearance_record: (*appear_record).clone(),
-                            },
+                            &mut cm,
+                            &ents,
+                            &appear_record,
+                            &seed,
+                            &classes,
+                            &mut clients,
+                            &mut logging_ins,
                         );
+                    } else {
+                        // otherwise, wait your turn.
+                        let position = queue.push(addr, &line.instance);
+                        info!("Server full, {} is #{} in line", addr, position);
+                        cm.send(addr, NetMessage::Queued(position));
+
+                        if queue.allow_spectators {
+                            let ent = welcome(
+                                addr,
+                                &mut cm,
+                                &ents,
+                                &appear_record,
+                                &seed,
+                                &classes,
+                                &mut clients,
+                                &mut logging_ins,
+                            );
+                            spectators.insert(ent, Spectator).unwrap();
+                        }
+                    }
+                }
+
+                // The internal networking system sends this over the channel
+                // when a client logs off or is kicked.
+                NetMessage::InsertComp(_, NetComponent::Dead(_)) => {
+                    // whether they were playing or waiting, they aren't anymore.
+                    if queue.contains(&addr) {
+                        line_moved = true;
+                    }
+                    queue.leave(&addr);
+
+                    if let Some(id) = cm.addr_to_ent.remove(&addr) {
+                        info!("Player with entity {} left", id);
+                        let ent = ents.entity(id);
 
-                        clients.insert(ent, Client(addr.clone())).unwrap();
-                        logging_ins.insert(ent, LoggingIn).unwrap();
-                        cm.addr_to_ent.insert(addr, ent.id());
+                        // if they had a body, everyone else needs to stop rendering it.
+                        if poses.get(ent).is_some() {
+                            for Client(other) in (&clients).join() {
+                                if *other != addr {
+                                    cm.insert_comp(*other, ent, Dead);
+                                }
+                            }
+                        }
+                        // they may have only left for another instance,
+                        // in which case nothing else from this one should reach them.
+                        clients.remove(ent);
+
+                        lu.insert(ent, Dead);
                     }
                 }
 
                 // We need to devise some way to prevent the client
                 // from inserting certain components onto themselves.
                 NetMessage::InsertComp(_, comp) => {
-                    let id = cm.addr_to_ent[&addr];
+                    let id = match cm.addr_to_ent.get(&addr) {
+                        Some(&id) => id,
+                        None => {
+                            warn!(
+                                "Client {:?} isn't logged in, ignoring their component",
+                                addr
+                            );
+                            continue;
+                        }
+                    };
                     trace!("inserting component to Client {}", id);
                     let ent = ents.entity(id);
+                    let spectator_spawning = match &comp {
+                        NetComponent::SpawnPlayer(spawn) if spectators.contains(ent) => {
+                            Some(spawn.class.clone())
+                        }
+                        _ => None,
+                    };
                     if !ents.is_alive(ent) {
                         panic!("Cannot insert for disconnected client!?");
+                    } else if let Some(class) = spectator_spawning {
+                        // they'll be spawned as the class they picked when it's their turn.
+                        trace!("spectator {} tried to spawn", id);
+                        chosen_classes
+                            .insert(ent, ChosenClass(class))
+                            .expect("couldn't remember a spectator's class");
                     } else {
                         comp.insert(ent, &lu);
                     }
@@ -67,6 +193,57 @@ impl<'a> System<'a> for HandleClientPackets {
                 NetMessage::Establishment { .. } => {
                     error!("Client {:?} sent establishment packet!", addr)
                 }
+
+                NetMessage::Lobby(_) => error!(
+                    "Client {:?}'s lobby command should've been carried out by Instances",
+                    addr
+                ),
+
+                NetMessage::Notice(_)
+                | NetMessage::Kicked(_)
+                | NetMessage::Queued(_)
+                | NetMessage::RemoveComp(..) => {
+                    error!(
+                        "Client {:?} sent a message only the server should send!",
+                        addr
+                    )
+                }
+            }
+        }
+
+        // let in as many people as there's room for.
+        while let Some(addr) = queue.admit(&line.instance) {
+            line_moved = true;
+            info!("{} made it to the front of the line", addr);
+
+            let spectating = cm.addr_to_ent.get(&addr).map(|&id| ents.entity(id));
+            match spectating {
+                // they've been spectating, so they already have the world.
+                // if they haven't picked a class yet, they'll spawn once they do.
+                Some(ent) => {
+                    spectators.remove(ent);
+                    if let Some(ChosenClass(class)) = chosen_classes.remove(ent) {
+                        lu.insert(ent, comn::net::SpawnPlayer { class });
+                    }
+                }
+                None => {
+                    welcome(
+                        addr,
+                        &mut cm,
+                        &ents,
+                        &appear_record,
+                        &seed,
+                        &classes,
+                        &mut clients,
+                        &mut logging_ins,
+                    );
+                }
+            }
+        }
+
+        if line_moved {
+            for (position, addr) in queue.positions() {
+                cm.send(*addr, NetMessage::Queued(position));
             }
         }
     }
@@ -0,0 +1,135 @@
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+/// Keeps track of how many players are in the game,
+/// and who's waiting for a spot to open up.
+///
+/// Clients who are waiting may be spectating, in which case they have an entity
+/// (with the Spectator component) and are being sent the world, but they can't
+/// spawn until they're let in.
+///
+/// One JoinQueue is shared by every instance, so that `max_players` counts everyone
+/// on the server; each client waits to be let into a particular instance.
+#[derive(Default)]
+pub struct JoinQueue {
+    /// The most players that can be in the game at once, if there's a limit.
+    pub max_players: Option<usize>,
+    /// Whether clients who are waiting get to watch the game in the meantime.
+    pub allow_spectators: bool,
+    /// How many clients are in the game (and not waiting) right now.
+    pub players: usize,
+    /// Who's waiting, and the name of the instance they're waiting to get into.
+    waiting: VecDeque<(SocketAddr, String)>,
+}
+impl JoinQueue {
+    pub fn new(max_players: Option<usize>, allow_spectators: bool) -> Self {
+        Self {
+            max_players,
+            allow_spectators,
+            ..Default::default()
+        }
+    }
+
+    #[inline]
+    /// Whether or not another player could join the game right now.
+    pub fn has_room(&self) -> bool {
+        self.max_players
+            .map(|max| self.players < max)
+            .unwrap_or(true)
+    }
+
+    #[inline]
+    pub fn contains(&self, addr: &SocketAddr) -> bool {
+        self.waiting.iter().any(|(a, _)| a == addr)
+    }
+
+    /// Puts this client at the back of the line for this instance,
+    /// returning their position in it. The first position in line is 1.
+    pub fn push(&mut self, addr: SocketAddr, instance: &str) -> usize {
+        self.waiting.push_back((addr, instance.to_string()));
+        self.waiting.len()
+    }
+
+    /// Takes the client at the front of the line out of it, if there's room for them
+    /// and they're waiting to get into this instance.
+    /// Otherwise, they're let in once their own instance calls this.
+    pub fn admit(&mut self, instance: &str) -> Option<SocketAddr> {
+        let front = self.waiting.front().map(|(_, i)| i.as_str());
+        if self.has_room() && front == Some(instance) {
+            let (addr, _) = self.waiting.pop_front()?;
+            self.players += 1;
+            Some(addr)
+        } else {
+            None
+        }
+    }
+
+    /// Call when a client leaves the server, whether they were waiting or playing.
+    pub fn leave(&mut self, addr: &SocketAddr) {
+        match self.waiting.iter().position(|(a, _)| a == addr) {
+            Some(index) => {
+                self.waiting.remove(index);
+            }
+            None => self.players = self.players.saturating_sub(1),
+        }
+    }
+
+    /// Everyone who's waiting, along with their position in line.
+    pub fn positions(&self) -> impl Iterator<Item = (usize, &SocketAddr)> {
+        self.waiting
+            .iter()
+            .enumerate()
+            .map(|(i, (addr, _))| (i + 1, addr))
+    }
+}
+
+/// The JoinQueue an instance's entrance lets players in from,
+/// along with the name of that instance.
+#[derive(Clone, Default)]
+pub struct JoinLine {
+    pub queue: Arc<Mutex<JoinQueue>>,
+    pub instance: String,
+}
+impl JoinLine {
+    pub fn new(queue: JoinQueue, instance: &str) -> Self {
+        Self {
+            queue: Arc::new(Mutex::new(queue)),
+            instance: instance.to_string(),
+        }
+    }
+
+    /// The same JoinQueue, for another instance's entrance.
+    pub fn join(&self, instance: &str) -> Self {
+        Self {
+            queue: self.queue.clone(),
+            instance: instance.to_string(),
+        }
+    }
+}
+
+#[test]
+fn test_join_queue() {
+    let addr = |port| SocketAddr::from(([127, 0, 0, 1], port));
+    let mut queue = JoinQueue::new(Some(1), false);
+
+    assert!(queue.has_room());
+    queue.players += 1;
+    assert!(!queue.has_room());
+
+    assert_eq!(queue.push(addr(1), "main"), 1);
+    assert_eq!(queue.push(addr(2), "crypt-run"), 2);
+    assert_eq!(queue.admit("main"), None);
+
+    // someone waiting leaves, so the player behind them moves up.
+    queue.leave(&addr(1));
+    assert_eq!(queue.positions().next(), Some((1, &addr(2))));
+
+    // the player in the game leaves, so the first in line gets in.
+    queue.leave(&addr(3));
+    // but only by the instance they're waiting for.
+    assert_eq!(queue.admit("main"), None);
+    assert_eq!(queue.admit("crypt-run"), Some(addr(2)));
+    assert_eq!(queue.players, 1);
+    assert_eq!(queue.admit("crypt-run"), None);
+}
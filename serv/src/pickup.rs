@@ -1,3 +1,5 @@
+use crate::config::{LevelEvent, LevelEvents};
+use crate::moderation::{Moderation, Violation};
 use crate::net::prelude::*;
 use comn::{
     item::{DropRequest, Inventory, PickupRequest, MAX_INTERACTION_DISTANCE_SQUARED},
@@ -14,6 +16,8 @@ impl<'a> System<'a> for ItemPickupDrop {
     type SystemData = (
         Entities<'a>,
         Read<'a, ConnectionManager>,
+        Write<'a, Moderation>,
+        Write<'a, LevelEvents>,
         WriteStorage<'a, DropRequest>,
         WriteStorage<'a, PickupRequest>,
         WriteStorage<'a, Pos>,
@@ -24,7 +28,18 @@ impl<'a> System<'a> for ItemPickupDrop {
 
     fn run(
         &mut self,
-        (ents, cm, mut drops, mut picks, mut poses, mut invs, items, clients): Self::SystemData,
+        (
+            ents,
+            cm,
+            mut moderation,
+            mut events,
+            mut drops,
+            mut picks,
+            mut poses,
+            mut invs,
+            items,
+            clients,
+        ): Self::SystemData,
     ) {
         (&*ents, &poses, drops.drain())
             .join()
@@ -39,29 +54,22 @@ impl<'a> System<'a> for ItemPickupDrop {
                 info!("re-physicalizing an item!");
 
                 // taking the item out of their inventory
-                let player_inventory = invs.get_mut(player_ent).expect(
-                    "Couldn't get inventory for player to drop item",
-                );
+                let player_inventory = invs
+                    .get_mut(player_ent)
+                    .expect("Couldn't get inventory for player to drop item");
+                let &Client(player_addr) = clients
+                    .get(player_ent)
+                    .expect("Couldn't get address for player to drop item");
                 let item_ent = ents.entity(match player_inventory.clear(&item_inventory_index) {
-                    Ok(slot) => match slot {
-                        Some(ent) => ent,
-                        None => {
-                            // POSSIBLE HACKING
-                            error!(
-                                "Player[{}] attempted to drop item at invalid index: {:?}",
-                                player_ent.id(),
-                                item_inventory_index,
-                            );
-                            return;
-                        }
-                    },
-                    Err(e) => {
-                        // POSSIBLE HACKING
-                        error!(
-                            "Error fetching item in order to drop it. Player[{}], item index {:?}: {:?}",
-                            player_ent.id(),
-                            item_inventory_index,
-                            e
+                    Ok(Some(ent)) => ent,
+                    // POSSIBLE HACKING
+                    // either the slot is empty, or there's no such slot.
+                    Ok(None) | Err(_) => {
+                        moderation.report(
+                            player_addr,
+                            Violation::InvalidDrop {
+                                index: item_inventory_index,
+                            },
                         );
                         return;
                     }
@@ -76,9 +84,6 @@ impl<'a> System<'a> for ItemPickupDrop {
                     .expect("Couldn't insert position to re-physicalize an item");
 
                 // updating the client's record of their player's inventory
-                let &Client(player_addr) = clients.get(player_ent).expect(
-                    "Couldn't get address for player to refresh their inventory after drop",
-                );
                 cm.insert_comp(player_addr, player_ent, player_inventory.clone());
             });
 
@@ -130,11 +135,18 @@ impl<'a> System<'a> for ItemPickupDrop {
                                     index,
                                 );
                                 cm.insert_comp(player_addr, player_ent, player_inventory.clone());
-                                Some(item_ent)
+                                Some((player_ent, item_ent))
                             },
                         }
                     } else {
                         // tryna hack!?
+                        moderation.report(
+                            player_addr,
+                            Violation::PickupOutOfRange {
+                                item: item_ent.id(),
+                                distance: player_to_item_distance_squared.sqrt(),
+                            },
+                        );
                         None
                     }
                 },
@@ -142,7 +154,7 @@ impl<'a> System<'a> for ItemPickupDrop {
             .collect::<Vec<_>>()
             .into_iter()
             // the items are close enough! itemize all 'em mfers!
-            .for_each(|item_ent| {
+            .for_each(|(player_ent, item_ent)| {
                 poses
                     .remove(item_ent)
                     .expect("couldn't un-positionize an item to pick it up!");
@@ -150,6 +162,11 @@ impl<'a> System<'a> for ItemPickupDrop {
                 for &Client(addr) in clients.join() {
                     cm.insert_comp(addr, item_ent, comn::item::Deposition);
                 }
+
+                events.push(LevelEvent::ItemPickup {
+                    player: player_ent.id(),
+                    item: item_ent.id(),
+                });
             });
     }
 }
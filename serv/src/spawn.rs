@@ -0,0 +1,95 @@
+//! Where players are put when they spawn.
+//!
+//! Entities made from map entries with the `spawn` class are spawn points.
+//! A player is put on one where they won't overlap anything with a Hitbox,
+//! preferring whichever is closest to the other players, so that a party that's been
+//! split up by the stairs or by dying comes back together. If every spawn point is taken,
+//! the one closest to the other players is used anyway, and if the level has none at all,
+//! players spawn at (1, 1).
+//!
+//! A level's script can decide a player's components itself, including where they are,
+//! with its `spawn_player(player, client_info)` function. It's called once a player has been
+//! spawned from the `player` prefab, and the components it returns replace the prefab's.
+//! `client_info` has the player's `address`, and whether they `arrived` from another level.
+use comn::{collide, prelude::*, Hitbox};
+
+/// The class of the map entries players spawn on.
+pub const SPAWN_CLASS: &str = "spawn";
+
+/// Where players spawn in levels without any spawn points.
+pub fn fallback() -> Vec2 {
+    Vec2::new(1.0, 1.0)
+}
+
+/// Whether a player with that Hitbox at `at` would overlap the obstacle.
+fn overlaps(at: Vec2, hitbox: Option<&Hitbox>, (o_iso, o_hb): &(Iso2, Hitbox)) -> bool {
+    use collide::query::{self, PointQuery, Proximity};
+
+    let o_hb_iso = o_hb.iso(o_iso);
+    let iso = Iso2::new(at, 0.0);
+    match hitbox {
+        Some(hb) => {
+            let proximity =
+                query::proximity(&hb.iso(&iso), &hb.cuboid, &o_hb_iso, &o_hb.cuboid, 0.0);
+            proximity == Proximity::Intersecting
+        }
+        None => o_hb.cuboid.contains_point(&o_hb_iso, &na::Point2::from(at)),
+    }
+}
+
+/// Which of the spawn `points` a player with that Hitbox should be put on,
+/// given where everything solid is and where the rest of the players are.
+pub fn pick(
+    points: &[Vec2],
+    hitbox: Option<&Hitbox>,
+    obstacles: &[(Iso2, Hitbox)],
+    teammates: &[Vec2],
+) -> Vec2 {
+    // how far the closest teammate is; the same for every point if there's nobody else.
+    let distance = |p: &&Vec2| {
+        teammates
+            .iter()
+            .map(|t| (*t - **p).norm())
+            .fold(std::f32::INFINITY, f32::min)
+    };
+    let closest = |a: &&Vec2, b: &&Vec2| {
+        distance(a)
+            .partial_cmp(&distance(b))
+            .unwrap_or(std::cmp::Ordering::Equal)
+    };
+
+    points
+        .iter()
+        .filter(|p| !obstacles.iter().any(|o| overlaps(**p, hitbox, o)))
+        .min_by(closest)
+        .or_else(|| points.iter().min_by(closest))
+        .cloned()
+        .unwrap_or_else(fallback)
+}
+
+#[test]
+fn test_pick_spawn_point() {
+    let hitbox = Hitbox::vec(Vec2::new(0.5, 0.5));
+    let points = [
+        Vec2::new(0.0, 0.0),
+        Vec2::new(10.0, 0.0),
+        Vec2::new(20.0, 0.0),
+    ];
+    let wall = (Iso2::new(Vec2::new(10.0, 0.5), 0.0), hitbox.clone());
+
+    // with nobody else around, the first one is as good as any.
+    assert_eq!(pick(&points, Some(&hitbox), &[], &[]), points[0]);
+    // the one closest to the rest of the party...
+    let party = [Vec2::new(12.0, 0.0)];
+    assert_eq!(pick(&points, Some(&hitbox), &[], &party), points[1]);
+    // unless there's something in the way.
+    assert_eq!(
+        pick(&points, Some(&hitbox), &[wall.clone()], &party),
+        points[2]
+    );
+    assert_eq!(
+        pick(&points[1..2], Some(&hitbox), &[wall], &party),
+        points[1]
+    );
+    assert_eq!(pick(&[], Some(&hitbox), &[], &party), fallback());
+}
@@ -0,0 +1,193 @@
+//! Triggers are areas that notice entities going into them, staying in them and leaving them,
+//! i.e. so that a level can close the doors once the players are all in the boss room.
+//! Unlike Hitboxes, they don't get in anyone's way.
+//!
+//! A Trigger only notices entities whose Alignment matches its own, and if it names a class,
+//! only the ones made from a map entry with that class. An entity is in a Trigger once its
+//! Hitbox overlaps the Trigger, or once its position is inside it if it has no Hitbox.
+//!
+//! Level scripts hear about it through these hooks, where `name` and `classes` come from
+//! the map entry the Trigger was made from, like in `on_entity_death`:
+//!
+//! - `on_trigger_enter(trigger, entity, name, classes)`, the tick the entity goes in.
+//! - `on_trigger_stay(trigger, entity, name, classes)`, every tick after that it's still in.
+//! - `on_trigger_exit(trigger, entity, name, classes)`, the tick it leaves.
+//!
+//! Entities that die in a Trigger, or Triggers that are despawned, don't count as leaving.
+//! Rust systems can read the same events from the `EventChannel<TriggerEvent>` resource,
+//! by registering a reader with it in their `setup`.
+//!
+//! Map entries are given a Trigger by a prefab their classes name, i.e.
+//!
+//! ```toml
+//! [prefab.boss_room]
+//! trigger = { extents = [10.0, 10.0], alignment = "Players" }
+//! ```
+use crate::combat::Alignment;
+use crate::config::{LevelEvent, LevelEvents, MapOrigin};
+use comn::{collide, prelude::*, Cuboid, Hitbox, Scriptable};
+use specs::{prelude::*, shrev::EventChannel, Component};
+use std::collections::BTreeSet;
+
+fn anyone() -> Alignment {
+    Alignment::All
+}
+
+#[derive(Clone, Debug, Component, Scriptable)]
+/// An area that notices entities going into it, centered on the entity that has it.
+pub struct Trigger {
+    /// Scripts see this as the Trigger's full width and height.
+    #[script(rename = "extents")]
+    pub cuboid: Cuboid<f32>,
+    /// Only entities with this Alignment set it off; anything aligned does if this is All.
+    #[script(default = "anyone")]
+    pub alignment: Alignment,
+    /// If this isn't empty, only entities made from a map entry with this class set it off.
+    #[script(default = "String::new")]
+    pub class: String,
+}
+
+impl Trigger {
+    /// Whether an entity at `iso`, with that Hitbox if it has one,
+    /// is in this Trigger when it's at `at`.
+    pub fn contains(&self, at: &Iso2, iso: &Iso2, hitbox: Option<&Hitbox>) -> bool {
+        use collide::query::{self, PointQuery, Proximity};
+
+        match hitbox {
+            Some(hb) => {
+                let proximity = query::proximity(at, &self.cuboid, &hb.iso(iso), &hb.cuboid, 0.0);
+                proximity == Proximity::Intersecting
+            }
+            None => self
+                .cuboid
+                .contains_point(at, &na::Point2::from(iso.translation.vector)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TriggerEventKind {
+    Enter,
+    Stay,
+    Exit,
+}
+
+#[derive(Debug, Clone, Copy)]
+/// Something an entity did in a Trigger.
+pub struct TriggerEvent {
+    pub kind: TriggerEventKind,
+    pub trigger: Entity,
+    pub ent: Entity,
+}
+
+impl TriggerEvent {
+    /// The event level scripts are handed,
+    /// given the MapOrigin of the Trigger if it was made from the map.
+    fn level_event(&self, origin: Option<MapOrigin>) -> LevelEvent {
+        let (trigger, ent) = (self.trigger.id(), self.ent.id());
+        match self.kind {
+            TriggerEventKind::Enter => LevelEvent::TriggerEnter {
+                trigger,
+                ent,
+                origin,
+            },
+            TriggerEventKind::Stay => LevelEvent::TriggerStay {
+                trigger,
+                ent,
+                origin,
+            },
+            TriggerEventKind::Exit => LevelEvent::TriggerExit {
+                trigger,
+                ent,
+                origin,
+            },
+        }
+    }
+}
+
+/// Keeps track of which entities are in which Triggers, and tells everyone when that changes.
+///
+/// This should run after everything that moves entities.
+#[derive(Default)]
+pub struct TrackTriggers {
+    /// Every Trigger and an entity in it, as of last tick.
+    inside: BTreeSet<(Entity, Entity)>,
+}
+impl<'a> System<'a> for TrackTriggers {
+    type SystemData = (
+        Entities<'a>,
+        Write<'a, EventChannel<TriggerEvent>>,
+        Write<'a, LevelEvents>,
+        ReadStorage<'a, Trigger>,
+        ReadStorage<'a, Pos>,
+        ReadStorage<'a, Hitbox>,
+        ReadStorage<'a, Alignment>,
+        ReadStorage<'a, MapOrigin>,
+    );
+
+    fn run(
+        &mut self,
+        (ents, mut channel, mut level_events, triggers, poses, hitboxes, alignments, origins): Self::SystemData,
+    ) {
+        let mut inside = BTreeSet::new();
+        for (trigger_ent, trigger, Pos { iso: at }) in (&*ents, &triggers, &poses).join() {
+            for (ent, Pos { iso }, alignment) in (&*ents, &poses, &alignments).join() {
+                let has_class = || {
+                    trigger.class.is_empty()
+                        || origins
+                            .get(ent)
+                            .map(|o| o.classes.contains(&trigger.class))
+                            .unwrap_or(false)
+                };
+                if ent != trigger_ent
+                    && *alignment == trigger.alignment
+                    && has_class()
+                    && trigger.contains(at, iso, hitboxes.get(ent))
+                {
+                    inside.insert((trigger_ent, ent));
+                }
+            }
+        }
+
+        let mut events = Vec::new();
+        for &(trigger, ent) in &inside {
+            let kind = if self.inside.contains(&(trigger, ent)) {
+                TriggerEventKind::Stay
+            } else {
+                TriggerEventKind::Enter
+            };
+            events.push(TriggerEvent { kind, trigger, ent });
+        }
+        for &(trigger, ent) in self.inside.difference(&inside) {
+            if ents.is_alive(trigger) && ents.is_alive(ent) {
+                events.push(TriggerEvent {
+                    kind: TriggerEventKind::Exit,
+                    trigger,
+                    ent,
+                });
+            }
+        }
+        self.inside = inside;
+
+        for event in events {
+            level_events.push(event.level_event(origins.get(event.trigger).cloned()));
+            channel.single_write(event);
+        }
+    }
+}
+
+#[test]
+fn test_trigger_contains() {
+    let trigger = Trigger {
+        cuboid: Cuboid::new(Vec2::new(2.0, 1.0)),
+        alignment: Alignment::Players,
+        class: String::new(),
+    };
+    let at = Iso2::translation(10.0, 0.0);
+    let hitbox = Hitbox::vec(Vec2::new(0.5, 0.5));
+
+    assert!(trigger.contains(&at, &Iso2::translation(11.5, 0.5), None));
+    assert!(!trigger.contains(&at, &Iso2::translation(12.2, 0.0), None));
+    assert!(trigger.contains(&at, &Iso2::translation(12.2, 0.0), Some(&hitbox)));
+    assert!(!trigger.contains(&at, &Iso2::translation(13.0, 0.0), Some(&hitbox)));
+}